@@ -0,0 +1,254 @@
+// ISO base media file format (ISO/IEC 14496-12) box parsing, as used by
+// CR3, HEIF/AVIF, JPEG XL and QuickTime/MP4 containers
+
+use std::io::prelude::*;
+use std::io;
+use std::io::SeekFrom;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+pub type FourCC = [u8; 4];
+
+/// location and type of a single box
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxHeader {
+    pub kind: FourCC,
+    /// extended type, for boxes of kind "uuid"
+    pub usertype: Option<[u8; 16]>,
+    /// offset of the start of the box
+    pub offset: u64,
+    /// total size of the box, including the header
+    pub size: u64,
+    pub header_len: u64,
+}
+
+impl BoxHeader {
+    /// read a box header at the current position. a size of 0 means the
+    /// box extends to `end`, which is usually the end of the file.
+    pub fn read<R: Read + Seek>(rdr: &mut R, end: u64) -> io::Result<Self> {
+        let offset = try!(rdr.stream_position());
+        let mut size = try!(rdr.read_u32::<BigEndian>()) as u64;
+        let mut kind = [0u8; 4];
+        try!(rdr.read_exact(&mut kind));
+        let mut header_len = 8;
+
+        if size == 1 {
+            size = try!(rdr.read_u64::<BigEndian>());
+            header_len += 8;
+        } else if size == 0 {
+            size = end - offset;
+        }
+
+        let usertype = if &kind == b"uuid" {
+            let mut u = [0u8; 16];
+            try!(rdr.read_exact(&mut u));
+            header_len += 16;
+            Some(u)
+        } else {
+            None
+        };
+
+        if size < header_len || size > end.saturating_sub(offset) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid box size: {}", size)));
+        }
+
+        Ok(BoxHeader{ kind: kind, usertype: usertype, offset: offset, size: size, header_len: header_len })
+    }
+
+    /// offset of the box payload
+    pub fn data_offset(&self) -> u64 {
+        self.offset + self.header_len
+    }
+
+    pub fn data_len(&self) -> u64 {
+        self.size - self.header_len
+    }
+
+    /// offset of the first byte after the box
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+
+    /// read the entire payload of the box
+    pub fn read_data<R: Read + Seek>(&self, rdr: &mut R) -> io::Result<Vec<u8>> {
+        let mut v = vec![0u8; self.data_len() as usize];
+        try!(rdr.seek(SeekFrom::Start(self.data_offset())));
+        try!(rdr.read_exact(&mut v));
+        Ok(v)
+    }
+
+    /// the boxes contained within this one, starting `skip` bytes into the payload
+    pub fn children<R: Read + Seek>(&self, rdr: &mut R, skip: u64) -> io::Result<Vec<BoxHeader>> {
+        read_boxes(rdr, self.data_offset() + skip, self.end())
+    }
+}
+
+/// read the headers of all boxes in [start, end)
+pub fn read_boxes<R: Read + Seek>(rdr: &mut R, start: u64, end: u64) -> io::Result<Vec<BoxHeader>> {
+    let mut boxes = vec![];
+    let mut pos = start;
+    // a trailing partial header is treated as padding
+    while pos + 8 <= end {
+        try!(rdr.seek(SeekFrom::Start(pos)));
+        let b = try!(BoxHeader::read(rdr, end));
+        pos = b.end();
+        boxes.push(b);
+    }
+    Ok(boxes)
+}
+
+/// read the headers of all top level boxes in the file
+pub fn read_file<R: Read + Seek>(rdr: &mut R) -> io::Result<Vec<BoxHeader>> {
+    let end = try!(rdr.seek(SeekFrom::End(0)));
+    read_boxes(rdr, 0, end)
+}
+
+/// find the first box of the given kind
+pub fn find<'a>(boxes: &'a [BoxHeader], kind: &FourCC) -> Option<&'a BoxHeader> {
+    boxes.iter().find(|b| &b.kind == kind)
+}
+
+/// read the version and flags at the start of a full box payload
+pub fn read_full_box<R: Read>(rdr: &mut R) -> io::Result<(u8, u32)> {
+    let v = try!(rdr.read_u32::<BigEndian>());
+    Ok(((v >> 24) as u8, v & 0xFF_FFFF))
+}
+
+/// read a big endian unsigned integer of 0, 2, 4 or 8 bytes
+pub fn read_uint<R: Read>(rdr: &mut R, size: u8) -> io::Result<u64> {
+    match size {
+        0 => Ok(0),
+        2 => rdr.read_u16::<BigEndian>().map(|v| v as u64),
+        4 => rdr.read_u32::<BigEndian>().map(|v| v as u64),
+        8 => rdr.read_u64::<BigEndian>(),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid field size: {}", size))),
+    }
+}
+
+/// read a null terminated UTF-8 string. a missing terminator at the end of the data is tolerated.
+pub fn read_cstring<R: BufRead>(rdr: &mut R) -> io::Result<String> {
+    let mut v = vec![];
+    try!(rdr.read_until(0, &mut v));
+    if v.last() == Some(&0) {
+        v.pop();
+    }
+    String::from_utf8(v).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 string"))
+}
+
+/// write a big endian unsigned integer of 0, 2, 4 or 8 bytes
+pub fn write_uint<W: Write>(w: &mut W, size: u8, v: u64) -> io::Result<()> {
+    match size {
+        0 if v == 0 => Ok(()),
+        2 if v <= 0xFFFF => w.write_u16::<BigEndian>(v as u16),
+        4 if v <= 0xFFFF_FFFF => w.write_u32::<BigEndian>(v as u32),
+        8 => w.write_u64::<BigEndian>(v),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} does not fit in {} bytes", v, size))),
+    }
+}
+
+/// write a box with the given payload, using a 64-bit size if necessary
+pub fn write_box<W: Write>(w: &mut W, kind: &FourCC, payload: &[u8]) -> io::Result<()> {
+    let size = 8 + payload.len() as u64;
+    if size > 0xFFFF_FFFF {
+        try!(w.write_u32::<BigEndian>(1));
+        try!(w.write_all(kind));
+        try!(w.write_u64::<BigEndian>(size + 8));
+    } else {
+        try!(w.write_u32::<BigEndian>(size as u32));
+        try!(w.write_all(kind));
+    }
+    w.write_all(payload)
+}
+
+/// write a full box with the given version, flags and payload
+pub fn write_full_box<W: Write>(w: &mut W, kind: &FourCC, version: u8, flags: u32, payload: &[u8]) -> io::Result<()> {
+    let mut v = Vec::with_capacity(4 + payload.len());
+    try!(v.write_u32::<BigEndian>((version as u32) << 24 | (flags & 0xFF_FFFF)));
+    v.extend_from_slice(payload);
+    write_box(w, kind, &v)
+}
+
+/// read the major brand and compatible brands of an ftyp box
+pub fn read_brands<R: Read + Seek>(rdr: &mut R, ftyp: &BoxHeader) -> io::Result<Vec<FourCC>> {
+    let d = try!(ftyp.read_data(rdr));
+    if d.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated ftyp box"));
+    }
+    // major brand, 4 byte minor version, compatible brands
+    let mut brands = vec![[d[0], d[1], d[2], d[3]]];
+    for c in d[8..].chunks(4).filter(|c| c.len() == 4) {
+        brands.push([c[0], c[1], c[2], c[3]]);
+    }
+    Ok(brands)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// build a box with the given kind and payload
+    pub fn make_box(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut v = vec![];
+        let size = 8 + payload.len() as u32;
+        v.extend_from_slice(&[(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8]);
+        v.extend_from_slice(kind);
+        v.extend_from_slice(payload);
+        v
+    }
+
+    /// build a full box with the given kind, version, flags and payload
+    pub fn make_full_box(kind: &[u8], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+        let mut v = vec![version, (flags >> 16) as u8, (flags >> 8) as u8, flags as u8];
+        v.extend_from_slice(payload);
+        make_box(kind, &v)
+    }
+
+    #[test]
+    fn nested_boxes() {
+        let mut inner = make_box(b"abcd", b"12345");
+        inner.extend(make_box(b"efgh", b""));
+        let mut file = make_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        file.extend(make_box(b"meta", &inner));
+        // 64-bit size
+        file.extend_from_slice(&[0, 0, 0, 1, b'm', b'd', b'a', b't', 0, 0, 0, 0, 0, 0, 0, 18, 0xAA, 0xBB]);
+
+        let mut c = Cursor::new(file);
+        let boxes = read_file(&mut c).expect("read boxes");
+        assert_eq!(boxes.iter().map(|b| b.kind).collect::<Vec<_>>(), vec![*b"ftyp", *b"meta", *b"mdat"]);
+        assert_eq!(boxes[2].header_len, 16);
+        assert_eq!(boxes[2].read_data(&mut c).expect("mdat data"), vec![0xAA, 0xBB]);
+
+        let brands = read_brands(&mut c, &boxes[0]).expect("brands");
+        assert_eq!(brands, vec![*b"heic", *b"mif1", *b"heic"]);
+
+        let children = boxes[1].children(&mut c, 0).expect("children");
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].read_data(&mut c).expect("data"), b"12345".to_vec());
+    }
+
+    #[test]
+    fn full_box_fields() {
+        let mut c = Cursor::new(vec![1, 0, 0, 3, 0, 5, 0, 0, 0, 7, b'h', b'i', 0, b'x']);
+        assert_eq!(read_full_box(&mut c).expect("full box"), (1, 3));
+        assert_eq!(read_uint(&mut c, 2).expect("u16"), 5);
+        assert_eq!(read_uint(&mut c, 4).expect("u32"), 7);
+        assert!(read_uint(&mut c, 3).is_err());
+        assert_eq!(read_cstring(&mut c).expect("string"), "hi");
+        assert_eq!(read_cstring(&mut c).expect("unterminated string"), "x");
+
+        let mut v = vec![];
+        write_full_box(&mut v, b"iloc", 1, 3, &[0, 5]).expect("write full box");
+        assert_eq!(v, make_full_box(b"iloc", 1, 3, &[0, 5]));
+        assert!(write_uint(&mut v, 2, 0x10000).is_err());
+    }
+
+    #[test]
+    fn invalid_size() {
+        let mut c = Cursor::new(vec![0, 0, 0, 0x40, b'f', b'r', b'e', b'e', 0, 0]);
+        assert!(read_file(&mut c).is_err());
+
+        let mut c = Cursor::new(vec![0, 0, 0, 1, b'f', b'r', b'e', b'e', 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(read_file(&mut c).is_err());
+    }
+}
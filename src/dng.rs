@@ -0,0 +1,280 @@
+// https://wwwimages2.adobe.com/content/dam/acom/en/products/photoshop/pdfs/dng_spec_1.4.0.0.pdf
+//
+// DNG is a TIFF file, typically with a preview in IFD0 and the raw image
+// in a SubIFD. all image data is held and relocated by tiff::Tiff.
+
+use std::io::prelude::*;
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use exif::{Ifd, EntryData};
+use exif::tag;
+use exif::tag::dng as dng_tag;
+use exif::makernote::{Context, Decoded, Registry};
+use tiff::Tiff;
+
+/// top level data structure representing a DNG file
+#[derive(Clone, Debug)]
+pub struct Dng {
+    pub tiff: Tiff,
+}
+
+/// a colour matrix, stored in row major order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub values: Vec<f64>,
+}
+
+impl Matrix {
+    /// the value at the given row and column, if in range
+    pub fn get(&self, row: usize, col: usize) -> Option<f64> {
+        if col >= self.cols {
+            return None;
+        }
+        self.values.get(row * self.cols + col).cloned()
+    }
+}
+
+/// the original MakerNote, as preserved in DNGPrivateData by Adobe software
+#[derive(Clone, Debug)]
+pub struct OriginalMakerNote {
+    pub big_endian: bool,
+    /// offset of the note within its original file, needed to interpret
+    /// notes whose internal offsets are relative to that file
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl Dng {
+    /// read a DNG file, including all image data
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let tiff = try!(Tiff::new(rdr));
+        let is_dng = tiff.exif.ifds.first().and_then(|ifd| ifd.entry(dng_tag::DNG_VERSION)).is_some();
+        if !is_dng {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing DNGVersion"));
+        }
+        Ok(Dng{ tiff: tiff })
+    }
+
+    /// write the DNG file. raw data and DNGPrivateData are written unmodified.
+    pub fn encode<W: Write + Seek>(&self, w: &mut W) -> io::Result<()> {
+        self.tiff.encode(w)
+    }
+
+    fn ifd0(&self) -> &Ifd {
+        &self.tiff.exif.ifds[0]
+    }
+
+    /// DNGVersion, e.g. [1, 4, 0, 0]
+    pub fn version(&self) -> Option<[u8; 4]> {
+        match self.ifd0().entry(dng_tag::DNG_VERSION) {
+            Some(e) => match e.data {
+                EntryData::Byte(ref v) if v.len() == 4 => Some([v[0], v[1], v[2], v[3]]),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    pub fn unique_camera_model(&self) -> Option<&str> {
+        self.ifd0().ascii(dng_tag::UNIQUE_CAMERA_MODEL)
+    }
+
+    /// the IFD holding the main raw image, NewSubFileType 0
+    pub fn raw_ifd(&self) -> Option<&Ifd> {
+        let ifd0 = self.ifd0();
+        let sub_ifds = ifd0.children.iter().filter(|c| c.id == tag::SUB_IFDS);
+        Some(ifd0).into_iter().chain(sub_ifds).find(|ifd| {
+            ifd.entry(tag::NEW_SUBFILE_TYPE).and_then(|e| e.data.uints()) == Some(vec![0])
+        })
+    }
+
+    /// ColorMatrix1 or ColorMatrix2, mapping XYZ to reference camera space
+    pub fn color_matrix(&self, n: u8) -> Option<Matrix> {
+        let t = if n == 1 { dng_tag::COLOR_MATRIX_1 } else { dng_tag::COLOR_MATRIX_2 };
+        self.matrix(t, 3, false)
+    }
+
+    /// ForwardMatrix1 or ForwardMatrix2, mapping white balanced camera colors to XYZ D50
+    pub fn forward_matrix(&self, n: u8) -> Option<Matrix> {
+        let t = if n == 1 { dng_tag::FORWARD_MATRIX_1 } else { dng_tag::FORWARD_MATRIX_2 };
+        self.matrix(t, 3, true)
+    }
+
+    /// CameraCalibration1 or CameraCalibration2, a square matrix
+    pub fn camera_calibration(&self, n: u8) -> Option<Matrix> {
+        let t = if n == 1 { dng_tag::CAMERA_CALIBRATION_1 } else { dng_tag::CAMERA_CALIBRATION_2 };
+        let m = try_matrix(self.ifd0(), t);
+        let size = (m.len() as f64).sqrt() as usize;
+        if size == 0 || size * size != m.len() {
+            return None;
+        }
+        Some(Matrix{ rows: size, cols: size, values: m })
+    }
+
+    /// CalibrationIlluminant1 or CalibrationIlluminant2, using the Exif LightSource values
+    pub fn calibration_illuminant(&self, n: u8) -> Option<u16> {
+        let t = if n == 1 { dng_tag::CALIBRATION_ILLUMINANT_1 } else { dng_tag::CALIBRATION_ILLUMINANT_2 };
+        self.ifd0().entry(t).and_then(|e| e.data.uints()).and_then(|v| v.first().map(|&x| x as u16))
+    }
+
+    /// raw contents of DNGPrivateData
+    pub fn private_data(&self) -> Option<&[u8]> {
+        match self.ifd0().entry(dng_tag::DNG_PRIVATE_DATA).map(|e| &e.data) {
+            Some(&EntryData::Byte(ref v)) | Some(&EntryData::Undef(ref v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// the original MakerNote, if preserved in DNGPrivateData
+    pub fn original_maker_note(&self) -> Option<OriginalMakerNote> {
+        // "Adobe\0", "MakN", 4 byte count, 2 byte order marker, 4 byte original offset
+        const HEADER_LEN: usize = 20;
+        let d = self.private_data()?;
+        if d.len() < HEADER_LEN || &d[..10] != b"Adobe\0MakN" {
+            return None;
+        }
+        let count = BigEndian::read_u32(&d[10..14]) as usize;
+        let big_endian = match &d[14..16] {
+            b"MM" => true,
+            b"II" => false,
+            _ => return None,
+        };
+        let end = 14 + count;
+        if count < 6 || d.len() < end {
+            return None;
+        }
+        Some(OriginalMakerNote{
+            big_endian: big_endian,
+            offset: BigEndian::read_u32(&d[16..20]) as u64,
+            data: d[HEADER_LEN..end].to_vec(),
+        })
+    }
+
+    /// decode the original MakerNote preserved in DNGPrivateData
+    pub fn maker_note(&self, registry: &Registry) -> Option<io::Result<Decoded>> {
+        let note = self.original_maker_note()?;
+        let ctx = Context{
+            make: self.tiff.exif.make(),
+            model: self.tiff.exif.model(),
+            big_endian: note.big_endian,
+            offset: Some(note.offset),
+        };
+        registry.decode_note(&ctx, &note.data)
+    }
+
+    fn matrix(&self, t: u16, width: usize, transposed: bool) -> Option<Matrix> {
+        let m = try_matrix(self.ifd0(), t);
+        let other = m.len() / width;
+        if other == 0 || other * width != m.len() {
+            return None;
+        }
+        let (rows, cols) = if transposed { (width, other) } else { (other, width) };
+        Some(Matrix{ rows: rows, cols: cols, values: m })
+    }
+}
+
+fn try_matrix(ifd: &Ifd, t: u16) -> Vec<f64> {
+    ifd.entry(t).and_then(|e| e.data.floats()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use exif::{Exif, Ifd, Entry, EntryData};
+    use exif::tag;
+    use exif::tag::dng as dng_tag;
+    use tiff::{Tiff, ImageData};
+
+    fn srational(v: &[(i32, i32)]) -> EntryData {
+        EntryData::SRational(v.iter().map(|&(n, d)| (n as i64) << 32 | (d as u32 as i64)).collect())
+    }
+
+    fn sub_ifd(subfile_type: u32) -> Ifd {
+        Ifd{ id: tag::SUB_IFDS, entries: vec![
+            Entry{ tag: tag::NEW_SUBFILE_TYPE, data: EntryData::ULong(vec![subfile_type]) },
+        ], children: vec![] }
+    }
+
+    fn sample() -> Tiff {
+        let mut private = b"Adobe\0MakN".to_vec();
+        private.extend_from_slice(&[0, 0, 0, 10, b'I', b'I', 0, 0, 0x12, 0x34, 1, 2, 3, 4]);
+
+        let ifd0 = Ifd{ id: 0, entries: vec![
+            Entry{ tag: tag::NEW_SUBFILE_TYPE, data: EntryData::ULong(vec![1]) },
+            Entry{ tag: tag::MAKE, data: EntryData::Ascii("Acme".to_string()) },
+            Entry{ tag: dng_tag::DNG_VERSION, data: EntryData::Byte(vec![1, 4, 0, 0]) },
+            Entry{ tag: dng_tag::COLOR_MATRIX_1, data: srational(&[(1, 2), (-1, 4), (0, 1), (0, 1), (1, 1), (0, 1), (0, 1), (0, 1), (3, 2)]) },
+            Entry{ tag: dng_tag::CALIBRATION_ILLUMINANT_1, data: EntryData::UShort(vec![21]) },
+            Entry{ tag: dng_tag::DNG_PRIVATE_DATA, data: EntryData::Byte(private) },
+        ], children: vec![sub_ifd(0), sub_ifd(1)] };
+
+        Tiff{
+            exif: Exif::from_ifds(vec![ifd0]),
+            data: vec![
+                ImageData{ path: vec![0], tag: tag::STRIP_OFFSETS, chunks: vec![b"preview".to_vec()] },
+                ImageData{ path: vec![0, 0], tag: tag::STRIP_OFFSETS, chunks: vec![b"raw strip 1".to_vec(), b"raw strip 2".to_vec()] },
+                ImageData{ path: vec![0, 1], tag: tag::TILE_OFFSETS, chunks: vec![b"tile".to_vec()] },
+            ],
+        }
+    }
+
+    fn roundtrip(t: &Tiff) -> Dng {
+        let mut buf = Cursor::new(vec![]);
+        t.encode(&mut buf).expect("encode dng");
+        buf.set_position(0);
+        Dng::new(&mut buf).expect("decode dng")
+    }
+
+    #[test]
+    fn sub_ifds() {
+        let dng = roundtrip(&sample());
+        let ifd0 = &dng.tiff.exif.ifds[0];
+        assert_eq!(ifd0.children.len(), 2);
+        assert!(ifd0.children.iter().all(|c| c.id == tag::SUB_IFDS));
+
+        let raw = dng.raw_ifd().expect("raw ifd");
+        assert_eq!(raw.entry(tag::NEW_SUBFILE_TYPE).and_then(|e| e.data.uints()), Some(vec![0]));
+
+        let chunks = dng.tiff.data.iter().map(|d| d.chunks.clone()).collect::<Vec<_>>();
+        assert_eq!(chunks, sample().data.iter().map(|d| d.chunks.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn typed_tags() {
+        let dng = roundtrip(&sample());
+        assert_eq!(dng.version(), Some([1, 4, 0, 0]));
+        assert_eq!(dng.calibration_illuminant(1), Some(21));
+        assert_eq!(dng.calibration_illuminant(2), None);
+
+        let m = dng.color_matrix(1).expect("color matrix");
+        assert_eq!((m.rows, m.cols), (3, 3));
+        assert_eq!(m.get(0, 1), Some(-0.25));
+        assert_eq!(m.get(2, 2), Some(1.5));
+        assert_eq!(m.get(0, 3), None);
+        assert_eq!(m.get(3, 0), None);
+
+        let note = dng.original_maker_note().expect("original maker note");
+        assert!(!note.big_endian);
+        assert_eq!(note.offset, 0x1234);
+        assert_eq!(note.data, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rewrite_preserves_private_data() {
+        let mut dng = roundtrip(&sample());
+        let private = dng.private_data().expect("private data").to_vec();
+        dng.tiff.exif.ifds[0].entries.push(Entry{
+            tag: tag::IMG_DESCRIPTION,
+            data: EntryData::Ascii("moved everything along".to_string()),
+        });
+
+        let dng2 = roundtrip(&dng.tiff);
+        assert_eq!(dng2.private_data(), Some(&private[..]));
+        assert_eq!(dng2.tiff.data[1].chunks, dng.tiff.data[1].chunks);
+    }
+}
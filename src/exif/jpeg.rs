@@ -4,42 +4,516 @@
 
 use std::io::prelude::*;
 use std::io;
-use std::io::SeekFrom;
 
-use byteorder::{ReadBytesExt, BigEndian};
+use std::cmp;
+use std::mem;
 
-/// helper to extract an exif segment from a jpeg file
-pub fn extract_exif<R: Read + Seek>(rdr: &mut R) -> io::Result<Vec<u8>> {
-    loop {
-        // find next segment marker
-        if 0xFF != try!(rdr.read_u8()) {
+use byteorder::{ReadBytesExt, WriteBytesExt, ByteOrder, BigEndian};
+
+use md5;
+use xmp::{Xmp, Property, Value, ns, DEFAULT_PADDING};
+
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+pub const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+pub const XMP_EXTENSION_HEADER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DQT: u8 = 0xDB;
+const DHT: u8 = 0xC4;
+const COM: u8 = 0xFE;
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+/// the largest payload of a segment, after its length
+const MAX_SEGMENT_DATA: usize = 65533;
+const MAX_STANDARD_XMP: usize = MAX_SEGMENT_DATA - 29;
+/// extended XMP data per segment, after the header, GUID, length and offset
+const MAX_EXTENSION_CHUNK: usize = MAX_SEGMENT_DATA - 35 - 32 - 8;
+
+/// helper to extract the exif payload of a jpeg file, after its header.
+/// returns None if the image data or end of image is reached first.
+pub fn extract_exif<R: Read>(rdr: &mut R) -> io::Result<Option<Vec<u8>>> {
+    for s in Segments::new(rdr) {
+        let (_, s) = try!(s);
+        if s.marker == APP1 && s.data.starts_with(EXIF_HEADER) {
+            return Ok(Some(s.data[EXIF_HEADER.len()..].to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// a marker segment, without its length
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub marker: u8,
+    pub data: Vec<u8>,
+}
+
+/// the kind of a marker segment
+#[derive(Clone, Debug, PartialEq)]
+pub enum Marker {
+    Soi,
+    /// APPn, with the null terminated identifier its payload starts with,
+    /// such as "Exif" or "ICC_PROFILE", or empty if there is none
+    App(u8, String),
+    Dqt,
+    /// start of frame n, e.g. 0 for baseline and 2 for progressive
+    Sof(u8),
+    Dht,
+    Sos,
+    Com,
+    Eoi,
+    Other(u8),
+}
+
+impl Segment {
+    pub fn kind(&self) -> Marker {
+        match self.marker {
+            SOI => Marker::Soi,
+            0xE0..=0xEF => {
+                let id = self.data.iter().take(80).position(|&b| b == 0)
+                    .map(|n| String::from_utf8_lossy(&self.data[..n]).into_owned());
+                Marker::App(self.marker - APP0, id.unwrap_or_default())
+            }
+            DQT => Marker::Dqt,
+            DHT => Marker::Dht,
+            // C8 is reserved and CC is arithmetic coding conditioning
+            m @ 0xC0..=0xCF if m != 0xC8 && m != 0xCC => Marker::Sof(m - 0xC0),
+            SOS => Marker::Sos,
+            COM => Marker::Com,
+            EOI => Marker::Eoi,
+            m => Marker::Other(m),
+        }
+    }
+}
+
+/// an iterator over the marker segments of a jpeg file, with the offset of
+/// each payload from where reading started. SOI, EOI and other standalone
+/// markers have an empty payload. iteration ends after SOS, leaving the
+/// reader at the start of the entropy coded data, or after EOI.
+pub struct Segments<R> {
+    rdr: R,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: Read> Segments<R> {
+    pub fn new(rdr: R) -> Self {
+        Segments{ rdr: rdr, offset: 0, done: false }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.rdr
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        self.offset += 1;
+        self.rdr.read_u8()
+    }
+
+    fn read_segment(&mut self) -> io::Result<(u64, Segment)> {
+        if self.offset == 0 {
+            if try!(self.rdr.read_u16::<BigEndian>()) != 0xFFD8 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a jpeg file"));
+            }
+            self.offset = 2;
+            return Ok((2, Segment{ marker: SOI, data: vec![] }));
+        }
+
+        if 0xFF != try!(self.read_u8()) {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "couldn't find segment marker"));
         }
+        let mut marker = try!(self.read_u8());
+        // any number of fill bytes may precede the marker
+        while marker == 0xFF {
+            marker = try!(self.read_u8());
+        }
+        // a stuffed zero byte only occurs within entropy coded data
+        if marker == 0x00 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected byte stuffing outside of scan data"));
+        }
+        if is_standalone(marker) {
+            return Ok((self.offset, Segment{ marker: marker, data: vec![] }));
+        }
+
+        let len = try!(self.rdr.read_u16::<BigEndian>());
+        if len < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid segment length"));
+        }
+        let offset = self.offset + 2;
+        let mut data = vec![0u8; len as usize - 2];
+        try!(self.rdr.read_exact(&mut data));
+        self.offset += len as u64;
+        Ok((offset, Segment{ marker: marker, data: data }))
+    }
+}
+
+impl<R: Read> Iterator for Segments<R> {
+    type Item = io::Result<(u64, Segment)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let r = self.read_segment();
+        self.done = match r {
+            Ok((_, ref s)) => s.marker == SOS || s.marker == EOI,
+            Err(_) => true,
+        };
+        Some(r)
+    }
+}
+
+/// read all marker segments of a jpeg file, up to the start of the image data
+pub fn read_segments<R: Read>(rdr: &mut R) -> io::Result<Vec<Segment>> {
+    read_header(rdr).map(|h| h.0)
+}
+
+// the segments between SOI and the segment which ended them, SOS or EOI.
+// the reader is left just after that segment.
+fn read_header<R: Read>(rdr: &mut R) -> io::Result<(Vec<Segment>, Segment)> {
+    let mut segments = vec![];
+    for s in Segments::new(rdr) {
+        let (_, s) = try!(s);
+        match s.marker {
+            SOI => {}
+            SOS | EOI => return Ok((segments, s)),
+            _ => segments.push(s),
+        }
+    }
+    unreachable!("segments end with SOS, EOI or an error")
+}
+
+/// edit the marker segments of a jpeg file, between SOI and the image data.
+/// the scan header and entropy coded data are copied verbatim on writing.
+pub struct JpegEditor<R> {
+    rdr: R,
+    segments: Vec<Segment>,
+    end: Segment,
+}
+
+impl<R: Read> JpegEditor<R> {
+    pub fn new(mut rdr: R) -> io::Result<Self> {
+        let (segments, end) = try!(read_header(&mut rdr));
+        Ok(JpegEditor{ rdr: rdr, segments: segments, end: end })
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// the index of the first segment matching `f`
+    pub fn position<F: Fn(&Segment) -> bool>(&self, f: F) -> Option<usize> {
+        self.segments.iter().position(f)
+    }
+
+    pub fn insert(&mut self, index: usize, s: Segment) {
+        self.segments.insert(index, s);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Segment {
+        self.segments.remove(index)
+    }
+
+    /// remove all segments not matching `f`
+    pub fn retain<F: FnMut(&Segment) -> bool>(&mut self, f: F) {
+        self.segments.retain(f);
+    }
+
+    /// replace a segment, returning the old one
+    pub fn replace(&mut self, index: usize, s: Segment) -> Segment {
+        mem::replace(&mut self.segments[index], s)
+    }
+
+    /// move a segment to a new index, shifting those in between
+    pub fn reorder(&mut self, from: usize, to: usize) {
+        let s = self.segments.remove(from);
+        self.segments.insert(to, s);
+    }
+
+    pub fn write<W: Write>(mut self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(&[0xFF, SOI]));
+        for s in &self.segments {
+            try!(write_segment(w, s));
+        }
+        try!(write_segment(w, &self.end));
+        try!(io::copy(&mut self.rdr, w));
+        Ok(())
+    }
+}
+
+/// copy a jpeg file, replacing its marker segments with the result of `f`.
+/// the image data is copied unmodified.
+pub fn rewrite_segments<R: Read, W: Write, F: FnOnce(&mut Vec<Segment>) -> io::Result<()>>(rdr: &mut R, w: &mut W, f: F) -> io::Result<()> {
+    let mut e = try!(JpegEditor::new(rdr));
+    try!(f(&mut e.segments));
+    e.write(w)
+}
+
+/// write a segment with its marker and length, or just the marker if standalone
+pub fn write_segment<W: Write>(w: &mut W, s: &Segment) -> io::Result<()> {
+    if is_standalone(s.marker) {
+        return w.write_all(&[0xFF, s.marker]);
+    }
+    if s.data.len() > MAX_SEGMENT_DATA {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "segment too large"));
+    }
+    try!(w.write_all(&[0xFF, s.marker]));
+    try!(w.write_u16::<BigEndian>(s.data.len() as u16 + 2));
+    w.write_all(&s.data)
+}
 
-        // is this a segment we care about?
-        let seg_id = try!(rdr.read_u8());
-        match seg_id {
-            0x0 => {} // byte stuffing, do nothing
-            0xD8 => {} // image start, do nothing
-            0xE1 => {  // APP1, may be EXIF
-                let len = (try!(rdr.read_u16::<BigEndian>()) - 2) as usize;
-
-                const HDR_SZ: usize = 6;
-                let mut hdr = vec![0u8; HDR_SZ];
-                try!(rdr.read_exact(&mut hdr));
-
-                if &hdr == &[b'E', b'x', b'i', b'f', 0x00, 0x00] {
-                    let mut segment = vec![0u8; len - HDR_SZ];
-                    try!(rdr.read_exact(&mut segment));
-                    return Ok(segment);
-                }
-                try!(rdr.seek(SeekFrom::Current((len - HDR_SZ) as i64)));
+/// read the XMP of a jpeg file, including any extended XMP
+pub fn read_xmp<R: Read>(rdr: &mut R) -> io::Result<Option<Xmp>> {
+    xmp_from_segments(&try!(read_segments(rdr)))
+}
+
+/// the XMP from a jpeg file's segments. properties from extended XMP,
+/// if present and intact, are merged into those of the standard packet,
+/// otherwise the standard packet is returned alone.
+pub fn xmp_from_segments(segments: &[Segment]) -> io::Result<Option<Xmp>> {
+    let standard = match segments.iter().find(|s| s.marker == APP1 && s.data.starts_with(XMP_HEADER)) {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let mut xmp = try!(Xmp::parse(&standard.data[XMP_HEADER.len()..]));
+    let guid = match xmp.remove(ns::XMP_NOTE, "HasExtendedXMP") {
+        Some(Property{ value: Value::Text(g), .. }) => g,
+        _ => return Ok(Some(xmp)),
+    };
+
+    // incomplete or mismatched extended XMP leaves the standard packet alone
+    let ext = reassemble_extended_xmp(segments, &guid).ok()
+        .and_then(|d| d)
+        .and_then(|d| Xmp::parse(&d).ok());
+    if let Some(ext) = ext {
+        for n in ext.namespaces {
+            if !xmp.namespaces.iter().any(|e| e.0 == n.0) {
+                xmp.namespaces.push(n);
             }
-            _ => {
-                // skip segment
-                let len = try!(rdr.read_u16::<BigEndian>()) - 2;
-                try!(rdr.seek(SeekFrom::Current(len as i64)));
+        }
+        for p in ext.properties {
+            xmp.set_property(p);
+        }
+    }
+    Ok(Some(xmp))
+}
+
+// the extended XMP with the given GUID, from chunks which may be in any order:
+// header, GUID, u32 full length, u32 offset, data
+fn reassemble_extended_xmp(segments: &[Segment], guid: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut chunks = vec![];
+    for s in segments.iter().filter(|s| s.marker == APP1 && s.data.starts_with(XMP_EXTENSION_HEADER)) {
+        let d = &s.data[XMP_EXTENSION_HEADER.len()..];
+        if d.len() < 40 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated extended XMP segment"));
+        }
+        // other GUIDs are left over from earlier edits
+        if &d[..32] == guid.as_bytes() {
+            chunks.push((BigEndian::read_u32(&d[32..]) as usize, BigEndian::read_u32(&d[36..]) as usize, &d[40..]));
+        }
+    }
+    let len = match chunks.first() {
+        Some(c) => c.0,
+        None => return Ok(None),
+    };
+    if chunks.iter().map(|c| c.2.len()).sum::<usize>() != len || chunks.iter().any(|c| c.0 != len) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete extended XMP"));
+    }
+
+    let mut data = vec![0u8; len];
+    for (_, offset, chunk) in chunks {
+        match offset.checked_add(chunk.len()) {
+            Some(end) if end <= len => data[offset..end].copy_from_slice(chunk),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "extended XMP chunk out of bounds")),
+        }
+    }
+    if md5::hex_digest(&data) != guid.to_uppercase() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "extended XMP doesn't match its GUID"));
+    }
+    Ok(Some(data))
+}
+
+/// encode XMP as APP1 segments. if the packet doesn't fit in a single segment,
+/// the largest properties are moved to extended XMP, split across further segments.
+pub fn xmp_segments(xmp: &Xmp) -> io::Result<Vec<Segment>> {
+    let mut standard = xmp.clone();
+    standard.remove(ns::XMP_NOTE, "HasExtendedXMP");
+    if try!(packet_len(&standard)) <= MAX_STANDARD_XMP {
+        return Ok(vec![try!(standard_xmp_segment(&standard))]);
+    }
+
+    let mut extended = Xmp{ namespaces: xmp.namespaces.clone(), about: xmp.about.clone(), properties: vec![] };
+    let mut sizes = vec![];
+    for p in &standard.properties {
+        let single = Xmp{ namespaces: vec![], about: String::new(), properties: vec![p.clone()] };
+        sizes.push((try!(packet_len(&single)), p.ns.clone(), p.name.clone()));
+    }
+    sizes.sort_by_key(|s| cmp::Reverse(s.0));
+
+    // the GUID is a fixed length, so a placeholder gives the final size
+    standard.set(ns::XMP_NOTE, "HasExtendedXMP", Value::Text("0".repeat(32)));
+    for (_, ns, name) in sizes {
+        if try!(packet_len(&standard)) <= MAX_STANDARD_XMP {
+            break;
+        }
+        extended.properties.extend(standard.remove(&ns, &name));
+    }
+    if try!(packet_len(&standard)) > MAX_STANDARD_XMP {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "XMP too large for a jpeg file"));
+    }
+
+    let mut ext = vec![];
+    try!(extended.encode_xmpmeta(&mut ext));
+    let guid = md5::hex_digest(&ext);
+    standard.set(ns::XMP_NOTE, "HasExtendedXMP", Value::Text(guid.clone()));
+
+    let mut segments = vec![try!(standard_xmp_segment(&standard))];
+    for (i, chunk) in ext.chunks(MAX_EXTENSION_CHUNK).enumerate() {
+        let mut d = XMP_EXTENSION_HEADER.to_vec();
+        d.extend_from_slice(guid.as_bytes());
+        try!(d.write_u32::<BigEndian>(ext.len() as u32));
+        try!(d.write_u32::<BigEndian>((i * MAX_EXTENSION_CHUNK) as u32));
+        d.extend_from_slice(chunk);
+        segments.push(Segment{ marker: APP1, data: d });
+    }
+    Ok(segments)
+}
+
+/// copy a jpeg file, replacing or removing its XMP. new XMP segments
+/// follow the JFIF and exif segments at the start of the file.
+pub fn write_xmp<R: Read, W: Write>(rdr: &mut R, w: &mut W, xmp: Option<&Xmp>) -> io::Result<()> {
+    rewrite_segments(rdr, w, |segments| {
+        segments.retain(|s| !(s.marker == APP1 && (s.data.starts_with(XMP_HEADER) || s.data.starts_with(XMP_EXTENSION_HEADER))));
+        if let Some(x) = xmp {
+            let pos = segments.iter().position(|s| !(s.marker == APP0 || (s.marker == APP1 && s.data.starts_with(EXIF_HEADER))))
+                .unwrap_or(segments.len());
+            for (i, s) in try!(xmp_segments(x)).into_iter().enumerate() {
+                segments.insert(pos + i, s);
             }
         }
+        Ok(())
+    })
+}
+
+// markers without a length or payload: TEM, RSTn, SOI and EOI
+fn is_standalone(marker: u8) -> bool {
+    matches!(marker, 0x01 | 0xD0..=0xD9)
+}
+
+fn packet_len(x: &Xmp) -> io::Result<usize> {
+    let mut buf = vec![];
+    try!(x.encode(&mut buf, 0));
+    Ok(buf.len())
+}
+
+// a standard packet, with as much of the default padding as fits
+fn standard_xmp_segment(x: &Xmp) -> io::Result<Segment> {
+    let padding = cmp::min(DEFAULT_PADDING, MAX_STANDARD_XMP - try!(packet_len(x)));
+    let mut d = XMP_HEADER.to_vec();
+    try!(x.encode(&mut d, padding));
+    Ok(Segment{ marker: APP1, data: d })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // SOI, APP0, then a scan which must be copied unmodified
+    const JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46,
+                          0xFF, 0xDA, 0x00, 0x03, 0x01, 0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD9];
+
+    #[test]
+    fn segments_and_editor() {
+        let mut f = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x0A];
+        f.extend_from_slice(b"Exif\0\0\x01\x02");
+        f.extend_from_slice(&[0xFF, 0xFF, 0xDB, 0x00, 0x03, 0x07, 0xFF, 0xC2, 0x00, 0x02, 0xFF, 0xFE, 0x00, 0x04, b'h', b'i']);
+        f.extend_from_slice(&JPEG[8..]);
+
+        let found: Vec<(u64, Marker)> = Segments::new(Cursor::new(&f)).map(|s| {
+            let (offset, s) = s.expect("segment");
+            (offset, s.kind())
+        }).collect();
+        assert_eq!(found, vec![(2, Marker::Soi), (6, Marker::App(1, "Exif".to_string())), (19, Marker::Dqt),
+                               (24, Marker::Sof(2)), (28, Marker::Com), (34, Marker::Sos)]);
+
+        let mut e = JpegEditor::new(Cursor::new(&f)).expect("editor");
+        let com = e.position(|s| s.kind() == Marker::Com).expect("comment");
+        e.reorder(com, 0);
+        e.replace(0, Segment{ marker: COM, data: b"edited".to_vec() });
+        e.insert(0, Segment{ marker: APP0, data: b"JFIF\0".to_vec() });
+        e.retain(|s| s.marker != DQT);
+        assert_eq!(e.remove(3).kind(), Marker::Sof(2));
+        let mut out = vec![];
+        e.write(&mut out).expect("write");
+
+        let segments = read_segments(&mut Cursor::new(&out)).expect("read segments");
+        assert_eq!(segments.iter().map(Segment::kind).collect::<Vec<_>>(),
+                   vec![Marker::App(0, "JFIF".to_string()), Marker::Com, Marker::App(1, "Exif".to_string())]);
+        assert_eq!(segments[1].data, b"edited");
+        assert!(out.ends_with(&JPEG[8..]));
+    }
+
+    #[test]
+    fn extract_exif_markers() {
+        // fill bytes and standalone markers before the exif segment
+        let f = [0xFF, 0xD8, 0xFF, 0xFF, 0xFF, 0xD0, 0xFF, 0x01, 0xFF, 0xFF, 0xE1, 0x00, 0x0B,
+                 b'E', b'x', b'i', b'f', 0, 0, 1, 2, 3];
+        assert_eq!(extract_exif(&mut Cursor::new(&f[..])).expect("exif"), Some(vec![1, 2, 3]));
+
+        // an exif header within the entropy coded data isn't a segment
+        let mut f = JPEG.to_vec();
+        f.splice(16..16, [0xFF, 0xE1, 0x00, 0x08, b'E', b'x', b'i', b'f', 0, 0].iter().cloned());
+        assert_eq!(extract_exif(&mut Cursor::new(&f)).expect("no exif"), None);
+        assert_eq!(extract_exif(&mut Cursor::new(&[0xFF, 0xD8, 0xFF, 0xD9][..])).expect("no exif"), None);
+
+        for bad in &[&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x01][..], &[0xFF, 0xD8, 0xFF, 0x00, 0x00, 0x08], &[0xFF, 0xD8, 0x12]] {
+            assert_eq!(extract_exif(&mut Cursor::new(bad)).map_err(|e| e.kind()), Err(io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn extended_xmp() {
+        let mut x = Xmp::default();
+        x.set(ns::DC, "format", Value::Text("image/jpeg".to_string()));
+        x.set(ns::PHOTOSHOP, "History", Value::Text("edit ".repeat(30000)));
+
+        let mut out = vec![];
+        write_xmp(&mut Cursor::new(JPEG), &mut out, Some(&x)).expect("write xmp");
+        assert!(out.ends_with(&JPEG[8..]));
+
+        let segments = read_segments(&mut Cursor::new(&out)).expect("read segments");
+        assert_eq!(segments[0].marker, APP0);
+        assert!(segments[1].data.starts_with(XMP_HEADER));
+        assert_eq!(segments.iter().filter(|s| s.data.starts_with(XMP_EXTENSION_HEADER)).count(), 3);
+
+        let y = read_xmp(&mut Cursor::new(&out)).expect("read xmp").expect("xmp present");
+        assert_eq!(y.text(ns::DC, "format"), Some("image/jpeg"));
+        assert_eq!(y.text(ns::PHOTOSHOP, "History"), x.text(ns::PHOTOSHOP, "History"));
+        assert!(y.property(ns::XMP_NOTE, "HasExtendedXMP").is_none());
+
+        // a corrupted chunk no longer matches the GUID, and a missing one
+        // leaves the extension incomplete: either way, the standard packet remains
+        let guid = segments[1..].iter().find(|s| s.data.starts_with(XMP_EXTENSION_HEADER))
+            .map(|s| String::from_utf8(s.data[XMP_EXTENSION_HEADER.len()..][..32].to_vec()).expect("guid"))
+            .expect("extension");
+        let mut bad = segments.clone();
+        let last = bad.len() - 1;
+        let end = bad[last].data.len() - 1;
+        bad[last].data[end] ^= 1;
+        let mut missing = segments.clone();
+        missing.remove(last);
+        for s in &[bad, missing] {
+            assert!(reassemble_extended_xmp(s, &guid).is_err());
+            let y = xmp_from_segments(s).expect("read xmp").expect("xmp present");
+            assert_eq!(y.text(ns::DC, "format"), Some("image/jpeg"));
+            assert!(y.property(ns::PHOTOSHOP, "History").is_none());
+        }
+
+        let mut stripped = vec![];
+        write_xmp(&mut Cursor::new(&out), &mut stripped, None).expect("remove xmp");
+        assert_eq!(&stripped[..], JPEG);
     }
 }
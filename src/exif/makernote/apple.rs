@@ -0,0 +1,240 @@
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use exif::{Ifd, Entry, EntryData};
+use exif::tag::apple as tag;
+use super::{decode_ifd, APPLE};
+
+// signature, 2 byte version, "MM" byte order marker
+const HEADER_LEN: usize = 14;
+
+/// Apple MakerNote, as written by iOS devices
+///
+/// offsets within the note are relative to the start of the note itself,
+/// and data is always big endian.
+#[derive(Clone, Debug)]
+pub struct AppleMakerNote {
+    pub version: u16,
+    pub ifd: Ifd,
+}
+
+/// capture timestamp relative to device boot, as stored in the RunTime entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunTime {
+    pub flags: u64,
+    pub value: u64,
+    pub epoch: u64,
+    pub timescale: u64,
+}
+
+impl RunTime {
+    /// value in seconds, or None if the timescale is invalid
+    pub fn seconds(&self) -> Option<f64> {
+        if self.timescale == 0 || self.flags & 1 == 0 {
+            return None;
+        }
+        Some(self.value as f64 / self.timescale as f64)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HdrImageType {
+    Hdr,
+    Original,
+    Unknown(i32),
+}
+
+impl AppleMakerNote {
+    /// true if the given MakerNote data looks like an Apple note
+    pub fn matches(data: &[u8]) -> bool {
+        data.starts_with(APPLE.signature)
+    }
+
+    /// decode an Apple MakerNote from the raw contents of the MakerNote entry
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        if !Self::matches(data) || data.len() < HEADER_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Apple MakerNote"));
+        }
+        if &data[12..14] != b"MM" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid endianness marker"));
+        }
+        let version = BigEndian::read_u16(&data[10..12]);
+
+        let ifd = try!(decode_ifd(data, 0, APPLE.ifd, true));
+        Ok(AppleMakerNote{ version: version, ifd: ifd })
+    }
+
+    /// time since boot at capture, used to order burst and Live Photo frames
+    pub fn run_time(&self) -> Option<RunTime> {
+        match self.ifd.entry(tag::RUN_TIME) {
+            Some(&Entry{ data: EntryData::Undef(ref v), .. }) => decode_run_time(v),
+            _ => None,
+        }
+    }
+
+    /// accelerometer reading at capture, in units of g (x, y, z)
+    pub fn acceleration_vector(&self) -> Option<[f64; 3]> {
+        match self.ifd.entry(tag::ACCELERATION_VECTOR) {
+            Some(&Entry{ data: EntryData::SRational(ref v), .. }) if v.len() == 3 => {
+                let mut out = [0f64; 3];
+                for (o, r) in out.iter_mut().zip(v) {
+                    let (num, den) = ((*r >> 32) as i32, *r as i32);
+                    *o = if den == 0 { 0.0 } else { num as f64 / den as f64 };
+                }
+                Some(out)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn hdr_image_type(&self) -> Option<HdrImageType> {
+        match self.ifd.entry(tag::HDR_IMAGE_TYPE) {
+            Some(&Entry{ data: EntryData::SLong(ref v), .. }) if !v.is_empty() => Some(match v[0] {
+                3 => HdrImageType::Hdr,
+                4 => HdrImageType::Original,
+                n => HdrImageType::Unknown(n),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn burst_uuid(&self) -> Option<&str> {
+        self.ifd.ascii(tag::BURST_UUID)
+    }
+
+    /// identifier shared between the still and video halves of a Live Photo
+    pub fn content_identifier(&self) -> Option<&str> {
+        self.ifd.ascii(tag::CONTENT_IDENTIFIER)
+    }
+
+    pub fn image_unique_id(&self) -> Option<&str> {
+        self.ifd.ascii(tag::IMAGE_UNIQUE_ID)
+    }
+}
+
+// RunTime is a binary plist containing a dictionary of integers, only
+// decode as much of the bplist format as required to extract those.
+// https://opensource.apple.com/source/CF/CF-1153.18/CFBinaryPList.c
+fn decode_run_time(d: &[u8]) -> Option<RunTime> {
+    const TRAILER_LEN: usize = 32;
+    if d.len() < 8 + TRAILER_LEN || &d[..8] != b"bplist00" {
+        return None;
+    }
+
+    let trailer = &d[d.len() - TRAILER_LEN..];
+    let offset_sz = trailer[6] as usize;
+    let ref_sz = trailer[7] as usize;
+    let num_objects = BigEndian::read_u64(&trailer[8..16]) as usize;
+    let top = BigEndian::read_u64(&trailer[16..24]) as usize;
+    let table = BigEndian::read_u64(&trailer[24..32]) as usize;
+
+    // the trailer is untrusted, so offsets may overflow
+    let object_offset = |idx: usize| -> Option<usize> {
+        if idx >= num_objects { return None; }
+        let start = idx.checked_mul(offset_sz)?.checked_add(table)?;
+        d.get(start..start.checked_add(offset_sz)?).map(read_be_uint).map(|v| v as usize)
+    };
+
+    let off = object_offset(top)?;
+    let marker = *d.get(off)?;
+    if marker >> 4 != 0xD {
+        return None; // top level object must be a dict
+    }
+    let count = (marker & 0xF) as usize;
+    if count == 0xF {
+        return None; // long dicts not expected here
+    }
+
+    let mut rt = RunTime{ flags: 0, value: 0, epoch: 0, timescale: 0 };
+    let refs = off + 1;
+    for i in 0..count {
+        let key_ref = d.get(refs + i * ref_sz..refs + (i + 1) * ref_sz).map(read_be_uint)?;
+        let val_ref = d.get(refs + (count + i) * ref_sz..refs + (count + i + 1) * ref_sz).map(read_be_uint)?;
+
+        let key = object_offset(key_ref as usize).and_then(|o| bplist_ascii(d, o))?;
+        let val = object_offset(val_ref as usize).and_then(|o| bplist_int(d, o))?;
+        match key {
+            b"flags" => rt.flags = val,
+            b"value" => rt.value = val,
+            b"epoch" => rt.epoch = val,
+            b"timescale" => rt.timescale = val,
+            _ => {},
+        }
+    }
+    Some(rt)
+}
+
+fn read_be_uint(b: &[u8]) -> u64 {
+    b.iter().fold(0u64, |acc, &x| acc << 8 | x as u64)
+}
+
+fn bplist_int(d: &[u8], off: usize) -> Option<u64> {
+    let marker = *d.get(off)?;
+    if marker >> 4 != 0x1 {
+        return None;
+    }
+    let n = 1usize << (marker & 0xF);
+    d.get(off + 1..off + 1 + n).map(read_be_uint)
+}
+
+fn bplist_ascii(d: &[u8], off: usize) -> Option<&[u8]> {
+    let marker = *d.get(off)?;
+    let n = (marker & 0xF) as usize;
+    if marker >> 4 != 0x5 || n == 0xF {
+        return None;
+    }
+    d.get(off + 1..off + 1 + n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use exif::{Exif, jpeg};
+
+    #[test]
+    fn decode_iphone() {
+        let mut f = File::open("src/fixtures/IMG_2222.JPG").expect("couldn't open file");
+        let segment = jpeg::extract_exif(&mut f).expect("extract exif").expect("exif present");
+        let e = Exif::new(&mut Cursor::new(segment)).expect("extract exif");
+
+        let note = AppleMakerNote::new(e.maker_note().expect("maker note")).expect("decode apple note");
+        assert_eq!(note.version, 1);
+        assert_eq!(note.ifd.entries.len(), 11);
+
+        let rt = note.run_time().expect("run time");
+        assert_eq!(rt.flags, 1);
+        assert_eq!(rt.timescale, 1000000000);
+        assert_eq!(rt.value, 0x15fe0ff2bf2f);
+
+        let av = note.acceleration_vector().expect("acceleration vector");
+        assert!((av[0] - (-98.0 / 23929.0)).abs() < 1e-9);
+
+        assert_eq!(note.content_identifier(), None);
+    }
+
+    #[test]
+    fn decode_live_photo() {
+        let f = File::open("src/fixtures/apple-live-photo.bin").expect("couldn't open file");
+        let e = Exif::new(&mut BufReader::new(f)).expect("extract exif");
+
+        let note = AppleMakerNote::new(e.maker_note().expect("maker note")).expect("decode apple note");
+        assert_eq!(note.content_identifier(), Some("7A3C2E1F-4B5D-4E6F-8A9B-0C1D2E3F4A5B"));
+        assert_eq!(note.burst_uuid(), Some("5E8B1E4D-0E6B-4C39-9A3F-2B7D8C1A6F00"));
+        assert_eq!(note.hdr_image_type(), Some(HdrImageType::Hdr));
+        assert_eq!(note.run_time(), None);
+    }
+
+    #[test]
+    fn malformed_run_time() {
+        // a trailer with an object table offset and object count near u64::MAX
+        let mut d = b"bplist00\xd0".to_vec();
+        d.extend_from_slice(&[0, 0, 0, 0, 0, 0, 8, 1]);
+        d.extend_from_slice(&[0xFF; 8]);
+        d.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        d.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0]);
+        assert_eq!(decode_run_time(&d), None);
+    }
+}
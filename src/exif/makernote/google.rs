@@ -0,0 +1,138 @@
+use std::io;
+
+use xmp::{Xmp, Property, Value, ns};
+
+const SIGNATURE: &[u8] = b"HDRP";
+
+/// Google HDR+ MakerNote, as written by Pixel and Nexus devices
+///
+/// the payload is an obfuscated, compressed protobuf and is kept opaque;
+/// decoding the HDR+ parameters it holds is not supported. motion photo
+/// details aren't stored in the MakerNote, see `motion_photo`.
+#[derive(Clone, Debug)]
+pub struct GoogleMakerNote {
+    pub version: u8,
+    pub payload: Vec<u8>,
+}
+
+impl GoogleMakerNote {
+    /// true if the given MakerNote data looks like a Google HDR+ note
+    pub fn matches(data: &[u8]) -> bool {
+        data.len() > SIGNATURE.len() && data.starts_with(SIGNATURE)
+    }
+
+    /// decode a Google MakerNote from the raw contents of the MakerNote entry
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        if !Self::matches(data) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Google HDR+ MakerNote"));
+        }
+        let version = data[SIGNATURE.len()];
+        match version {
+            2 | 3 => {},
+            v => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported HDR+ MakerNote version: {}", v))),
+        }
+        Ok(GoogleMakerNote{
+            version: version,
+            payload: data[SIGNATURE.len() + 1..].to_vec(),
+        })
+    }
+}
+
+/// a still image with a short video appended to the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct MotionPhoto {
+    pub version: Option<u32>,
+    /// time of the still image within the video in microseconds, -1 if unspecified
+    pub presentation_timestamp_us: Option<i64>,
+    /// length of the video at the end of the file, in bytes
+    pub video_length: Option<u64>,
+}
+
+/// the motion photo described by the GCamera properties of an image's XMP,
+/// either as MotionPhoto with a Container directory, or as the older MicroVideo
+pub fn motion_photo(xmp: &Xmp) -> Option<MotionPhoto> {
+    let text = |name| xmp.text(ns::GCAMERA, name).map(str::trim);
+    if text("MotionPhoto") == Some("1") {
+        return Some(MotionPhoto{
+            version: text("MotionPhotoVersion").and_then(|s| s.parse().ok()),
+            presentation_timestamp_us: text("MotionPhotoPresentationTimestampUs").and_then(|s| s.parse().ok()),
+            video_length: container_item_length(xmp, "MotionPhoto"),
+        });
+    }
+    if text("MicroVideo") == Some("1") {
+        return Some(MotionPhoto{
+            version: text("MicroVideoVersion").and_then(|s| s.parse().ok()),
+            presentation_timestamp_us: text("MicroVideoPresentationTimestampUs").and_then(|s| s.parse().ok()),
+            // MicroVideoOffset counts back from the end of the file
+            video_length: text("MicroVideoOffset").and_then(|s| s.parse().ok()),
+        });
+    }
+    None
+}
+
+// the Item:Length of the Container:Directory item with the given semantic
+fn container_item_length(xmp: &Xmp, semantic: &str) -> Option<u64> {
+    let items = match xmp.get(ns::CONTAINER, "Directory")? {
+        Value::Array(_, items) => items,
+        _ => return None,
+    };
+    for i in items {
+        let item = match field(i, ns::CONTAINER, "Item") {
+            Some(item) => item,
+            None => continue,
+        };
+        if let Some(Value::Text(s)) = field(item, ns::CONTAINER_ITEM, "Semantic").map(|p| &p.value) {
+            if s == semantic {
+                return match field(item, ns::CONTAINER_ITEM, "Length").map(|p| &p.value) {
+                    Some(Value::Text(l)) => l.trim().parse().ok(),
+                    _ => None,
+                };
+            }
+        }
+    }
+    None
+}
+
+// a field of a struct value
+fn field<'a>(p: &'a Property, ns: &str, name: &str) -> Option<&'a Property> {
+    match p.value {
+        Value::Struct(ref fields) => fields.iter().find(|f| f.ns == ns && f.name == name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_hdrp() {
+        let note = GoogleMakerNote::new(b"HDRP\x03\x01\x02\x03").expect("decode google note");
+        assert_eq!(note.version, 3);
+        assert_eq!(note.payload, vec![1, 2, 3]);
+
+        assert!(GoogleMakerNote::new(b"HDRP\x09\x01").is_err());
+        assert!(!GoogleMakerNote::matches(b"Apple iOS\0"));
+    }
+
+    #[test]
+    fn gcamera_motion_photo() {
+        let x = Xmp::parse(br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description rdf:about="" xmlns:GCamera="http://ns.google.com/photos/1.0/camera/"
+                xmlns:Container="http://ns.google.com/photos/1.0/container/"
+                xmlns:Item="http://ns.google.com/photos/1.0/container/item/"
+                GCamera:MotionPhoto="1" GCamera:MotionPhotoVersion="1" GCamera:MotionPhotoPresentationTimestampUs="1033878">
+              <Container:Directory><rdf:Seq>
+                <rdf:li rdf:parseType="Resource"><Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary" Item:Length="0"/></rdf:li>
+                <rdf:li rdf:parseType="Resource"><Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="2718513"/></rdf:li>
+              </rdf:Seq></Container:Directory>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#).expect("parse xmp");
+        assert_eq!(motion_photo(&x), Some(MotionPhoto{ version: Some(1), presentation_timestamp_us: Some(1033878), video_length: Some(2718513) }));
+
+        let mut legacy = Xmp::default();
+        legacy.set(ns::GCAMERA, "MicroVideo", Value::Text("1".to_string()));
+        legacy.set(ns::GCAMERA, "MicroVideoOffset", Value::Text("4567".to_string()));
+        assert_eq!(motion_photo(&legacy), Some(MotionPhoto{ version: None, presentation_timestamp_us: None, video_length: Some(4567) }));
+        assert_eq!(motion_photo(&Xmp::default()), None);
+    }
+}
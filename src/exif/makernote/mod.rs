@@ -0,0 +1,331 @@
+// vendor specific MakerNote decoders
+// http://www.sno.phy.queensu.ca/~phil/exiftool/TagNames/Apple.html
+// http://www.sno.phy.queensu.ca/~phil/exiftool/TagNames/Google.html
+// http://www.exiv2.org/makernote.html
+
+pub mod apple;
+pub mod google;
+
+use std::io;
+use std::io::{Cursor, SeekFrom};
+use std::io::prelude::*;
+
+use byteorder::{BigEndian, LittleEndian};
+
+use exif::{Exif, Ifd};
+
+/// information about the document a MakerNote was found in,
+/// used by decoders to identify the vendor and to interpret offsets
+#[derive(Clone, Copy, Debug)]
+pub struct Context<'a> {
+    pub make: Option<&'a str>,
+    pub model: Option<&'a str>,
+    /// byte order of the enclosing document
+    pub big_endian: bool,
+    /// offset of the note within the enclosing document, if known
+    pub offset: Option<u64>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(exif: &'a Exif) -> Self {
+        Context{
+            make: exif.make(),
+            model: exif.model(),
+            big_endian: exif.big_endian,
+            offset: exif.maker_note_offset,
+        }
+    }
+}
+
+/// a vendor specific MakerNote decoder
+pub trait MakerNote {
+    /// vendor name, used for diagnostics
+    fn name(&self) -> &str;
+
+    /// return true if this decoder understands the given note
+    fn matches(&self, ctx: &Context, data: &[u8]) -> bool;
+
+    /// decode the note into an Ifd
+    fn decode(&self, ctx: &Context, data: &[u8]) -> io::Result<Ifd>;
+}
+
+/// a MakerNote decoded by one of the decoders in a Registry
+#[derive(Clone, Debug)]
+pub struct Decoded {
+    pub vendor: String,
+    pub ifd: Ifd,
+}
+
+/// collection of MakerNote decoders, consulted in order of most recent registration.
+///
+/// Google HDR+ notes are not IFD structured, see google::GoogleMakerNote.
+pub struct Registry {
+    decoders: Vec<Box<dyn MakerNote>>,
+}
+
+impl Registry {
+    /// create a Registry with no decoders
+    pub fn empty() -> Self {
+        Registry{ decoders: vec![] }
+    }
+
+    /// add a decoder, taking precedence over those already registered
+    pub fn register(&mut self, d: Box<dyn MakerNote>) {
+        self.decoders.push(d);
+    }
+
+    /// find the decoder for the given note, if any
+    pub fn find(&self, ctx: &Context, data: &[u8]) -> Option<&dyn MakerNote> {
+        self.decoders.iter().rev()
+            .find(|d| d.matches(ctx, data))
+            .map(|d| &**d)
+    }
+
+    /// decode the MakerNote of the given Exif.
+    /// returns None if there is no note, or no decoder recognises it,
+    /// in which case the note remains available as EntryData::Undef.
+    pub fn decode(&self, exif: &Exif) -> Option<io::Result<Decoded>> {
+        let data = exif.maker_note()?;
+        self.decode_note(&Context::new(exif), data)
+    }
+
+    /// decode a MakerNote found outside of the usual Exif location
+    pub fn decode_note(&self, ctx: &Context, data: &[u8]) -> Option<io::Result<Decoded>> {
+        self.find(ctx, data).map(|d| {
+            d.decode(ctx, data).map(|ifd| Decoded{ vendor: d.name().to_string(), ifd: ifd })
+        })
+    }
+}
+
+impl Default for Registry {
+    /// create a Registry with all built in decoders
+    fn default() -> Self {
+        let mut r = Registry::empty();
+        for l in LAYOUTS {
+            r.register(Box::new(l.clone()));
+        }
+        r
+    }
+}
+
+/// how offsets within a note are interpreted
+#[derive(Clone, Copy, Debug)]
+pub enum Base {
+    /// relative to the start of the note
+    Note,
+    /// relative to an embedded TIFF header at the given position within the note
+    Header(u64),
+    /// relative to the enclosing document
+    Document,
+}
+
+/// byte order of a note
+#[derive(Clone, Copy, Debug)]
+pub enum Order {
+    /// same as the enclosing document
+    Document,
+    Big,
+    Little,
+    /// "II" or "MM" marker at the given position within the note
+    Marker(usize),
+}
+
+/// describes the layout of an IFD structured note, sufficient to decode
+/// most vendors without any additional code
+#[derive(Clone, Debug)]
+pub struct Layout {
+    pub name: &'static str,
+    /// required prefix of Exif Make, if any
+    pub make: Option<&'static str>,
+    /// required prefix of the note data
+    pub signature: &'static [u8],
+    /// position of the IFD within the note
+    pub ifd: u64,
+    pub base: Base,
+    pub order: Order,
+}
+
+impl Layout {
+    fn big_endian(&self, ctx: &Context, data: &[u8]) -> io::Result<bool> {
+        match self.order {
+            Order::Document => Ok(ctx.big_endian),
+            Order::Big => Ok(true),
+            Order::Little => Ok(false),
+            Order::Marker(p) => match data.get(p..p + 2) {
+                Some(b"MM") => Ok(true),
+                Some(b"II") => Ok(false),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid endianness marker")),
+            },
+        }
+    }
+}
+
+impl MakerNote for Layout {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn matches(&self, ctx: &Context, data: &[u8]) -> bool {
+        let make_ok = match (self.make, ctx.make) {
+            (Some(want), Some(make)) => make.starts_with(want),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        make_ok && data.starts_with(self.signature)
+    }
+
+    fn decode(&self, ctx: &Context, data: &[u8]) -> io::Result<Ifd> {
+        let origin = match self.base {
+            Base::Note => 0,
+            Base::Header(h) => -(h as i64),
+            Base::Document => match ctx.offset {
+                Some(o) => o as i64,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "MakerNote offset required but unknown")),
+            },
+        };
+        let big_endian = try!(self.big_endian(ctx, data));
+        decode_ifd(data, origin, (origin + self.ifd as i64) as u64, big_endian)
+    }
+}
+
+pub const APPLE: Layout = Layout{
+    name: "Apple", make: None, signature: b"Apple iOS\0", ifd: 14, base: Base::Note, order: Order::Big,
+};
+
+pub const CANON: Layout = Layout{
+    name: "Canon", make: Some("Canon"), signature: b"", ifd: 0, base: Base::Document, order: Order::Document,
+};
+
+pub const FUJIFILM: Layout = Layout{
+    name: "Fujifilm", make: None, signature: b"FUJIFILM", ifd: 12, base: Base::Note, order: Order::Little,
+};
+
+pub const NIKON: Layout = Layout{
+    name: "Nikon", make: None, signature: b"Nikon\0\x02", ifd: 18, base: Base::Header(10), order: Order::Marker(10),
+};
+
+pub const OLYMPUS: Layout = Layout{
+    name: "Olympus", make: None, signature: b"OLYMPUS\0", ifd: 12, base: Base::Note, order: Order::Marker(8),
+};
+
+pub const PANASONIC: Layout = Layout{
+    name: "Panasonic", make: None, signature: b"Panasonic\0\0\0", ifd: 12, base: Base::Document, order: Order::Document,
+};
+
+pub const PENTAX: Layout = Layout{
+    name: "Pentax", make: None, signature: b"AOC\0", ifd: 6, base: Base::Document, order: Order::Marker(4),
+};
+
+pub const SONY: Layout = Layout{
+    name: "Sony", make: None, signature: b"SONY DSC \0\0\0", ifd: 12, base: Base::Document, order: Order::Document,
+};
+
+const LAYOUTS: &[Layout] = &[APPLE, CANON, FUJIFILM, NIKON, OLYMPUS, PANASONIC, PENTAX, SONY];
+
+/// decode an IFD from note data using the existing Ifd parser.
+///
+/// `origin` is the position of data[0] in the coordinate system used by
+/// offsets within the note, `ifd` is the position of the IFD in that system.
+pub fn decode_ifd(data: &[u8], origin: i64, ifd: u64, big_endian: bool) -> io::Result<Ifd> {
+    let mut rdr = Rebased{ inner: Cursor::new(data), origin: origin };
+    try!(rdr.seek(SeekFrom::Start(ifd)));
+    let (ifd, _) = {
+        if big_endian { try!(Ifd::new::<_, BigEndian>(&mut rdr, 0)) }
+        else          { try!(Ifd::new::<_, LittleEndian>(&mut rdr, 0)) }
+    };
+    Ok(ifd)
+}
+
+// adapts a reader such that absolute seeks are relative to `origin`
+struct Rebased<R> {
+    inner: R,
+    origin: i64,
+}
+
+impl<R: Read> Read for Rebased<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Rebased<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => {
+                let p = n as i64 - self.origin;
+                if p < 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "offset outside of MakerNote"));
+                }
+                SeekFrom::Start(p as u64)
+            },
+            p => p,
+        };
+        let n = try!(self.inner.seek(pos));
+        Ok((n as i64 + self.origin) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use exif::{Exif, Ifd, EntryData};
+
+    #[test]
+    fn detect_sony() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+        assert_eq!(e.maker_note_offset, Some(926));
+
+        let d = Registry::default().decode(&e).expect("known vendor").expect("decode note");
+        assert_eq!(d.vendor, "Sony");
+        assert_eq!(d.ifd.entries.len(), 93);
+        assert_eq!(d.ifd.ascii(0xb020), Some("Standard"));
+    }
+
+    struct Custom;
+
+    impl MakerNote for Custom {
+        fn name(&self) -> &str { "Custom" }
+        fn matches(&self, ctx: &Context, _: &[u8]) -> bool { ctx.make == Some("SONY") }
+        fn decode(&self, _: &Context, _: &[u8]) -> io::Result<Ifd> {
+            Ok(Ifd{ id: 0, entries: vec![], children: vec![] })
+        }
+    }
+
+    #[test]
+    fn custom_decoder_precedence() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+
+        let mut r = Registry::default();
+        r.register(Box::new(Custom));
+        let d = r.decode(&e).expect("known vendor").expect("decode note");
+        assert_eq!(d.vendor, "Custom");
+
+        assert!(Registry::empty().decode(&e).is_none());
+    }
+
+    #[test]
+    fn unknown_vendor_roundtrip() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let mut e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+
+        let note = b"ACME\0\x01\x02\x03\x04\x05".to_vec();
+        for ifd in e.ifds[0].children.iter_mut() {
+            for entry in ifd.entries.iter_mut() {
+                if entry.tag == ::exif::tag::MAKER_NOTE {
+                    entry.data = EntryData::Undef(note.clone());
+                }
+            }
+        }
+        assert!(Registry::default().decode(&e).is_none());
+
+        let mut buf = Cursor::new(vec![]);
+        e.encode(&mut buf).expect("encode exif");
+        buf.set_position(0);
+        let e2 = Exif::new(&mut buf).expect("decode exif");
+        assert_eq!(e2.maker_note(), Some(&note[..]));
+    }
+}
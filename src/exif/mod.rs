@@ -1,27 +1,46 @@
 
 pub mod tag;
 pub mod jpeg;
+pub mod makernote;
+pub mod types;
+
+pub use self::types::{DateTime, GpsPosition};
 
 use std::io::prelude::*;
 use std::io;
 use std::io::SeekFrom;
 use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian, LittleEndian, ByteOrder};
 
+pub const TIFF_MAGIC: u16 = 42;
+pub const BIG_TIFF_MAGIC: u16 = 43;
+
 /// top level data structure representing an entire exif document
 #[derive(Clone, Debug)]
 pub struct Exif {
     pub ifds: Vec<Ifd>,
+    /// byte order of the source document, also used when encoding
+    pub big_endian: bool,
+    /// true for BigTIFF documents, with 64-bit offsets
+    pub big_tiff: bool,
+    /// offset of the MakerNote data within the source document, if present.
+    /// some vendors (e.g. Canon, Sony, Panasonic, Pentax) encode offsets within
+    /// the note relative to the document, see `encode`.
+    pub maker_note_offset: Option<u64>,
 }
 
 // why doesn't io::Cursor implement any of these for anything other than [u8]? :(
+// positions are relative to where the inner writer was when we started,
+// so that exif can be written after e.g. a jpeg APP1 header
 struct PosWriter<T> {
     inner: T,
     pos: u64,
+    base: u64,
 }
 
 impl<T: Write + Seek> PosWriter<T> {
-    fn new(inner: T) -> Self {
-        PosWriter{ inner: inner, pos: 0 }
+    fn new(mut inner: T) -> io::Result<Self> {
+        let base = try!(inner.stream_position());
+        Ok(PosWriter{ inner: inner, pos: 0, base: base })
     }
     fn position(&self) -> u64 { self.pos }
 }
@@ -40,15 +59,35 @@ impl<T: Write> Write for PosWriter<T> {
 
 impl<T: Seek> Seek for PosWriter<T> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => SeekFrom::Start(self.base + n),
+            p => p,
+        };
         let n = try!(self.inner.seek(pos));
-        self.pos = n;
-        Ok(n)
+        self.pos = n - self.base;
+        Ok(self.pos)
     }
 }
 
 impl Exif {
+    /// create little endian, classic TIFF Exif from the given IFDs
+    pub fn from_ifds(ifds: Vec<Ifd>) -> Self {
+        Exif{
+            ifds: ifds,
+            big_endian: false,
+            big_tiff: false,
+            maker_note_offset: None,
+        }
+    }
+
     /// extract Exif from the given reader
     pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        Self::new_with_magic(rdr, &[TIFF_MAGIC, BIG_TIFF_MAGIC])
+    }
+
+    /// extract Exif from a TIFF variant using one of the given magic numbers,
+    /// as found in e.g. ORF ("IIRO") and RW2 (0x0055) raw files
+    pub fn new_with_magic<R: Read + Seek>(rdr: &mut R, magics: &[u16]) -> io::Result<Self> {
         let mut header = vec![0; 8];
         try!(rdr.read_exact(&mut header));
 
@@ -58,41 +97,152 @@ impl Exif {
             _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid endianness marker")),
         };
 
-        let offset_to_ifd = if big_endian {
-            BigEndian::read_u32(&header[4..])
+        let magic = if big_endian {
+            BigEndian::read_u16(&header[2..])
         } else {
-            LittleEndian::read_u32(&header[4..])
-        } as usize;
+            LittleEndian::read_u16(&header[2..])
+        };
 
-        try!(rdr.seek(SeekFrom::Start(offset_to_ifd as u64)));
+        // BigTIFF: 2 byte offset size (always 8), 2 reserved bytes, 8 byte offset to IFD
+        // https://www.awaresystems.be/imaging/tiff/bigtiff.html
+        if !magics.contains(&magic) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid tiff magic: {}", magic)));
+        }
+        let big_tiff = magic == BIG_TIFF_MAGIC;
+
+        let offset_to_ifd = if big_tiff {
+            header.resize(16, 0);
+            try!(rdr.read_exact(&mut header[8..]));
+            let offset_sz = if big_endian { BigEndian::read_u16(&header[4..]) } else { LittleEndian::read_u16(&header[4..]) };
+            if offset_sz != 8 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported BigTIFF offset size: {}", offset_sz)));
+            }
+            if big_endian { BigEndian::read_u64(&header[8..]) } else { LittleEndian::read_u64(&header[8..]) }
+        } else if big_endian {
+            BigEndian::read_u32(&header[4..]) as u64
+        } else {
+            LittleEndian::read_u32(&header[4..]) as u64
+        };
+
+        try!(rdr.seek(SeekFrom::Start(offset_to_ifd)));
 
+        let mut st = DecodeState{ big_tiff: big_tiff, ..DecodeState::default() };
         let mut ifds = vec![];
         for i in 0.. {
             let (ifd, offset) = {
-                if big_endian { try!(Ifd::new::<_, BigEndian>(rdr, i)) }
-                else          { try!(Ifd::new::<_, LittleEndian>(rdr, i)) }
+                if big_endian { try!(Ifd::decode::<_, BigEndian>(rdr, i, &mut st)) }
+                else          { try!(Ifd::decode::<_, LittleEndian>(rdr, i, &mut st)) }
             };
             ifds.push(ifd);
             if offset == 0 { break; } // last IFD
             try!(rdr.seek(SeekFrom::Start(offset as u64)));
         }
 
-        Ok(Exif{ ifds: ifds })
+        Ok(Exif{
+            ifds: ifds,
+            big_endian: big_endian,
+            big_tiff: big_tiff,
+            maker_note_offset: st.maker_note_offset,
+        })
     }
 
     /// Write an existing Exif to the given writer
+    ///
+    /// the MakerNote is copied verbatim, and usually ends up at a different
+    /// offset than in the source document. offsets within notes that are
+    /// relative to the document are not rebased, so they will be stale.
     pub fn encode<W: Write + Seek>(&self, w: &mut W) -> io::Result<()> {
-        const HEADER: [u8; 8] = [b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00];
-
-        let mut pw = PosWriter::new(w);
-        try!(pw.write_all(&HEADER));
+        const HEADER_LE: [u8; 8] = [b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00];
+        const HEADER_BE: [u8; 8] = [b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08];
+        const BIG_HEADER_LE: [u8; 16] = [b'I', b'I', 0x2B, 0x00, 0x08, 0x00, 0x00, 0x00,
+                                         0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        const BIG_HEADER_BE: [u8; 16] = [b'M', b'M', 0x00, 0x2B, 0x00, 0x08, 0x00, 0x00,
+                                         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10];
+
+        let mut pw = try!(PosWriter::new(w));
+        try!(pw.write_all(match (self.big_tiff, self.big_endian) {
+            (false, false) => &HEADER_LE[..],
+            (false, true) => &HEADER_BE[..],
+            (true, false) => &BIG_HEADER_LE[..],
+            (true, true) => &BIG_HEADER_BE[..],
+        }));
 
         for (n, ifd) in self.ifds.iter().enumerate() {
-            try!(ifd.encode::<_, LittleEndian>(&mut pw, n == self.ifds.len() - 1));
+            let last = n == self.ifds.len() - 1;
+            if self.big_endian { try!(ifd.encode::<_, BigEndian>(&mut pw, last, self.big_tiff)); }
+            else               { try!(ifd.encode::<_, LittleEndian>(&mut pw, last, self.big_tiff)); }
         }
 
         Ok(())
     }
+
+    /// camera manufacturer, from IFD0
+    pub fn make(&self) -> Option<&str> {
+        self.ifds.first().and_then(|ifd| ifd.ascii(tag::MAKE))
+    }
+
+    /// camera model, from IFD0
+    pub fn model(&self) -> Option<&str> {
+        self.ifds.first().and_then(|ifd| ifd.ascii(tag::MODEL))
+    }
+
+    /// raw contents of the MakerNote entry in the Exif subIFD, if present
+    pub fn maker_note(&self) -> Option<&[u8]> {
+        match self.child(tag::EXIF_IFD_POINTER).and_then(|ifd| ifd.entry(tag::MAKER_NOTE)) {
+            Some(&Entry{ data: EntryData::Undef(ref v), .. }) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// capture time from the Exif subIFD, with its UTC offset if recorded
+    pub fn date_time_original(&self) -> Option<DateTime> {
+        let ifd = self.child(tag::EXIF_IFD_POINTER)?;
+        let mut d = DateTime::from_exif(ifd.ascii(tag::DATE_TIME_ORIGINAL)?)?;
+        d.offset = ifd.ascii(tag::OFFSET_TIME_ORIGINAL).and_then(DateTime::parse_offset);
+        Some(d)
+    }
+
+    /// position from the GPS IFD, if latitude and longitude are both present
+    pub fn gps(&self) -> Option<GpsPosition> {
+        let ifd = self.child(tag::GPS_INFO_IFD_POINTER)?;
+        let coord = |val: u16, r: u16| GpsPosition::degrees_from_dms(&ifd.entry(val)?.data.floats()?, ifd.ascii(r)?);
+        let altitude = ifd.entry(tag::gps::ALTITUDE).and_then(|e| e.data.floats()).and_then(|v| v.first().cloned())
+            .map(|a| match ifd.entry(tag::gps::ALTITUDE_REF).and_then(|e| e.data.uints()) {
+                Some(ref r) if r.first() == Some(&1) => -a,
+                _ => a,
+            });
+        Some(GpsPosition{
+            latitude: coord(tag::gps::LATITUDE, tag::gps::LATITUDE_REF)?,
+            longitude: coord(tag::gps::LONGITUDE, tag::gps::LONGITUDE_REF)?,
+            altitude: altitude,
+        })
+    }
+
+    // a child of IFD0, such as the Exif or GPS IFD
+    fn child(&self, id: u16) -> Option<&Ifd> {
+        self.ifds.first().and_then(|ifd| ifd.children.iter().find(|c| c.id == id))
+    }
+
+    /// IFD0, created if the document is empty
+    pub fn ifd0_mut(&mut self) -> &mut Ifd {
+        if self.ifds.is_empty() {
+            self.ifds.push(Ifd{ id: 0, entries: vec![], children: vec![] });
+        }
+        &mut self.ifds[0]
+    }
+
+    /// a child of IFD0 such as the Exif or GPS IFD, created if not present
+    pub fn child_mut(&mut self, id: u16) -> &mut Ifd {
+        let ifd0 = self.ifd0_mut();
+        let i = match ifd0.children.iter().position(|c| c.id == id) {
+            Some(i) => i,
+            None => {
+                ifd0.children.push(Ifd{ id: id, entries: vec![], children: vec![] });
+                ifd0.children.len() - 1
+            }
+        };
+        &mut ifd0.children[i]
+    }
 }
 
 /// Image file directory - container for a collection of Entries
@@ -104,8 +254,44 @@ pub struct Ifd {
 }
 
 impl Ifd {
+    /// find the first entry in this IFD with the given tag
+    pub fn entry(&self, tag: u16) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+
+    /// contents of the Ascii entry with the given tag, if present
+    pub fn ascii(&self, tag: u16) -> Option<&str> {
+        match self.entry(tag) {
+            Some(&Entry{ data: EntryData::Ascii(ref s), .. }) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// add an entry, replacing any existing entry with the same tag
+    pub fn set(&mut self, tag: u16, data: EntryData) {
+        match self.entries.iter().position(|e| e.tag == tag) {
+            Some(i) => self.entries[i].data = data,
+            None => self.entries.push(Entry{ tag: tag, data: data }),
+        }
+    }
+
+    pub fn remove(&mut self, tag: u16) -> Option<Entry> {
+        let i = self.entries.iter().position(|e| e.tag == tag)?;
+        Some(self.entries.remove(i))
+    }
+
     fn new<R: Read + Seek, B: ByteOrder>(rdr: &mut R, id: u16) -> io::Result<(Self, usize)> {
-        let num_headers = try!(rdr.read_u16::<B>());
+        Self::decode::<_, B>(rdr, id, &mut DecodeState::default())
+    }
+
+    fn decode<R: Read + Seek, B: ByteOrder>(rdr: &mut R, id: u16, st: &mut DecodeState) -> io::Result<(Self, usize)> {
+        let pos = try!(rdr.stream_position());
+        if st.visited.contains(&pos) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("IFD loop at offset {}", pos)));
+        }
+        st.visited.push(pos);
+
+        let num_headers = if st.big_tiff { try!(rdr.read_u64::<B>()) } else { try!(rdr.read_u16::<B>()) as u64 };
 
         let mut children = vec![];
         let mut entries = vec![];
@@ -113,10 +299,10 @@ impl Ifd {
         // headers are continguous, followed by offset_to_next_ifd and entry data
         let mut hdrs = vec![];
         for _ in 0..num_headers {
-            hdrs.push(try!(EntryHeader::decode::<_, B>(rdr)));
+            hdrs.push(try!(EntryHeader::decode::<_, B>(rdr, st.big_tiff)));
         }
 
-        let offset_to_next_ifd = try!(rdr.read_u32::<B>()) as usize;
+        let offset_to_next_ifd = if st.big_tiff { try!(rdr.read_u64::<B>()) } else { try!(rdr.read_u32::<B>()) as u64 } as usize;
 
         // XXX: plumb this back up to the Exif struct
         // let mut thumbnail = JpegThumbnail::new();
@@ -125,14 +311,22 @@ impl Ifd {
             match h.tag {
                 // follow known pointers to generate SubIFDs
                 tag::EXIF_IFD_POINTER | tag::GPS_INFO_IFD_POINTER | tag::INTEROPERABILITY_IFD_POINTER => {
-                    if let OffsetValue::Value(ref v) = h.offset_val {
-                        let off = B::read_u32(v);
-                        try!(rdr.seek(SeekFrom::Start(off as u64)));
-                        let (ifd, _) = try!(Ifd::new::<_, B>(rdr, h.tag));
+                    if let OffsetValue::Value(_) = h.offset_val {
+                        let ptrs = try!(h.pointers::<_, B>(rdr));
+                        try!(rdr.seek(SeekFrom::Start(ptrs[0])));
+                        let (ifd, _) = try!(Ifd::decode::<_, B>(rdr, h.tag, st));
                         children.push(ifd);
                     }
                     // XXX: provide invalid format feedback
                 },
+                // an array of pointers to child IFDs, used by DNG for raw and preview images
+                tag::SUB_IFDS => {
+                    for ptr in try!(h.pointers::<_, B>(rdr)) {
+                        try!(rdr.seek(SeekFrom::Start(ptr)));
+                        let (ifd, _) = try!(Ifd::decode::<_, B>(rdr, h.tag, st));
+                        children.push(ifd);
+                    }
+                },
                 /*
                 // only handle jpeg thumbnails at the moment
                 tag::JPEG_THUMBNAIL_LENGTH => {
@@ -147,6 +341,9 @@ impl Ifd {
                 },
                 */
                 _ => {
+                    if let (tag::MAKER_NOTE, &OffsetValue::Offset(o)) = (h.tag, &h.offset_val) {
+                        st.maker_note_offset = Some(o);
+                    }
                     let e = try!(Entry::from_header::<_, B>(rdr, h));
                     entries.push(e);
                 },
@@ -160,67 +357,136 @@ impl Ifd {
         }, offset_to_next_ifd))
     }
 
-    /// similar to Entry::encode_header(), but the offset to the subIFD
-    /// is encoded in the ULong immediate
-    fn encode_subifd_header<W: Write, B: ByteOrder>(&self, pw: &mut PosWriter<W>, data_offset: usize) -> io::Result<()> {
-        try!(pw.write_u16::<B>(self.id));
-        try!(pw.write_u16::<B>(4));  // ULong
-        try!(pw.write_u32::<B>(1));
-        pw.write_u32::<B>(data_offset as u32)
-    }
+    fn encode<W: Write + Seek, B: ByteOrder>(&self, pw: &mut PosWriter<W>, last: bool, big: bool) -> io::Result<()> {
+        let (ifd_header_len, ptr_len) = if big { (20, 8) } else { (12, 4) };
+
+        // children sharing a tag (e.g. SubIFDs) are referenced by a single header
+        let mut groups: Vec<(u16, Vec<&Ifd>)> = vec![];
+        for c in &self.children {
+            if let Some(g) = groups.iter_mut().find(|g| g.0 == c.id) {
+                g.1.push(c);
+                continue;
+            }
+            groups.push((c.id, vec![c]));
+        }
 
-    fn encode<W: Write + Seek, B: ByteOrder>(&self, pw: &mut PosWriter<W>, last: bool) -> io::Result<()> {
-        const IFD_HEADER_LEN: usize = 12;
-        const NEXT_IFD_PTR_LEN: usize = 4;
+        // headers must be written in ascending tag order
+        let mut headers = self.entries.iter().map(Header::Entry)
+            .chain(groups.iter().map(|g| Header::Children(g.0, &g.1)))
+            .collect::<Vec<_>>();
+        headers.sort_by_key(|h| h.tag());
 
-        let num_headers = self.entries.len() + self.children.len();
-        try!(pw.write_u16::<B>(num_headers as u16));
+        let num_headers = headers.len();
+        if big { try!(pw.write_u64::<B>(num_headers as u64)); }
+        else   { try!(pw.write_u16::<B>(num_headers as u16)); }
 
         // data segment for this IFD starts here
-        let mut data_offset = pw.position() as usize + num_headers * IFD_HEADER_LEN + NEXT_IFD_PTR_LEN;
+        let mut data_offset = pw.position() as usize + num_headers * ifd_header_len + ptr_len;
 
-        // track which EntryData instances must be written to this IFD's data segment
-        let mut offset_data_entries = vec![];
+        // track which headers must write to this IFD's data segment
+        let mut offset_data = vec![];
+        // locations at which to write the offset of each child, once known
+        let mut child_ptrs = vec![];
 
         // write headers for both entries and children...
-        for e in &self.entries {
-            if try!(e.encode_header::<_, B>(pw, data_offset)) {
-                data_offset += e.data.total_sz();
-                offset_data_entries.push(e);
+        for h in &headers {
+            match *h {
+                Header::Entry(e) => {
+                    if try!(e.encode_header::<_, B>(pw, data_offset, big)) {
+                        data_offset += e.data.total_sz();
+                        offset_data.push(h);
+                    }
+                },
+                Header::Children(id, ifds) => {
+                    try!(pw.write_u16::<B>(id));
+                    if big {
+                        try!(pw.write_u16::<B>(18));  // Ifd8
+                        try!(pw.write_u64::<B>(ifds.len() as u64));
+                    } else {
+                        try!(pw.write_u16::<B>(4));  // ULong
+                        try!(pw.write_u32::<B>(ifds.len() as u32));
+                    }
+                    if ifds.len() == 1 {
+                        child_ptrs.push((pw.position(), ifds[0]));
+                        try!(write_ptr::<_, B>(pw, 0, big)); // placeholder
+                    } else {
+                        try!(write_ptr::<_, B>(pw, data_offset as u64, big));
+                        data_offset += ifds.len() * ptr_len;
+                        offset_data.push(h);
+                    }
+                },
             }
         }
 
-        for c in &self.children {
-            try!(c.encode_subifd_header::<_, B>(pw, data_offset));
-        }
-
         // write offset to next IFD or 0 if this is the last
         // we'll need to seek back here once we know the size of the written data segment and any subIFDs
         let next_offset_pos = pw.position();
-        try!(pw.write_u32::<B>(0u32)); // placeholder
+        try!(write_ptr::<_, B>(pw, 0, big)); // placeholder
 
         // ...followed by data for both entries and children
-        for e in offset_data_entries {
-            try!(e.data.encode_offset_data::<_, B>(pw));
+        for h in offset_data {
+            match *h {
+                Header::Entry(e) => try!(e.data.encode_offset_data::<_, B>(pw)),
+                Header::Children(_, ifds) => for c in ifds {
+                    child_ptrs.push((pw.position(), c));
+                    try!(write_ptr::<_, B>(pw, 0, big)); // placeholder
+                },
+            }
         }
 
-        for c in &self.children {
-            // at the moment, we do not descend into child hierarchies
-            try!(c.encode::<_, B>(pw, true));
+        for (ptr_pos, c) in child_ptrs {
+            try!(pad_to_word(pw));
+            try!(patch_ptr::<_, B>(pw, ptr_pos, big));
+            try!(c.encode::<_, B>(pw, true, big));
         }
 
         // seek back to next_offset location and write it, now that we know it
         if !last {
-            let cur_pos = pw.position();
-            try!(pw.seek(SeekFrom::Start(next_offset_pos)));
-            try!(pw.write_u32::<B>(cur_pos as u32));
-            try!(pw.seek(SeekFrom::Start(cur_pos)));
+            try!(pad_to_word(pw));
+            try!(patch_ptr::<_, B>(pw, next_offset_pos, big));
         }
 
         Ok(())
     }
 }
 
+// a single header within an encoded IFD
+enum Header<'a> {
+    Entry(&'a Entry),
+    Children(u16, &'a [&'a Ifd]),
+}
+
+impl<'a> Header<'a> {
+    fn tag(&self) -> u16 {
+        match *self {
+            Header::Entry(e) => e.tag,
+            Header::Children(id, _) => id,
+        }
+    }
+}
+
+fn write_ptr<W: Write, B: ByteOrder>(w: &mut W, ptr: u64, big: bool) -> io::Result<()> {
+    if big { w.write_u64::<B>(ptr) }
+    else   { w.write_u32::<B>(ptr as u32) }
+}
+
+// write the current position at an earlier location
+fn patch_ptr<W: Write + Seek, B: ByteOrder>(pw: &mut PosWriter<W>, at: u64, big: bool) -> io::Result<()> {
+    let cur_pos = pw.position();
+    try!(pw.seek(SeekFrom::Start(at)));
+    try!(write_ptr::<_, B>(pw, cur_pos, big));
+    try!(pw.seek(SeekFrom::Start(cur_pos)));
+    Ok(())
+}
+
+// IFDs must begin on a word boundary
+fn pad_to_word<W: Write + Seek>(pw: &mut PosWriter<W>) -> io::Result<()> {
+    if pw.position() & 1 == 1 {
+        try!(pw.write_all(&[0]));
+    }
+    Ok(())
+}
+
 /*
 #[derive(Debug)]
 pub struct JpegThumbnail {
@@ -256,9 +522,18 @@ impl JpegThumbnail {
 }
 */
 
+// state accumulated while decoding a chain of IFDs
+#[derive(Default)]
+struct DecodeState {
+    big_tiff: bool,
+    maker_note_offset: Option<u64>,
+    /// offsets of the IFDs decoded so far, to detect loops
+    visited: Vec<u64>,
+}
+
 #[derive(Debug)]
 enum OffsetValue {
-    Offset(u32),
+    Offset(u64),
     Value(Vec<u8>),
 }
 
@@ -266,24 +541,27 @@ enum OffsetValue {
 struct EntryHeader {
     tag: u16,
     format: u16,
-    count: u32,
+    count: u64,
     offset_val: OffsetValue,
 }
 
 impl EntryHeader {
-    fn decode<R: Read, B: ByteOrder>(rdr: &mut R) -> io::Result<Self> {
+    fn decode<R: Read, B: ByteOrder>(rdr: &mut R, big: bool) -> io::Result<Self> {
         let tag = try!(rdr.read_u16::<B>());
         let fmt = try!(rdr.read_u16::<B>());
-        let n = try!(rdr.read_u32::<B>());
+        let n = if big { try!(rdr.read_u64::<B>()) } else { try!(rdr.read_u32::<B>()) as u64 };
 
-        // if all the data fits into 4 bytes, expect an OffsetValue::Value encoded immediately,
+        // if all the data fits into 4 (BigTIFF: 8) bytes, expect an OffsetValue::Value encoded immediately,
         // otherwise expect an OffsetValue::Offset to the data
-        let ov = if Self::datatype_sz(fmt) * n as usize <= 4 {
-            let mut buf = vec![0u8; 4];
+        let immediate_sz = if big { 8 } else { 4 };
+        let ov = if (Self::datatype_sz(fmt) as u64).saturating_mul(n) <= immediate_sz {
+            let mut buf = vec![0u8; immediate_sz as usize];
             try!(rdr.read_exact(&mut buf));
             OffsetValue::Value(buf)
+        } else if big {
+            OffsetValue::Offset(try!(rdr.read_u64::<B>()))
         } else {
-            OffsetValue::Offset(try!(rdr.read_u32::<B>()))
+            OffsetValue::Offset(try!(rdr.read_u32::<B>()) as u64)
         };
 
         Ok(EntryHeader{
@@ -298,8 +576,9 @@ impl EntryHeader {
         match dt {
             1 /*Byte*/ | 2 /*Ascii*/ | 6 /*SignedByte*/ | 7 /*Undef*/ => 1,
             3 /*UShort*/ | 8 /*SShort*/ => 2,
-            4 /*ULong*/ | 9 /*SLong*/ | 11 /*Float32*/ => 4,
-            5 /*URational*/ | 10 /*SRational*/ | 12 /*Float64*/ => 8,
+            4 /*ULong*/ | 9 /*SLong*/ | 11 /*Float32*/ | 13 /*Ifd*/ => 4,
+            5 /*URational*/ | 10 /*SRational*/ | 12 /*Float64*/ |
+            16 /*ULong8*/ | 17 /*SLong8*/ | 18 /*Ifd8*/ => 8,
             _ => 0,
         }
     }
@@ -308,8 +587,52 @@ impl EntryHeader {
         Self::datatype_sz(self.format)
     }
 
-    fn data_sz(&self) -> usize {
-        self.sz() * self.count as usize
+    // decode the offsets held by a pointer to one or more IFDs
+    fn pointers<R: Read + Seek, B: ByteOrder>(&self, rdr: &mut R) -> io::Result<Vec<u64>> {
+        let d = try!(self.data(rdr));
+        let mut ptrs = Vec::with_capacity(self.capacity(&d));
+        let mut c = io::Cursor::new(d);
+        for _ in 0..self.count {
+            ptrs.push(match self.format {
+                4 | 13 => try!(c.read_u32::<B>()) as u64,
+                16 | 18 => try!(c.read_u64::<B>()),
+                f => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid IFD pointer format: 0x{:x}", f))),
+            });
+        }
+        if ptrs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty IFD pointer"));
+        }
+        Ok(ptrs)
+    }
+
+    fn data_sz(&self) -> Option<usize> {
+        (self.sz() as u64).checked_mul(self.count)
+            .filter(|&n| n <= usize::MAX as u64)
+            .map(|n| n as usize)
+    }
+
+    // the raw data of the entry, checking that data at an offset lies within the stream
+    fn data<R: Read + Seek>(&self, rdr: &mut R) -> io::Result<Vec<u8>> {
+        let o = match self.offset_val {
+            OffsetValue::Value(ref v) => return Ok(v.to_owned()),
+            OffsetValue::Offset(o) => o,
+        };
+        let len = try!(self.data_sz().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "entry data too large")));
+        let end = try!(rdr.seek(SeekFrom::End(0)));
+        match o.checked_add(len as u64) {
+            Some(e) if e <= end => (),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("entry data out of bounds: 0x{:x}", self.tag))),
+        }
+        let mut v = vec![0u8; len];
+        try!(rdr.seek(SeekFrom::Start(o)));
+        try!(rdr.read_exact(&mut v));
+        Ok(v)
+    }
+
+    // the number of values held by the data read for this entry, which bounds
+    // the count without trusting it
+    fn capacity(&self, d: &[u8]) -> usize {
+        (self.count as usize).min(d.len() / self.sz().max(1))
     }
 }
 
@@ -330,20 +653,23 @@ impl Entry {
 
     /// encode the header portion of this enty,
     /// return true if data must subsequently be written into the data segment
-    fn encode_header<W: Write, B: ByteOrder>(&self, w: &mut W, data_offset: usize) -> io::Result<bool> {
+    fn encode_header<W: Write, B: ByteOrder>(&self, w: &mut W, data_offset: usize, big: bool) -> io::Result<bool> {
         try!(w.write_u16::<B>(self.tag));
         try!(w.write_u16::<B>(self.data.format_code()));
-        try!(w.write_u32::<B>(self.data.len() as u32));
+        if big { try!(w.write_u64::<B>(self.data.len() as u64)); }
+        else   { try!(w.write_u32::<B>(self.data.len() as u32)); }
 
-        if self.data.total_sz() > 4 {
-            try!(w.write_u32::<B>(data_offset as u32));
+        let immediate_sz = if big { 8 } else { 4 };
+        if self.data.total_sz() > immediate_sz {
+            if big { try!(w.write_u64::<B>(data_offset as u64)); }
+            else   { try!(w.write_u32::<B>(data_offset as u32)); }
             Ok(true)
         } else {
             try!(self.data.encode_offset_data::<_, B>(w));
-            // immediate data must be padded to 4 bytes
-            let pad = 4isize - self.data.total_sz() as isize;
+            // immediate data must be padded to 4 (BigTIFF: 8) bytes
+            let pad = immediate_sz as isize - self.data.total_sz() as isize;
             if pad > 0 {
-                let pbuf = [0u8; 4];
+                let pbuf = [0u8; 8];
                 try!(w.write_all(&mut &pbuf[..pad as usize]));
             }
             Ok(false)
@@ -358,17 +684,49 @@ pub enum EntryData {
     Ascii(String),
     UShort(Vec<u16>),
     ULong(Vec<u32>),
-    URational(Vec<u64>),
+    URational(Vec<u64>),  // numerator << 32 | denominator
     SignedByte(Vec<i8>),
     Undef(Vec<u8>),  // or vendor specific
     SShort(Vec<i16>),
     SLong(Vec<i32>),
-    SRational(Vec<i64>),
+    SRational(Vec<i64>),  // numerator << 32 | denominator
     Float32(Vec<f32>),
     Float64(Vec<f64>),
+    ULong8(Vec<u64>),  // BigTIFF only
+    SLong8(Vec<i64>),  // BigTIFF only
+    Ifd8(Vec<u64>),    // BigTIFF only
 }
 
 impl EntryData {
+    /// values of an unsigned integer entry, widened to u64
+    pub fn uints(&self) -> Option<Vec<u64>> {
+        use self::EntryData::*;
+        match *self {
+            Byte(ref v) => Some(v.iter().map(|&x| x as u64).collect()),
+            UShort(ref v) => Some(v.iter().map(|&x| x as u64).collect()),
+            ULong(ref v) => Some(v.iter().map(|&x| x as u64).collect()),
+            ULong8(ref v) | Ifd8(ref v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+
+    /// values of a numeric entry as f64, rationals are divided out
+    pub fn floats(&self) -> Option<Vec<f64>> {
+        use self::EntryData::*;
+        fn ratio(num: f64, den: f64) -> f64 {
+            if den == 0.0 { 0.0 } else { num / den }
+        }
+        match *self {
+            URational(ref v) => Some(v.iter().map(|&r| ratio((r >> 32) as f64, (r as u32) as f64)).collect()),
+            SRational(ref v) => Some(v.iter().map(|&r| ratio((r >> 32) as i32 as f64, (r as i32) as f64)).collect()),
+            Float32(ref v) => Some(v.iter().map(|&x| x as f64).collect()),
+            Float64(ref v) => Some(v.clone()),
+            SShort(ref v) => Some(v.iter().map(|&x| x as f64).collect()),
+            SLong(ref v) => Some(v.iter().map(|&x| x as f64).collect()),
+            _ => self.uints().map(|v| v.iter().map(|&x| x as f64).collect()),
+        }
+    }
+
     fn item_sz(&self) -> usize {
         use self::EntryData::*;
         match *self {
@@ -376,6 +734,7 @@ impl EntryData {
             UShort(_) | SShort(_) => 2,
             ULong(_) | SLong(_) | Float32(_) => 4,
             URational(_) | SRational(_) | Float64(_) => 8,
+            ULong8(_) | SLong8(_) | Ifd8(_) => 8,
         }
     }
 
@@ -395,6 +754,9 @@ impl EntryData {
             SRational(ref v) => v.len(),
             Float32(ref v) => v.len(),
             Float64(ref v) => v.len(),
+            ULong8(ref v) => v.len(),
+            SLong8(ref v) => v.len(),
+            Ifd8(ref v) => v.len(),
         }
     }
 
@@ -417,78 +779,101 @@ impl EntryData {
             SRational(_) => 10,
             Float32(_) => 11,
             Float64(_) => 12,
+            ULong8(_) => 16,
+            SLong8(_) => 17,
+            Ifd8(_) => 18,
         }
     }
 
     fn from_header<R: Read + Seek, B: ByteOrder>(rdr: &mut R, h: &EntryHeader) -> io::Result<Self> {
-        let d = match h.offset_val {
-            OffsetValue::Value(ref v) => v.to_owned(),
-            OffsetValue::Offset(o) => {
-                let mut v = vec![0u8; h.data_sz()];
-                try!(rdr.seek(SeekFrom::Start(o as u64)));
-                try!(rdr.read_exact(&mut v));
-                v
-            }
-        };
+        let d = try!(h.data(rdr));
+        let n = h.capacity(&d);
 
         match h.format {
             1 => Ok(EntryData::Byte(d)),
             2 => {
                 let null_term = try!(d.iter().position(|&c| c == 0)
                                         .ok_or(io::Error::new(io::ErrorKind::InvalidData, "invalid ascii data, no null terminator")));
-                Ok(EntryData::Ascii(String::from_utf8_lossy(&d[..null_term]).to_string()))
+                // the spec says 7-bit ASCII, but UTF-8 is common, and anything
+                // else is most likely Latin-1
+                let d = &d[..null_term];
+                Ok(EntryData::Ascii(match ::std::str::from_utf8(d) {
+                    Ok(s) => s.to_string(),
+                    Err(_) => d.iter().map(|&c| c as char).collect(),
+                }))
             },
             3 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_u16::<B>())); }
                 Ok(EntryData::UShort(v))
             },
-            4 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+            // IFD offsets, as used by some MakerNotes, are kept as plain ULong
+            4 | 13 => {
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_u32::<B>())); }
                 Ok(EntryData::ULong(v))
             },
             5 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
-                for _ in 0..h.count { v.push(try!(c.read_u64::<B>())); }
+                for _ in 0..h.count {
+                    let num = try!(c.read_u32::<B>()) as u64;
+                    let den = try!(c.read_u32::<B>()) as u64;
+                    v.push(num << 32 | den);
+                }
                 Ok(EntryData::URational(v))
             },
             // XXX: better way to convert?
             6 => Ok(EntryData::SignedByte(d.iter().map(|&b| b as i8).collect())),
             7 => Ok(EntryData::Undef(d)),
             8 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_u16::<B>()) as i16); }
                 Ok(EntryData::SShort(v))
             },
             9 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_u32::<B>()) as i32); }
                 Ok(EntryData::SLong(v))
             },
             10 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
-                for _ in 0..h.count { v.push(try!(c.read_i64::<B>())); }
+                for _ in 0..h.count {
+                    let num = try!(c.read_i32::<B>()) as i64;
+                    let den = try!(c.read_u32::<B>()) as i64;
+                    v.push(num << 32 | den);
+                }
                 Ok(EntryData::SRational(v))
             },
             11 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_f32::<B>())); }
                 Ok(EntryData::Float32(v))
             },
             12 => {
-                let mut v = Vec::with_capacity(h.count as usize);
+                let mut v = Vec::with_capacity(n);
                 let mut c = io::Cursor::new(d);
                 for _ in 0..h.count { v.push(try!(c.read_f64::<B>())); }
                 Ok(EntryData::Float64(v))
             },
+            16 | 18 => {
+                let mut v = Vec::with_capacity(n);
+                let mut c = io::Cursor::new(d);
+                for _ in 0..h.count { v.push(try!(c.read_u64::<B>())); }
+                Ok(if h.format == 16 { EntryData::ULong8(v) } else { EntryData::Ifd8(v) })
+            },
+            17 => {
+                let mut v = Vec::with_capacity(n);
+                let mut c = io::Cursor::new(d);
+                for _ in 0..h.count { v.push(try!(c.read_i64::<B>())); }
+                Ok(EntryData::SLong8(v))
+            },
             v => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid entry header format: 0x{:x}", v)))
         }
     }
@@ -505,7 +890,10 @@ impl EntryData {
             },
             UShort(ref v) =>    for d in v { try!(w.write_u16::<B>(*d)); },
             ULong(ref v) =>     for d in v { try!(w.write_u32::<B>(*d)); },
-            URational(ref v) => for d in v { try!(w.write_u64::<B>(*d)); },
+            URational(ref v) => for d in v {
+                try!(w.write_u32::<B>((*d >> 32) as u32));
+                try!(w.write_u32::<B>(*d as u32));
+            },
             SignedByte(ref v) => {
                 // XXX: better way to convert?
                 let vb = v.iter().map(|&b| b as u8).collect::<Vec<u8>>();
@@ -513,9 +901,14 @@ impl EntryData {
             },
             SShort(ref v) =>    for d in v { try!(w.write_u16::<B>(*d as u16)); },
             SLong(ref v) =>     for d in v { try!(w.write_u32::<B>(*d as u32)); },
-            SRational(ref v) => for d in v { try!(w.write_u64::<B>(*d as u64)); },
+            SRational(ref v) => for d in v {
+                try!(w.write_i32::<B>((*d >> 32) as i32));
+                try!(w.write_u32::<B>(*d as u32));
+            },
             Float32(ref v) =>   for d in v { try!(w.write_f32::<B>(*d)); },
             Float64(ref v) =>   for d in v { try!(w.write_f64::<B>(*d)); },
+            ULong8(ref v) | Ifd8(ref v) => for d in v { try!(w.write_u64::<B>(*d)); },
+            SLong8(ref v) =>    for d in v { try!(w.write_i64::<B>(*d)); },
         }
 
         Ok(())
@@ -523,15 +916,29 @@ impl EntryData {
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use std::fs::File;
     use std::io::{BufReader, Cursor};
 
+    /// a little endian exif with the given ascii entries in IFD0
+    pub fn exif_with(entries: &[(u16, &str)]) -> Exif {
+        Exif::from_ifds(vec![Ifd{ id: 0, entries: entries.iter().map(|&(t, v)| {
+            Entry{ tag: t, data: EntryData::Ascii(v.to_string()) }
+        }).collect(), children: vec![] }])
+    }
+
+    /// encode a file with `encode` and decode the result with `decode`
+    pub fn roundtrip<T, E: Fn(&T, &mut Vec<u8>) -> io::Result<()>, D: Fn(&mut Cursor<Vec<u8>>) -> io::Result<T>>(v: &T, encode: E, decode: D) -> T {
+        let mut buf = vec![];
+        encode(v, &mut buf).expect("encode file");
+        decode(&mut Cursor::new(buf)).expect("decode file")
+    }
+
     #[test]
     fn basic_decode_jpeg() {
         let mut f = File::open("src/fixtures/IMG_2222.JPG").expect("couldn't open file");
-        let segment = jpeg::extract_exif(&mut f).expect("extract exif");
+        let segment = jpeg::extract_exif(&mut f).expect("extract exif").expect("exif present");
 
         let e = Exif::new(&mut Cursor::new(segment)).expect("extract exif");
         dump_exif(&e);
@@ -558,6 +965,154 @@ mod tests {
         // dump_exif(&e);
     }
 
+    #[test]
+    fn rationals_little_endian() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+
+        // XResolution, 350/1, stored as little endian u32s
+        let x_res = |e: &Exif| match e.ifds[0].entry(0x011a).map(|e| &e.data) {
+            Some(EntryData::URational(v)) => v.clone(),
+            d => panic!("unexpected x resolution {:?}", d),
+        };
+        assert_eq!(x_res(&e), vec![350 << 32 | 1]);
+
+        let mut buf = Cursor::new(vec![]);
+        e.encode(&mut buf).expect("encode exif file");
+        buf.set_position(0);
+        assert_eq!(x_res(&Exif::new(&mut buf).expect("extract exif 2")), vec![350 << 32 | 1]);
+    }
+
+    #[test]
+    fn encode_after_prefix() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+
+        let mut plain = Cursor::new(vec![]);
+        e.encode(&mut plain).expect("encode exif");
+
+        let mut prefixed = Cursor::new(b"Exif\0\0".to_vec());
+        prefixed.set_position(6);
+        e.encode(&mut prefixed).expect("encode prefixed exif");
+        assert_eq!(&prefixed.get_ref()[..6], b"Exif\0\0");
+        assert_eq!(&prefixed.get_ref()[6..], &plain.get_ref()[..]);
+    }
+
+    #[test]
+    fn sibling_subifds_roundtrip() {
+        let child = |id, t| Ifd{ id: id, entries: vec![Entry{ tag: t, data: EntryData::Ascii("x".to_string()) }], children: vec![] };
+        let e = Exif::from_ifds(vec![Ifd{ id: 0, entries: vec![], children: vec![
+            child(tag::GPS_INFO_IFD_POINTER, tag::gps::LATITUDE_REF),
+            child(tag::EXIF_IFD_POINTER, tag::IMG_DESCRIPTION),
+        ] }]);
+
+        let mut buf = Cursor::new(vec![]);
+        e.encode(&mut buf).expect("encode exif");
+        buf.set_position(0);
+        let e2 = Exif::new(&mut buf).expect("decode exif");
+
+        // children are written in tag order
+        let ids = e2.ifds[0].children.iter().map(|c| (c.id, c.entries[0].tag)).collect::<Vec<_>>();
+        assert_eq!(ids, vec![(tag::EXIF_IFD_POINTER, tag::IMG_DESCRIPTION), (tag::GPS_INFO_IFD_POINTER, tag::gps::LATITUDE_REF)]);
+    }
+
+    #[test]
+    fn sub_ifd_loop() {
+        // IFD0 lists itself in SubIFDs
+        let tiff = [b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00,
+                    0x01, 0x00, 0x4A, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00];
+        let e = Exif::new(&mut Cursor::new(&tiff[..])).expect_err("loop");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn big_tiff_bad_count() {
+        // one ULong8 entry in IFD0, with a count of 2^61
+        let mut tiff = vec![b'I', b'I', 0x2B, 0x00, 0x08, 0x00, 0x00, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0,
+                            0x01, 0, 0, 0, 0, 0, 0, 0,
+                            0x00, 0x01, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0, 0x20, 0x10, 0, 0, 0, 0, 0, 0, 0,
+                            0, 0, 0, 0, 0, 0, 0, 0];
+        let e = Exif::new(&mut Cursor::new(&tiff[..])).expect_err("overflowing count");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // and with a count of 4, running past the end of the file
+        tiff[28..36].copy_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+        tiff[36] = 0x18;
+        let e = Exif::new(&mut Cursor::new(&tiff[..])).expect_err("data past the end");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        tiff[28] = 3;
+        assert!(Exif::new(&mut Cursor::new(&tiff[..])).is_ok());
+    }
+
+    #[test]
+    fn date_and_gps() {
+        let entry = |t, d| Entry{ tag: t, data: d };
+        let rational = |n: u64, d: u64| n << 32 | d;
+        let e = Exif::from_ifds(vec![Ifd{ id: 0, entries: vec![], children: vec![
+            Ifd{ id: tag::EXIF_IFD_POINTER, entries: vec![
+                entry(tag::DATE_TIME_ORIGINAL, EntryData::Ascii("2018:09:05 10:11:12".to_string())),
+                entry(tag::OFFSET_TIME_ORIGINAL, EntryData::Ascii("-07:00".to_string())),
+            ], children: vec![] },
+            Ifd{ id: tag::GPS_INFO_IFD_POINTER, entries: vec![
+                entry(tag::gps::LATITUDE_REF, EntryData::Ascii("N".to_string())),
+                entry(tag::gps::LATITUDE, EntryData::URational(vec![rational(37, 1), rational(30, 1), rational(0, 1)])),
+                entry(tag::gps::LONGITUDE_REF, EntryData::Ascii("W".to_string())),
+                entry(tag::gps::LONGITUDE, EntryData::URational(vec![rational(122, 1), rational(15, 1), rational(36, 1)])),
+                entry(tag::gps::ALTITUDE_REF, EntryData::Byte(vec![1])),
+                entry(tag::gps::ALTITUDE, EntryData::URational(vec![rational(21, 2)])),
+            ], children: vec![] },
+        ] }]);
+
+        assert_eq!(e.date_time_original().expect("date").to_string(), "2018-09-05T10:11:12-07:00");
+        assert_eq!(e.gps(), Some(GpsPosition{ latitude: 37.5, longitude: -122.26, altitude: Some(-10.5) }));
+    }
+
+    #[test]
+    fn big_tiff_roundtrip() {
+        let fe = File::open("src/fixtures/exif-sony-1.bin").expect("open file");
+        let mut e = Exif::new(&mut BufReader::new(fe)).expect("extract exif");
+        e.big_tiff = true;
+        e.big_endian = true;
+
+        let mut buf = Cursor::new(vec![]);
+        e.encode(&mut buf).expect("encode exif file");
+        assert_eq!(&buf.get_ref()[..4], &[b'M', b'M', 0x00, 0x2B]);
+
+        buf.set_position(0);
+        let e2 = Exif::new(&mut buf).expect("extract exif 2");
+        assert!(e2.big_tiff && e2.big_endian);
+        assert_eq!(e2.ifds.len(), e.ifds.len());
+        assert_eq!(e2.ifds[0].entries.len(), e.ifds[0].entries.len());
+        assert_eq!(e2.make(), e.make());
+    }
+
+    #[test]
+    fn big_tiff_decode() {
+        // single IFD with a ULong8 entry stored inline and an SLong8 pair stored out of line
+        let mut v = vec![b'I', b'I', 0x2B, 0x00, 0x08, 0x00, 0x00, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0];
+        v.extend_from_slice(&[0x02, 0, 0, 0, 0, 0, 0, 0]);
+        v.extend_from_slice(&[0x11, 0x01, 0x10, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]);
+        v.extend_from_slice(&[0x00, 0x90, 0x11, 0x00, 0x02, 0, 0, 0, 0, 0, 0, 0, 0x48, 0, 0, 0, 0, 0, 0, 0]);
+        v.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        v.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02, 0, 0, 0, 0, 0, 0, 0]);
+
+        let e = Exif::new(&mut Cursor::new(v.clone())).expect("extract bigtiff");
+        assert!(e.big_tiff);
+        match e.ifds[0].entries[0].data {
+            EntryData::ULong8(ref v) => assert_eq!(v, &vec![0x0123456789ABCDEF]),
+            ref d => panic!("unexpected entry data {:?}", d),
+        }
+        match e.ifds[0].entries[1].data {
+            EntryData::SLong8(ref v) => assert_eq!(v, &vec![-1, 2]),
+            ref d => panic!("unexpected entry data {:?}", d),
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        e.encode(&mut buf).expect("encode bigtiff");
+        assert_eq!(buf.into_inner(), v);
+    }
+
     fn dump_exif(e: &Exif) {
         for ifd in &e.ifds {
             println!("ifd 0x{:x}, {} entries, {} children", ifd.id, ifd.entries.len(), ifd.children.len());
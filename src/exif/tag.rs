@@ -7,13 +7,35 @@ pub const INTEROPERABILITY_IFD_POINTER: u16 = 0xa005;
 pub const JPEG_THUMBNAIL_LENGTH: u16 = 0x0202;
 pub const JPEG_THUMBNAIL_OFFSET: u16 = 0x0201;
 
+pub const NEW_SUBFILE_TYPE: u16 = 0x00fe;
+pub const SUB_IFDS: u16 = 0x014a;
+
+pub const STRIP_OFFSETS: u16 = 0x0111;
+pub const STRIP_BYTE_COUNTS: u16 = 0x0117;
+pub const TILE_OFFSETS: u16 = 0x0144;
+pub const TILE_BYTE_COUNTS: u16 = 0x0145;
+
+pub const COMPRESSION: u16 = 0x0103;
+pub const PHOTOMETRIC_INTERPRETATION: u16 = 0x0106;
 pub const IMG_DESCRIPTION: u16 = 0x010e;
+pub const MAKE: u16 = 0x010f;
+pub const MODEL: u16 = 0x0110;
 pub const ORIENTATION: u16 = 0x0112;
+pub const ARTIST: u16 = 0x013b;
+pub const XMP: u16 = 0x02bc;
+pub const COPYRIGHT: u16 = 0x8298;
+pub const IPTC: u16 = 0x83bb;
+pub const ICC_PROFILE: u16 = 0x8773;
 
 pub const GPS_INFO: u16 = 0x8825;
 pub const MODIFY_DATE: u16 = 0x0132;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+pub const USER_COMMENT: u16 = 0x9286;
+pub const MAKER_NOTE: u16 = 0x927c;
 
 pub mod gps {
+    pub const VERSION_ID: u16 = 0x0000;
     pub const LATITUDE_REF: u16 = 0x0001;
     pub const LATITUDE: u16 = 0x0002;
     pub const LONGITUDE_REF: u16 = 0x0003;
@@ -21,3 +43,109 @@ pub mod gps {
     pub const ALTITUDE_REF: u16 = 0x0005;
     pub const ALTITUDE: u16 = 0x0006;
 }
+
+pub mod apple {
+    pub const MAKER_NOTE_VERSION: u16 = 0x0001;
+    pub const RUN_TIME: u16 = 0x0003;
+    pub const ACCELERATION_VECTOR: u16 = 0x0008;
+    pub const HDR_IMAGE_TYPE: u16 = 0x000a;
+    pub const BURST_UUID: u16 = 0x000b;
+    pub const CONTENT_IDENTIFIER: u16 = 0x0011;
+    pub const IMAGE_UNIQUE_ID: u16 = 0x0015;
+}
+
+pub mod dng {
+    pub const DNG_VERSION: u16 = 0xc612;
+    pub const DNG_BACKWARD_VERSION: u16 = 0xc613;
+    pub const UNIQUE_CAMERA_MODEL: u16 = 0xc614;
+    pub const LOCALIZED_CAMERA_MODEL: u16 = 0xc615;
+    pub const CFA_PLANE_COLOR: u16 = 0xc616;
+    pub const CFA_LAYOUT: u16 = 0xc617;
+    pub const LINEARIZATION_TABLE: u16 = 0xc618;
+    pub const BLACK_LEVEL_REPEAT_DIM: u16 = 0xc619;
+    pub const BLACK_LEVEL: u16 = 0xc61a;
+    pub const BLACK_LEVEL_DELTA_H: u16 = 0xc61b;
+    pub const BLACK_LEVEL_DELTA_V: u16 = 0xc61c;
+    pub const WHITE_LEVEL: u16 = 0xc61d;
+    pub const DEFAULT_SCALE: u16 = 0xc61e;
+    pub const DEFAULT_CROP_ORIGIN: u16 = 0xc61f;
+    pub const DEFAULT_CROP_SIZE: u16 = 0xc620;
+    pub const COLOR_MATRIX_1: u16 = 0xc621;
+    pub const COLOR_MATRIX_2: u16 = 0xc622;
+    pub const CAMERA_CALIBRATION_1: u16 = 0xc623;
+    pub const CAMERA_CALIBRATION_2: u16 = 0xc624;
+    pub const REDUCTION_MATRIX_1: u16 = 0xc625;
+    pub const REDUCTION_MATRIX_2: u16 = 0xc626;
+    pub const ANALOG_BALANCE: u16 = 0xc627;
+    pub const AS_SHOT_NEUTRAL: u16 = 0xc628;
+    pub const AS_SHOT_WHITE_XY: u16 = 0xc629;
+    pub const BASELINE_EXPOSURE: u16 = 0xc62a;
+    pub const BASELINE_NOISE: u16 = 0xc62b;
+    pub const BASELINE_SHARPNESS: u16 = 0xc62c;
+    pub const BAYER_GREEN_SPLIT: u16 = 0xc62d;
+    pub const LINEAR_RESPONSE_LIMIT: u16 = 0xc62e;
+    pub const CAMERA_SERIAL_NUMBER: u16 = 0xc62f;
+    pub const LENS_INFO: u16 = 0xc630;
+    pub const CHROMA_BLUR_RADIUS: u16 = 0xc631;
+    pub const ANTI_ALIAS_STRENGTH: u16 = 0xc632;
+    pub const SHADOW_SCALE: u16 = 0xc633;
+    pub const DNG_PRIVATE_DATA: u16 = 0xc634;
+    pub const MAKER_NOTE_SAFETY: u16 = 0xc635;
+    pub const CALIBRATION_ILLUMINANT_1: u16 = 0xc65a;
+    pub const CALIBRATION_ILLUMINANT_2: u16 = 0xc65b;
+    pub const BEST_QUALITY_SCALE: u16 = 0xc65c;
+    pub const RAW_DATA_UNIQUE_ID: u16 = 0xc65d;
+    pub const ORIGINAL_RAW_FILE_NAME: u16 = 0xc68b;
+    pub const ACTIVE_AREA: u16 = 0xc68d;
+    pub const FORWARD_MATRIX_1: u16 = 0xc714;
+    pub const FORWARD_MATRIX_2: u16 = 0xc715;
+}
+
+pub mod olympus {
+    /// MakerNote tag pointing at the CameraSettings IFD
+    pub const CAMERA_SETTINGS: u16 = 0x2020;
+
+    // within CameraSettings, offsets are relative to the MakerNote
+    pub const PREVIEW_IMAGE_VALID: u16 = 0x0100;
+    pub const PREVIEW_IMAGE_START: u16 = 0x0101;
+    pub const PREVIEW_IMAGE_LENGTH: u16 = 0x0102;
+}
+
+/// RW2 IFD0 tags, which take the place of most baseline TIFF tags
+pub mod panasonic {
+    pub const PANASONIC_RAW_VERSION: u16 = 0x0001;
+    pub const SENSOR_WIDTH: u16 = 0x0002;
+    pub const SENSOR_HEIGHT: u16 = 0x0003;
+    pub const SENSOR_TOP_BORDER: u16 = 0x0004;
+    pub const SENSOR_LEFT_BORDER: u16 = 0x0005;
+    pub const SENSOR_BOTTOM_BORDER: u16 = 0x0006;
+    pub const SENSOR_RIGHT_BORDER: u16 = 0x0007;
+    pub const SAMPLES_PER_PIXEL: u16 = 0x0008;
+    pub const CFA_PATTERN: u16 = 0x0009;
+    pub const BITS_PER_SAMPLE: u16 = 0x000a;
+    pub const COMPRESSION: u16 = 0x000b;
+    pub const LINEARITY_LIMIT_RED: u16 = 0x000e;
+    pub const LINEARITY_LIMIT_GREEN: u16 = 0x000f;
+    pub const LINEARITY_LIMIT_BLUE: u16 = 0x0010;
+    pub const RED_BALANCE: u16 = 0x0011;
+    pub const BLUE_BALANCE: u16 = 0x0012;
+    pub const ISO: u16 = 0x0017;
+    pub const BLACK_LEVEL_RED: u16 = 0x001c;
+    pub const BLACK_LEVEL_GREEN: u16 = 0x001d;
+    pub const BLACK_LEVEL_BLUE: u16 = 0x001e;
+    pub const WB_RED_LEVEL: u16 = 0x0024;
+    pub const WB_GREEN_LEVEL: u16 = 0x0025;
+    pub const WB_BLUE_LEVEL: u16 = 0x0026;
+    /// an entire jpeg preview, whose exif holds the exif IFD and MakerNote
+    pub const JPG_FROM_RAW: u16 = 0x002e;
+    pub const CROP_TOP: u16 = 0x002f;
+    pub const CROP_LEFT: u16 = 0x0030;
+    pub const CROP_BOTTOM: u16 = 0x0031;
+    pub const CROP_RIGHT: u16 = 0x0032;
+    pub const RAW_DATA_OFFSET: u16 = 0x0118;
+}
+
+pub mod pentax {
+    pub const PREVIEW_IMAGE_LENGTH: u16 = 0x0003;
+    pub const PREVIEW_IMAGE_START: u16 = 0x0004;
+}
@@ -0,0 +1,271 @@
+// typed values shared between exif and other metadata sources, such as
+// QuickTime, which store the same information in different forms
+
+use std::fmt;
+
+/// a calendar date and time of day
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// offset from UTC in minutes, if known
+    pub offset: Option<i16>,
+}
+
+/// a position in degrees, positive north and east, with altitude in metres
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+impl DateTime {
+    /// parse the exif form, "YYYY:MM:DD HH:MM:SS". blank or zeroed fields,
+    /// used by some cameras to mean "unknown", are rejected.
+    pub fn from_exif(s: &str) -> Option<Self> {
+        let b = s.trim_end_matches('\0').as_bytes();
+        if b.len() < 19 || b[4] != b':' || b[7] != b':' || b[10] != b' ' || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        Self::from_fields(s, None)
+    }
+
+    /// parse ISO 8601 forms such as "2019-05-04T10:11:12-0700", as used by QuickTime.
+    /// the time and offset are optional.
+    pub fn from_iso8601(s: &str) -> Option<Self> {
+        // fields are sliced by byte position
+        if !s.is_ascii() {
+            return None;
+        }
+        let b = s.as_bytes();
+        if b.len() < 10 || b[4] != b'-' || b[7] != b'-' {
+            return None;
+        }
+        if b.len() == 10 {
+            let mut s = s.to_string();
+            s.push_str("T00:00:00");
+            return Self::from_fields(&s, None);
+        }
+        if b.len() < 19 || (b[10] != b'T' && b[10] != b' ') || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+
+        // skip fractional seconds
+        let mut rest = &s[19..];
+        if rest.starts_with('.') {
+            rest = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+        }
+        let offset = match rest {
+            "" => None,
+            "Z" => Some(0),
+            _ => Some(parse_offset(rest)?),
+        };
+        Self::from_fields(s, offset)
+    }
+
+    /// parse an exif OffsetTime* value, "+HH:MM", into minutes
+    pub fn parse_offset(s: &str) -> Option<i16> {
+        parse_offset(s.trim_end_matches('\0'))
+    }
+
+    /// the exif form, "YYYY:MM:DD HH:MM:SS"
+    pub fn to_exif(&self) -> String {
+        format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+
+    /// the offset in the exif OffsetTime* form, "+HH:MM"
+    pub fn exif_offset(&self) -> Option<String> {
+        self.offset.map(|o| format!("{}{:02}:{:02}", if o < 0 { '-' } else { '+' }, o.abs() / 60, o.abs() % 60))
+    }
+
+    /// a time in seconds since 1904-01-01 00:00:00 UTC, as used by QuickTime and ISO base media files
+    pub fn from_mac_epoch(secs: u64) -> Self {
+        // days from 1904-01-01 to 1970-01-01
+        const EPOCH_DELTA: i64 = 24107;
+        let days = (secs / 86400) as i64 - EPOCH_DELTA;
+        let rem = secs % 86400;
+
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = if z >= 0 { z } else { z - 146096 } / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime{
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+            offset: Some(0),
+        }
+    }
+
+    // fixed position fields, "YYYY?MM?DD?HH?MM?SS"
+    fn from_fields(s: &str, offset: Option<i16>) -> Option<Self> {
+        let field = |start: usize, len: usize| s.get(start..start + len).and_then(|f| f.parse::<u16>().ok());
+        let d = DateTime{
+            year: field(0, 4)?,
+            month: field(5, 2)? as u8,
+            day: field(8, 2)? as u8,
+            hour: field(11, 2)? as u8,
+            minute: field(14, 2)? as u8,
+            second: field(17, 2)? as u8,
+            offset: offset,
+        };
+        if d.year == 0 || d.month == 0 || d.month > 12 || d.day == 0 || d.day > 31 || d.hour > 23 || d.minute > 59 || d.second > 60 {
+            return None;
+        }
+        Some(d)
+    }
+}
+
+// "+HH:MM", "+HHMM" or "+HH"
+fn parse_offset(s: &str) -> Option<i16> {
+    if !s.is_ascii() {
+        return None;
+    }
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = s[1..].replace(':', "");
+    if digits.len() != 2 && digits.len() != 4 {
+        return None;
+    }
+    let h = digits[..2].parse::<i16>().ok()?;
+    let m = if digits.len() == 4 { digits[2..].parse::<i16>().ok()? } else { 0 };
+    if h > 23 || m > 59 {
+        return None;
+    }
+    Some(sign * (h * 60 + m))
+}
+
+impl fmt::Display for DateTime {
+    /// ISO 8601
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second));
+        match self.offset {
+            Some(0) => write!(f, "Z"),
+            Some(o) => write!(f, "{}{:02}:{:02}", if o < 0 { '-' } else { '+' }, o.abs() / 60, o.abs() % 60),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GpsPosition {
+    /// parse an ISO 6709 string such as "+37.3318-122.0312+010.000/", as
+    /// used by QuickTime. degrees may also be given as DDMM.M or DDMMSS.S.
+    pub fn from_iso6709(s: &str) -> Option<Self> {
+        let s = s.trim_end_matches('\0');
+        let s = s.split("CRS").next()?.trim_end_matches('/');
+
+        // split into signed components
+        let mut parts = vec![];
+        for (i, c) in s.char_indices() {
+            if c == '+' || c == '-' {
+                parts.push(i);
+            }
+        }
+        if parts.len() < 2 || parts[0] != 0 {
+            return None;
+        }
+        parts.push(s.len());
+        let part = |n: usize| &s[parts[n]..parts[n + 1]];
+
+        let latitude = iso6709_degrees(part(0), 2)?;
+        let longitude = iso6709_degrees(part(1), 3)?;
+        let altitude = if parts.len() > 3 { Some(part(2).parse::<f64>().ok()?) } else { None };
+        if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+            return None;
+        }
+        Some(GpsPosition{ latitude: latitude, longitude: longitude, altitude: altitude })
+    }
+
+    /// combine exif GPS components: degrees, minutes and seconds, with a
+    /// reference of "N"/"S" or "E"/"W" giving the sign
+    pub fn degrees_from_dms(dms: &[f64], reference: &str) -> Option<f64> {
+        if dms.is_empty() || dms.len() > 3 {
+            return None;
+        }
+        let d = dms.iter().zip(&[1.0, 60.0, 3600.0]).fold(0.0, |acc, (v, div)| acc + v / div);
+        match reference.trim_end_matches('\0') {
+            "N" | "E" => Some(d),
+            "S" | "W" => Some(-d),
+            _ => None,
+        }
+    }
+}
+
+// a signed component with `width` integer digits of degrees, followed by
+// optional minutes and seconds digits
+fn iso6709_degrees(s: &str, width: usize) -> Option<f64> {
+    let (sign, digits) = match s.split_at(1) {
+        ("+", d) => (1.0, d),
+        ("-", d) => (-1.0, d),
+        _ => return None,
+    };
+    let int_len = digits.find('.').unwrap_or(digits.len());
+    let v = digits.parse::<f64>().ok()?;
+    let deg = match int_len.checked_sub(width)? {
+        0 => v,
+        2 => (v / 100.0).trunc() + (v % 100.0) / 60.0,
+        4 => (v / 10000.0).trunc() + ((v / 100.0).trunc() % 100.0) / 60.0 + (v % 100.0) / 3600.0,
+        _ => return None,
+    };
+    Some(sign * deg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dates() {
+        let d = DateTime::from_exif("2018:09:05 10:11:12\0").expect("exif date");
+        assert_eq!((d.year, d.month, d.day, d.hour, d.minute, d.second, d.offset), (2018, 9, 5, 10, 11, 12, None));
+        assert!(DateTime::from_exif("0000:00:00 00:00:00").is_none());
+        assert!(DateTime::from_exif("    :  :     :  :  ").is_none());
+
+        let d = DateTime::from_iso8601("2019-05-04T10:11:12-0700").expect("iso date");
+        assert_eq!(d.offset, Some(-420));
+        assert_eq!(d.to_string(), "2019-05-04T10:11:12-07:00");
+        assert_eq!(DateTime::from_iso8601("2019-05-04T10:11:12.345Z").expect("fractional").offset, Some(0));
+        assert_eq!(DateTime::parse_offset("+05:30"), Some(330));
+
+        // multibyte characters where single byte fields are expected
+        assert_eq!(DateTime::parse_offset("+0\u{e9}0"), None);
+        assert!(DateTime::from_iso8601("2019-05-04T10:11:12+0\u{e9}0").is_none());
+        assert!(DateTime::from_iso8601("2019-05-04T10:11:1\u{e9}").is_none());
+
+        // 2001-01-01 00:00:00, 3061152000 seconds after the mac epoch
+        assert_eq!(DateTime::from_mac_epoch(3061152000).to_string(), "2001-01-01T00:00:00Z");
+        assert_eq!(DateTime::from_mac_epoch(3061152000 - 1).to_string(), "2000-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn parse_positions() {
+        let p = GpsPosition::from_iso6709("+37.3318-122.0312+010.000/").expect("decimal");
+        assert_eq!((p.latitude, p.longitude, p.altitude), (37.3318, -122.0312, Some(10.0)));
+
+        let p = GpsPosition::from_iso6709("+3719.908-12201.872/").expect("minutes");
+        assert!((p.latitude - 37.33180).abs() < 1e-5);
+        assert!((p.longitude + 122.03120).abs() < 1e-5);
+        assert_eq!(p.altitude, None);
+
+        assert!(GpsPosition::from_iso6709("37.3318/").is_none());
+        assert_eq!(GpsPosition::degrees_from_dms(&[37.0, 19.0, 54.48], "S"), Some(-(37.0 + 19.0 / 60.0 + 54.48 / 3600.0)));
+    }
+}
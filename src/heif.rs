@@ -0,0 +1,1045 @@
+// ISO/IEC 23008-12 (HEIF), as used by HEIC and AVIF files
+// https://nokiatech.github.io/heif/technical.html
+//
+// metadata is stored as items in the top level meta box: iinf describes each
+// item, iloc gives the location of its data, either in the file (usually
+// within mdat) or in the idat box inside meta.
+//
+// when rewriting, only meta is rebuilt. every other box is copied as is, and
+// new item data is appended in a separate mdat, so the coded image data is
+// never touched beyond moving it along by the change in size of meta.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+use bmff;
+use bmff::{BoxHeader, FourCC};
+use exif::Exif;
+
+pub const XMP_CONTENT_TYPE: &str = "application/rdf+xml";
+
+/// brands identifying HEIF based files
+const BRANDS: &[&FourCC] = &[b"mif1", b"msf1", b"heic", b"heix", b"hevc", b"hevx", b"avif", b"avis"];
+
+/// iloc construction methods
+pub const FILE_OFFSET: u8 = 0;
+pub const IDAT_OFFSET: u8 = 1;
+pub const ITEM_OFFSET: u8 = 2;
+
+/// an entry of the iinf box
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemInfo {
+    pub id: u32,
+    /// e.g. "hvc1", "av01", "Exif" or "mime"
+    pub kind: FourCC,
+    pub name: String,
+    /// for "mime" items
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+}
+
+/// a contiguous piece of an item's data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extent {
+    pub index: u64,
+    pub offset: u64,
+    /// 0 means the rest of the file, or the rest of idat
+    pub length: u64,
+}
+
+/// an entry of the iloc box
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub item_id: u32,
+    pub construction_method: u8,
+    pub data_reference_index: u16,
+    pub base_offset: u64,
+    pub extents: Vec<Extent>,
+}
+
+/// field sizes and version of an iloc box, retained so that it can be rewritten in kind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocationSizes {
+    pub version: u8,
+    pub offset_size: u8,
+    pub length_size: u8,
+    pub base_offset_size: u8,
+    pub index_size: u8,
+}
+
+/// an entry of the iref box: `from` refers to each of `to`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// reference type, e.g. "cdsc" for metadata describing an image
+    pub kind: FourCC,
+    pub from: u32,
+    pub to: Vec<u32>,
+}
+
+/// a change to make to a metadata item when rewriting a file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change<T> {
+    Keep,
+    Remove,
+    Set(T),
+}
+
+/// top level data structure representing the metadata of a HEIF file
+#[derive(Clone, Debug)]
+pub struct Heif {
+    pub brands: Vec<FourCC>,
+    /// top level boxes
+    pub boxes: Vec<BoxHeader>,
+    pub meta: BoxHeader,
+    pub primary_item: Option<u32>,
+    pub items: Vec<ItemInfo>,
+    pub locations: Vec<Location>,
+    pub location_sizes: LocationSizes,
+    pub references: Vec<Reference>,
+    pub idat: Option<BoxHeader>,
+    /// length of the file
+    pub len: u64,
+}
+
+/// return true if the reader appears to contain a HEIF file
+pub fn is_heif<R: Read + Seek>(rdr: &mut R) -> bool {
+    let boxes = match bmff::read_file(rdr) {
+        Ok(b) => b,
+        Err(_) => return false,
+    };
+    match bmff::find(&boxes, b"ftyp").map(|ftyp| bmff::read_brands(rdr, ftyp)) {
+        Some(Ok(brands)) => brands.iter().any(|b| BRANDS.contains(&b)),
+        _ => false,
+    }
+}
+
+impl Heif {
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let len = try!(rdr.seek(SeekFrom::End(0)));
+        let boxes = try!(bmff::read_boxes(rdr, 0, len));
+        let brands = match bmff::find(&boxes, b"ftyp") {
+            Some(ftyp) => try!(bmff::read_brands(rdr, ftyp)),
+            None => return Err(invalid("missing ftyp box")),
+        };
+        if !brands.iter().any(|b| BRANDS.contains(&b)) {
+            return Err(invalid("not a HEIF file"));
+        }
+
+        let meta = try!(bmff::find(&boxes, b"meta").cloned().ok_or(invalid("missing meta box")));
+        let children = try!(meta.children(rdr, 4));
+
+        let primary_item = match bmff::find(&children, b"pitm") {
+            Some(b) => Some(try!(read_pitm(rdr, b))),
+            None => None,
+        };
+        let items = match bmff::find(&children, b"iinf") {
+            Some(b) => try!(read_iinf(rdr, b)),
+            None => vec![],
+        };
+        let (locations, location_sizes) = match bmff::find(&children, b"iloc") {
+            Some(b) => try!(read_iloc(rdr, b)),
+            None => (vec![], LocationSizes{ version: 0, offset_size: 4, length_size: 4, base_offset_size: 0, index_size: 0 }),
+        };
+        let references = match bmff::find(&children, b"iref") {
+            Some(b) => try!(read_iref(rdr, b)),
+            None => vec![],
+        };
+
+        Ok(Heif{
+            brands: brands,
+            idat: bmff::find(&children, b"idat").cloned(),
+            boxes: boxes,
+            meta: meta,
+            primary_item: primary_item,
+            items: items,
+            locations: locations,
+            location_sizes: location_sizes,
+            references: references,
+            len: len,
+        })
+    }
+
+    pub fn item(&self, id: u32) -> Option<&ItemInfo> {
+        self.items.iter().find(|i| i.id == id)
+    }
+
+    pub fn location(&self, id: u32) -> Option<&Location> {
+        self.locations.iter().find(|l| l.item_id == id)
+    }
+
+    /// the first item of type "Exif"
+    pub fn exif_item(&self) -> Option<&ItemInfo> {
+        self.items.iter().find(|i| &i.kind == b"Exif")
+    }
+
+    /// the first "mime" item holding XMP
+    pub fn xmp_item(&self) -> Option<&ItemInfo> {
+        self.items.iter().find(|i| &i.kind == b"mime" && i.content_type.as_ref().map(|s| &s[..]) == Some(XMP_CONTENT_TYPE))
+    }
+
+    /// read the data of an item, concatenating its extents
+    pub fn read_item<R: Read + Seek>(&self, rdr: &mut R, id: u32) -> io::Result<Vec<u8>> {
+        let loc = try!(self.location(id).ok_or(invalid("item has no location")));
+        if loc.data_reference_index != 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "item data in an external file"));
+        }
+        let (start, end) = match loc.construction_method {
+            FILE_OFFSET => (0, self.len),
+            IDAT_OFFSET => match self.idat {
+                Some(ref b) => (b.data_offset(), b.end()),
+                None => return Err(invalid("item refers to missing idat box")),
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported iloc construction method")),
+        };
+
+        let mut data = vec![];
+        for e in &loc.extents {
+            let offset = try!(start.checked_add(loc.base_offset).and_then(|o| o.checked_add(e.offset))
+                              .ok_or(invalid("item extent out of bounds")));
+            let length = if e.length == 0 { end.saturating_sub(offset) } else { e.length };
+            if offset > end || length > end - offset {
+                return Err(invalid("item extent out of bounds"));
+            }
+            let n = data.len();
+            data.resize(n + length as usize, 0);
+            try!(rdr.seek(SeekFrom::Start(offset)));
+            try!(rdr.read_exact(&mut data[n..]));
+        }
+        Ok(data)
+    }
+
+    /// decode the Exif item, if present
+    pub fn exif<R: Read + Seek>(&self, rdr: &mut R) -> Option<io::Result<Exif>> {
+        let id = self.exif_item()?.id;
+        Some(self.read_item(rdr, id).and_then(|d| {
+            let tiff = try!(exif_tiff(&d));
+            Exif::new(&mut Cursor::new(tiff))
+        }))
+    }
+
+    /// the raw XMP packet, if present
+    pub fn xmp<R: Read + Seek>(&self, rdr: &mut R) -> Option<io::Result<Vec<u8>>> {
+        let id = self.xmp_item()?.id;
+        Some(self.read_item(rdr, id))
+    }
+
+    /// the ICC profile from the colr property of the primary item, if present
+    pub fn icc_profile<R: Read + Seek>(&self, rdr: &mut R) -> Option<io::Result<Vec<u8>>> {
+        self.read_icc_profile(rdr).transpose()
+    }
+
+    fn read_icc_profile<R: Read + Seek>(&self, rdr: &mut R) -> io::Result<Option<Vec<u8>>> {
+        let primary = match self.primary_item {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let children = try!(self.meta.children(rdr, 4));
+        let iprp = match bmff::find(&children, b"iprp") {
+            Some(b) => try!(b.children(rdr, 0)),
+            None => return Ok(None),
+        };
+        let (ipco, ipma) = match (bmff::find(&iprp, b"ipco"), bmff::find(&iprp, b"ipma")) {
+            (Some(c), Some(a)) => (try!(c.children(rdr, 0)), try!(read_ipma(rdr, a))),
+            _ => return Ok(None),
+        };
+
+        // property indices are 1-based, 0 meaning none
+        let props = ipma.into_iter().find(|p| p.0 == primary).map(|p| p.1).unwrap_or_default();
+        for i in props {
+            let b = match i.checked_sub(1).and_then(|i| ipco.get(i as usize)) {
+                Some(b) if &b.kind == b"colr" => b,
+                _ => continue,
+            };
+            let d = try!(b.read_data(rdr));
+            if d.len() > 4 && (&d[..4] == b"prof" || &d[..4] == b"rICC") {
+                return Ok(Some(d[4..].to_vec()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// the parts of the given [start, end) ranges not covered by any of `keep`
+fn subtract_ranges(ranges: Vec<(u64, u64)>, keep: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut out = ranges;
+    for &(ks, ke) in keep {
+        let mut next = vec![];
+        for (s, e) in out {
+            if ke <= s || ks >= e {
+                next.push((s, e));
+                continue;
+            }
+            if s < ks { next.push((s, ks)); }
+            if ke < e { next.push((ke, e)); }
+        }
+        out = next;
+    }
+    out
+}
+
+// the items affected by a rewrite
+struct Plan {
+    /// items to remove entirely
+    removed: Vec<u32>,
+    /// new items, described in iinf and referring to the primary item
+    added: Vec<ItemInfo>,
+    /// new data for added and replaced items, to be stored in the appended mdat
+    data: Vec<(u32, Vec<u8>)>,
+}
+
+impl Plan {
+    fn relocated(&self, id: u32) -> bool {
+        self.removed.contains(&id) || self.data.iter().any(|&(i, _)| i == id)
+    }
+}
+
+impl Heif {
+    /// write a copy of the file with the Exif and XMP items changed. all other
+    /// data is copied unmodified, except the old data of removed or replaced
+    /// items, which is zeroed so that e.g. removed GPS data doesn't linger.
+    /// files with a moov box, such as image sequences, are not supported.
+    pub fn rewrite<R: Read + Seek, W: Write>(&self, rdr: &mut R, w: &mut W, exif: Change<&Exif>, xmp: Change<&[u8]>) -> io::Result<()> {
+        // track sample offsets in moov would be invalidated by any change in the size of meta
+        if bmff::find(&self.boxes, b"moov").is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "unable to rewrite a file containing a moov box"));
+        }
+        let exif = match exif {
+            Change::Keep => Change::Keep,
+            Change::Remove => Change::Remove,
+            Change::Set(e) => Change::Set(try!(encode_exif_item(e))),
+        };
+        let xmp = match xmp {
+            Change::Keep => Change::Keep,
+            Change::Remove => Change::Remove,
+            Change::Set(x) => Change::Set(x.to_vec()),
+        };
+
+        let mut plan = Plan{ removed: vec![], added: vec![], data: vec![] };
+        let ids = self.items.iter().map(|i| i.id).chain(self.locations.iter().map(|l| l.item_id));
+        let mut next_id = ids.max().unwrap_or(0) + 1;
+        let exif_info = ItemInfo{ id: 0, kind: *b"Exif", name: String::new(), content_type: None, content_encoding: None };
+        let xmp_info = ItemInfo{ id: 0, kind: *b"mime", name: String::new(), content_type: Some(XMP_CONTENT_TYPE.to_string()), content_encoding: None };
+        plan_item(&mut plan, self.exif_item().map(|i| i.id), exif, exif_info, &mut next_id);
+        plan_item(&mut plan, self.xmp_item().map(|i| i.id), xmp, xmp_info, &mut next_id);
+
+        // extents may be shared between items, only zero those no remaining item refers to
+        let (mut zeroed, mut kept) = (vec![], vec![]);
+        for l in &self.locations {
+            let ranges = try!(self.item_ranges(l));
+            if plan.relocated(l.item_id) { zeroed.extend(ranges) } else { kept.extend(ranges) }
+        }
+        let zeroed = subtract_ranges(zeroed, &kept);
+
+        // the iloc offsets depend on the size of meta, which may in turn depend
+        // on the size of the offsets, so repeat until the size is stable
+        let data_len = plan.data.iter().fold(0, |n, d| n + d.1.len() as u64);
+        let mdat_header = if data_len + 8 > 0xFFFF_FFFF { 16 } else { 8 };
+        let old_end = self.boxes.last().map(|b| b.end()).unwrap_or(0);
+        let mut delta = 0i64;
+        let mut meta = None;
+        for _ in 0..4 {
+            let data_start = (old_end as i64 + delta) as u64 + mdat_header;
+            let locations = try!(self.new_locations(&plan, delta, data_start));
+            let m = try!(self.build_meta(rdr, &plan, &locations, &zeroed));
+            let d = m.len() as i64 - self.meta.size as i64;
+            if d == delta {
+                meta = Some(m);
+                break;
+            }
+            delta = d;
+        }
+        let meta = try!(meta.ok_or(invalid("unable to lay out meta box")));
+
+        let appending = !plan.data.is_empty();
+        for (i, b) in self.boxes.iter().enumerate() {
+            if b.offset == self.meta.offset {
+                try!(w.write_all(&meta));
+                continue;
+            }
+
+            // a last box with size 0 extends to the end of the file, which is about to move
+            let mut start = b.offset;
+            if appending && i == self.boxes.len() - 1 {
+                try!(rdr.seek(SeekFrom::Start(b.offset)));
+                if try!(rdr.read_u32::<BigEndian>()) == 0 {
+                    if b.size > 0xFFFF_FFFF || b.header_len != 8 {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "unable to append to an unsized box"));
+                    }
+                    try!(w.write_u32::<BigEndian>(b.size as u32));
+                    start += 4;
+                }
+            }
+            try!(copy_range(rdr, w, start, b.end(), &zeroed));
+        }
+
+        if appending {
+            let mut mdat = Vec::with_capacity(data_len as usize);
+            for (_, d) in &plan.data {
+                mdat.extend_from_slice(d);
+            }
+            try!(bmff::write_box(w, b"mdat", &mdat));
+        }
+        Ok(())
+    }
+
+    // absolute [start, end) ranges within the file of the data of an item
+    fn item_ranges(&self, l: &Location) -> io::Result<Vec<(u64, u64)>> {
+        let (start, end) = match (l.construction_method, &self.idat) {
+            (FILE_OFFSET, _) => (0, self.len),
+            (IDAT_OFFSET, Some(b)) => (b.data_offset(), b.end()),
+            _ => return Ok(vec![]),
+        };
+        let mut v = vec![];
+        for e in &l.extents {
+            let o = try!(start.checked_add(l.base_offset).and_then(|o| o.checked_add(e.offset))
+                         .ok_or(invalid("item extent out of bounds")));
+            let len = if e.length == 0 { end.saturating_sub(o) } else { e.length };
+            v.push((o, o.saturating_add(len)));
+        }
+        Ok(v)
+    }
+
+    // the iloc entries after rewriting, with file offsets moved by the change in size of meta
+    fn new_locations(&self, plan: &Plan, delta: i64, data_start: u64) -> io::Result<Vec<Location>> {
+        let shift = |abs: u64| -> io::Result<u64> {
+            if abs < self.meta.offset {
+                Ok(abs)
+            } else if abs >= self.meta.end() {
+                Ok((abs as i64 + delta) as u64)
+            } else {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "item data within the meta box"))
+            }
+        };
+
+        let mut locations = vec![];
+        for l in self.locations.iter().filter(|l| !plan.relocated(l.item_id)) {
+            if l.construction_method != FILE_OFFSET || l.data_reference_index != 0 {
+                locations.push(l.clone());
+                continue;
+            }
+
+            let ranges = try!(self.item_ranges(l));
+            let mut moved = Vec::with_capacity(ranges.len());
+            for &(start, end) in &ranges {
+                moved.push((try!(shift(start)), end - start));
+            }
+            let mut base = if l.base_offset == 0 { 0 } else { shift(l.base_offset).unwrap_or(0) };
+            if moved.iter().any(|&(o, _)| o < base) {
+                base = 0;
+            }
+
+            // explicit lengths, as the end of the file is about to change
+            let extents = l.extents.iter().zip(moved).map(|(e, (o, len))| Extent{ index: e.index, offset: o - base, length: len }).collect();
+            locations.push(Location{ base_offset: base, extents: extents, ..l.clone() });
+        }
+
+        let mut pos = data_start;
+        for &(id, ref d) in &plan.data {
+            locations.push(Location{
+                item_id: id,
+                construction_method: FILE_OFFSET,
+                data_reference_index: 0,
+                base_offset: 0,
+                extents: vec![Extent{ index: 0, offset: pos, length: d.len() as u64 }],
+            });
+            pos += d.len() as u64;
+        }
+        Ok(locations)
+    }
+
+    fn new_references(&self, plan: &Plan) -> Vec<Reference> {
+        let mut refs = vec![];
+        for r in self.references.iter().filter(|r| !plan.removed.contains(&r.from)) {
+            let to = r.to.iter().cloned().filter(|id| !plan.removed.contains(id)).collect::<Vec<_>>();
+            if !to.is_empty() {
+                refs.push(Reference{ to: to, ..r.clone() });
+            }
+        }
+        if let Some(primary) = self.primary_item {
+            for i in &plan.added {
+                refs.push(Reference{ kind: *b"cdsc", from: i.id, to: vec![primary] });
+            }
+        }
+        refs
+    }
+
+    fn build_meta<R: Read + Seek>(&self, rdr: &mut R, plan: &Plan, locations: &[Location], zeroed: &[(u64, u64)]) -> io::Result<Vec<u8>> {
+        try!(rdr.seek(SeekFrom::Start(self.meta.data_offset())));
+        let (version, flags) = try!(bmff::read_full_box(rdr));
+        let references = self.new_references(plan);
+
+        let mut payload = vec![];
+        let (mut iinf, mut iloc, mut iref) = (false, false, false);
+        for c in try!(self.meta.children(rdr, 4)) {
+            match &c.kind {
+                b"iinf" => { iinf = true; try!(self.write_iinf(rdr, &mut payload, Some(&c), plan)); }
+                b"iloc" => { iloc = true; try!(self.write_iloc(&mut payload, locations)); }
+                b"iref" => { iref = true; try!(write_iref(&mut payload, &references)); }
+                b"iprp" if !plan.removed.is_empty() => try!(write_iprp(rdr, &mut payload, &c, &plan.removed)),
+                _ => try!(copy_range(rdr, &mut payload, c.offset, c.end(), zeroed)),
+            }
+        }
+        if !iinf && !plan.added.is_empty() {
+            try!(self.write_iinf(rdr, &mut payload, None, plan));
+        }
+        if !iloc && !locations.is_empty() {
+            try!(self.write_iloc(&mut payload, locations));
+        }
+        if !iref {
+            try!(write_iref(&mut payload, &references));
+        }
+
+        let mut meta = vec![];
+        try!(bmff::write_full_box(&mut meta, b"meta", version, flags, &payload));
+        Ok(meta)
+    }
+
+    fn write_iinf<R: Read + Seek>(&self, rdr: &mut R, w: &mut Vec<u8>, b: Option<&BoxHeader>, plan: &Plan) -> io::Result<()> {
+        let mut entries = vec![];
+        let mut count = 0u64;
+        let (mut version, mut flags) = (0, 0);
+
+        // existing entries are copied as is
+        if let Some(b) = b {
+            try!(rdr.seek(SeekFrom::Start(b.data_offset())));
+            let (v, f) = try!(bmff::read_full_box(rdr));
+            version = v;
+            flags = f;
+            for infe in try!(b.children(rdr, 4 + if v == 0 { 2 } else { 4 })) {
+                if &infe.kind == b"infe" && plan.removed.contains(&try!(read_infe(&try!(infe.read_data(rdr)))).id) {
+                    continue;
+                }
+                try!(copy_range(rdr, &mut entries, infe.offset, infe.end(), &[]));
+                count += 1;
+            }
+        }
+        for i in &plan.added {
+            try!(write_infe(&mut entries, i));
+            count += 1;
+        }
+
+        if count > 0xFFFF {
+            version = 1;
+        }
+        let mut payload = vec![];
+        try!(bmff::write_uint(&mut payload, if version == 0 { 2 } else { 4 }, count));
+        payload.extend(entries);
+        bmff::write_full_box(w, b"iinf", version, flags, &payload)
+    }
+
+    fn write_iloc(&self, w: &mut Vec<u8>, locations: &[Location]) -> io::Result<()> {
+        let need = |v: u64| if v == 0 { 0 } else if v <= 0xFFFF_FFFF { 4 } else { 8 };
+        let mut s = self.location_sizes;
+        if locations.iter().any(|l| l.item_id > 0xFFFF) {
+            s.version = 2;
+        } else if s.version == 0 && locations.iter().any(|l| l.construction_method != FILE_OFFSET) {
+            s.version = 1;
+        }
+        for l in locations {
+            s.base_offset_size = s.base_offset_size.max(need(l.base_offset));
+            for e in &l.extents {
+                s.offset_size = s.offset_size.max(need(e.offset));
+                s.length_size = s.length_size.max(need(e.length));
+            }
+        }
+        if s.version == 0 {
+            s.index_size = 0;
+        }
+
+        let id_size = if s.version < 2 { 2 } else { 4 };
+        let mut p = vec![];
+        try!(p.write_u16::<BigEndian>((s.offset_size as u16) << 12 | (s.length_size as u16) << 8 |
+                                      (s.base_offset_size as u16) << 4 | s.index_size as u16));
+        try!(bmff::write_uint(&mut p, id_size, locations.len() as u64));
+        for l in locations {
+            try!(bmff::write_uint(&mut p, id_size, l.item_id as u64));
+            if s.version > 0 {
+                try!(p.write_u16::<BigEndian>(l.construction_method as u16));
+            }
+            try!(p.write_u16::<BigEndian>(l.data_reference_index));
+            try!(bmff::write_uint(&mut p, s.base_offset_size, l.base_offset));
+            try!(p.write_u16::<BigEndian>(l.extents.len() as u16));
+            for e in &l.extents {
+                try!(bmff::write_uint(&mut p, s.index_size, e.index));
+                try!(bmff::write_uint(&mut p, s.offset_size, e.offset));
+                try!(bmff::write_uint(&mut p, s.length_size, e.length));
+            }
+        }
+        bmff::write_full_box(w, b"iloc", s.version, 0, &p)
+    }
+}
+
+fn plan_item(plan: &mut Plan, existing: Option<u32>, change: Change<Vec<u8>>, info: ItemInfo, next_id: &mut u32) {
+    match change {
+        Change::Keep => {}
+        Change::Remove => plan.removed.extend(existing),
+        Change::Set(d) => {
+            let id = match existing {
+                Some(id) => id,
+                None => {
+                    let id = *next_id;
+                    *next_id += 1;
+                    plan.added.push(ItemInfo{ id: id, ..info });
+                    id
+                }
+            };
+            plan.data.push((id, d));
+        }
+    }
+}
+
+/// the data of an Exif item: the offset of the TIFF header, then "Exif\0\0" and the TIFF structure
+pub fn encode_exif_item(e: &Exif) -> io::Result<Vec<u8>> {
+    let mut c = Cursor::new(vec![0, 0, 0, 6, b'E', b'x', b'i', b'f', 0, 0]);
+    c.set_position(10);
+    try!(e.encode(&mut c));
+    Ok(c.into_inner())
+}
+
+// copy [start, end) from rdr to w, replacing the bytes within any of the zeroed ranges with 0
+fn copy_range<R: Read + Seek, W: Write>(rdr: &mut R, w: &mut W, start: u64, end: u64, zeroed: &[(u64, u64)]) -> io::Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pos = start;
+    try!(rdr.seek(SeekFrom::Start(start)));
+    while pos < end {
+        let n = ::std::cmp::min(buf.len() as u64, end - pos) as usize;
+        try!(rdr.read_exact(&mut buf[..n]));
+        for &(zs, ze) in zeroed {
+            let (s, e) = (zs.max(pos), ze.min(pos + n as u64));
+            if s < e {
+                for b in &mut buf[(s - pos) as usize..(e - pos) as usize] {
+                    *b = 0;
+                }
+            }
+        }
+        try!(w.write_all(&buf[..n]));
+        pos += n as u64;
+    }
+    Ok(())
+}
+
+fn write_infe<W: Write>(w: &mut W, i: &ItemInfo) -> io::Result<()> {
+    let version = if i.id > 0xFFFF { 3 } else { 2 };
+    let mut p = vec![];
+    try!(bmff::write_uint(&mut p, if version == 3 { 4 } else { 2 }, i.id as u64));
+    try!(p.write_u16::<BigEndian>(0)); // protection index
+    p.extend_from_slice(&i.kind);
+    p.extend_from_slice(i.name.as_bytes());
+    p.push(0);
+    if &i.kind == b"mime" {
+        p.extend_from_slice(i.content_type.as_ref().map(|s| s.as_bytes()).unwrap_or(b""));
+        p.push(0);
+        if let Some(ref e) = i.content_encoding {
+            p.extend_from_slice(e.as_bytes());
+            p.push(0);
+        }
+    }
+    bmff::write_full_box(w, b"infe", version, 0, &p)
+}
+
+fn write_iref<W: Write>(w: &mut W, refs: &[Reference]) -> io::Result<()> {
+    if refs.is_empty() {
+        return Ok(());
+    }
+    let large = refs.iter().any(|r| r.from > 0xFFFF || r.to.iter().any(|&id| id > 0xFFFF));
+    let id_size = if large { 4 } else { 2 };
+
+    let mut p = vec![];
+    for r in refs {
+        let mut d = vec![];
+        try!(bmff::write_uint(&mut d, id_size, r.from as u64));
+        try!(d.write_u16::<BigEndian>(r.to.len() as u16));
+        for &id in &r.to {
+            try!(bmff::write_uint(&mut d, id_size, id as u64));
+        }
+        try!(bmff::write_box(&mut p, &r.kind, &d));
+    }
+    bmff::write_full_box(w, b"iref", large as u8, 0, &p)
+}
+
+// copy iprp, dropping property associations of removed items from ipma
+fn write_iprp<R: Read + Seek, W: Write>(rdr: &mut R, w: &mut W, iprp: &BoxHeader, removed: &[u32]) -> io::Result<()> {
+    let mut p = vec![];
+    for c in try!(iprp.children(rdr, 0)) {
+        if &c.kind != b"ipma" {
+            try!(copy_range(rdr, &mut p, c.offset, c.end(), &[]));
+            continue;
+        }
+
+        let mut d = Cursor::new(try!(c.read_data(rdr)));
+        let (version, flags) = try!(bmff::read_full_box(&mut d));
+        let count = try!(d.read_u32::<BigEndian>());
+        let mut kept = 0u32;
+        let mut entries = vec![];
+        for _ in 0..count {
+            let start = d.position() as usize;
+            let id = try!(bmff::read_uint(&mut d, if version < 1 { 2 } else { 4 })) as u32;
+            let n = try!(d.read_u8()) as i64;
+            try!(d.seek(SeekFrom::Current(n * if flags & 1 == 1 { 2 } else { 1 })));
+            let end = d.position() as usize;
+            if end > d.get_ref().len() {
+                return Err(invalid("truncated ipma box"));
+            }
+            if !removed.contains(&id) {
+                entries.extend_from_slice(&d.get_ref()[start..end]);
+                kept += 1;
+            }
+        }
+
+        let mut payload = vec![];
+        try!(payload.write_u32::<BigEndian>(kept));
+        payload.extend(entries);
+        try!(bmff::write_full_box(&mut p, b"ipma", version, flags, &payload));
+    }
+    bmff::write_box(w, b"iprp", &p)
+}
+
+// item ids and the indices of their associated properties, without the essential flags
+fn read_ipma<R: Read + Seek>(rdr: &mut R, ipma: &BoxHeader) -> io::Result<Vec<(u32, Vec<u16>)>> {
+    let mut d = Cursor::new(try!(ipma.read_data(rdr)));
+    let (version, flags) = try!(bmff::read_full_box(&mut d));
+    let count = try!(d.read_u32::<BigEndian>());
+    let mut v = vec![];
+    for _ in 0..count {
+        let id = try!(bmff::read_uint(&mut d, if version < 1 { 2 } else { 4 })) as u32;
+        let n = try!(d.read_u8());
+        let mut props = vec![];
+        for _ in 0..n {
+            props.push(if flags & 1 == 1 {
+                try!(d.read_u16::<BigEndian>()) & 0x7fff
+            } else {
+                (try!(d.read_u8()) & 0x7f) as u16
+            });
+        }
+        v.push((id, props));
+    }
+    Ok(v)
+}
+
+/// the TIFF structure within the data of an Exif item, which starts
+/// with the offset of the TIFF header after that field, usually 6 to skip "Exif\0\0"
+pub fn exif_tiff(d: &[u8]) -> io::Result<&[u8]> {
+    if d.len() < 4 {
+        return Err(invalid("truncated Exif item"));
+    }
+    let offset = try!(Cursor::new(d).read_u32::<BigEndian>()) as usize;
+    d.get(4 + offset..).ok_or(invalid("invalid Exif item header offset"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_pitm<R: Read + Seek>(rdr: &mut R, b: &BoxHeader) -> io::Result<u32> {
+    let mut c = Cursor::new(try!(b.read_data(rdr)));
+    let (version, _) = try!(bmff::read_full_box(&mut c));
+    bmff::read_uint(&mut c, if version == 0 { 2 } else { 4 }).map(|v| v as u32)
+}
+
+fn read_iinf<R: Read + Seek>(rdr: &mut R, b: &BoxHeader) -> io::Result<Vec<ItemInfo>> {
+    try!(rdr.seek(SeekFrom::Start(b.data_offset())));
+    let (version, _) = try!(bmff::read_full_box(rdr));
+    let count_size = if version == 0 { 2 } else { 4 };
+    try!(bmff::read_uint(rdr, count_size));
+
+    let mut items = vec![];
+    for infe in try!(b.children(rdr, 4 + count_size as u64)) {
+        if &infe.kind == b"infe" {
+            items.push(try!(read_infe(&try!(infe.read_data(rdr)))));
+        }
+    }
+    Ok(items)
+}
+
+fn read_infe(d: &[u8]) -> io::Result<ItemInfo> {
+    let mut c = Cursor::new(d);
+    let (version, _) = try!(bmff::read_full_box(&mut c));
+    let id = try!(bmff::read_uint(&mut c, if version == 3 { 4 } else { 2 })) as u32;
+    try!(c.read_u16::<BigEndian>()); // protection index
+
+    let mut kind = [0u8; 4];
+    if version >= 2 {
+        try!(c.read_exact(&mut kind));
+    }
+    let name = try!(bmff::read_cstring(&mut c));
+
+    // versions 0 and 1 describe every item with a content type, and have no item type
+    let (content_type, content_encoding) = if version < 2 || &kind == b"mime" {
+        let t = try!(bmff::read_cstring(&mut c));
+        let e = try!(bmff::read_cstring(&mut c));
+        (Some(t), if e.is_empty() { None } else { Some(e) })
+    } else {
+        (None, None)
+    };
+
+    Ok(ItemInfo{ id: id, kind: kind, name: name, content_type: content_type, content_encoding: content_encoding })
+}
+
+fn read_iref<R: Read + Seek>(rdr: &mut R, b: &BoxHeader) -> io::Result<Vec<Reference>> {
+    try!(rdr.seek(SeekFrom::Start(b.data_offset())));
+    let (version, _) = try!(bmff::read_full_box(rdr));
+    let id_size = if version == 0 { 2 } else { 4 };
+
+    let mut refs = vec![];
+    for r in try!(b.children(rdr, 4)) {
+        let mut c = Cursor::new(try!(r.read_data(rdr)));
+        let from = try!(bmff::read_uint(&mut c, id_size)) as u32;
+        let count = try!(c.read_u16::<BigEndian>());
+        let mut to = vec![];
+        for _ in 0..count {
+            to.push(try!(bmff::read_uint(&mut c, id_size)) as u32);
+        }
+        refs.push(Reference{ kind: r.kind, from: from, to: to });
+    }
+    Ok(refs)
+}
+
+fn read_iloc<R: Read + Seek>(rdr: &mut R, b: &BoxHeader) -> io::Result<(Vec<Location>, LocationSizes)> {
+    let mut c = Cursor::new(try!(b.read_data(rdr)));
+    let (version, _) = try!(bmff::read_full_box(&mut c));
+    if version > 2 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported iloc version: {}", version)));
+    }
+
+    let sizes = try!(c.read_u16::<BigEndian>());
+    let s = LocationSizes{
+        version: version,
+        offset_size: (sizes >> 12) as u8,
+        length_size: ((sizes >> 8) & 0xF) as u8,
+        base_offset_size: ((sizes >> 4) & 0xF) as u8,
+        index_size: if version > 0 { (sizes & 0xF) as u8 } else { 0 },
+    };
+    let id_size = if version < 2 { 2 } else { 4 };
+
+    let count = try!(bmff::read_uint(&mut c, id_size));
+    let mut locations = vec![];
+    for _ in 0..count {
+        let item_id = try!(bmff::read_uint(&mut c, id_size)) as u32;
+        let construction_method = if version > 0 { (try!(c.read_u16::<BigEndian>()) & 0xF) as u8 } else { FILE_OFFSET };
+        let data_reference_index = try!(c.read_u16::<BigEndian>());
+        let base_offset = try!(bmff::read_uint(&mut c, s.base_offset_size));
+
+        let extent_count = try!(c.read_u16::<BigEndian>());
+        let mut extents = vec![];
+        for _ in 0..extent_count {
+            extents.push(Extent{
+                index: try!(bmff::read_uint(&mut c, s.index_size)),
+                offset: try!(bmff::read_uint(&mut c, s.offset_size)),
+                length: try!(bmff::read_uint(&mut c, s.length_size)),
+            });
+        }
+
+        locations.push(Location{
+            item_id: item_id,
+            construction_method: construction_method,
+            data_reference_index: data_reference_index,
+            base_offset: base_offset,
+            extents: extents,
+        });
+    }
+    Ok((locations, s))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use bmff::tests::{make_box, make_full_box};
+    use exif::tag;
+    use exif::tests::exif_with;
+
+    pub const XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+
+    pub fn exif_item() -> Vec<u8> {
+        let mut e = exif_with(&[(tag::MAKE, "Apple")]);
+        e.big_endian = true;
+        let mut buf = Cursor::new(vec![0, 0, 0, 6, b'E', b'x', b'i', b'f', 0, 0]);
+        buf.set_position(10);
+        e.encode(&mut buf).expect("encode exif");
+        buf.into_inner()
+    }
+
+    fn infe(id: u16, kind: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut p = vec![(id >> 8) as u8, id as u8, 0, 0];
+        p.extend_from_slice(kind);
+        p.push(0);
+        p.extend_from_slice(extra);
+        make_full_box(b"infe", 2, 0, &p)
+    }
+
+    /// a file with an image item (1), an Exif item (2) split across two extents
+    /// in mdat, and an XMP item (3) in idat
+    pub fn sample() -> Vec<u8> {
+        let exif = exif_item();
+        let split = 7;
+        let ftyp = make_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+
+        let mut iinf = vec![0, 3];
+        iinf.extend(infe(1, b"hvc1", b""));
+        iinf.extend(infe(2, b"Exif", b""));
+        iinf.extend(infe(3, b"mime", b"application/rdf+xml\0"));
+
+        // built twice: the mdat offsets depend on the size of meta
+        let meta = |mdat_data: u32| {
+            let mut iloc = vec![0x44, 0x00, 0, 3];
+            // image: single extent in mdat after the exif data
+            iloc.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 1]);
+            iloc.extend(be32(mdat_data + exif.len() as u32));
+            iloc.extend(be32(4));
+            // exif: two extents, in reverse order in the file
+            iloc.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 2]);
+            iloc.extend(be32(mdat_data + (exif.len() - split) as u32));
+            iloc.extend(be32(split as u32));
+            iloc.extend(be32(mdat_data));
+            iloc.extend(be32((exif.len() - split) as u32));
+            // xmp: idat
+            iloc.extend_from_slice(&[0, 3, 0, 1, 0, 0, 0, 1]);
+            iloc.extend(be32(0));
+            iloc.extend(be32(XMP.len() as u32));
+
+            let mut m = make_full_box(b"hdlr", 0, 0, b"\0\0\0\0pict\0\0\0\0\0\0\0\0\0\0\0\0\0");
+            m.extend(make_full_box(b"pitm", 0, 0, &[0, 1]));
+            m.extend(make_full_box(b"iinf", 0, 0, &iinf));
+            m.extend(make_full_box(b"iloc", 1, 0, &iloc));
+            m.extend(make_full_box(b"iref", 0, 0, &make_box(b"cdsc", &[0, 2, 0, 1, 0, 1])));
+            // one property, associated with the image and, unusually, the exif item
+            let mut iprp = make_box(b"ipco", &make_full_box(b"ispe", 0, 0, &[0, 0, 0, 1, 0, 0, 0, 1]));
+            iprp.extend(make_full_box(b"ipma", 0, 0, &[0, 0, 0, 2, 0, 1, 1, 0x81, 0, 2, 1, 0x01]));
+            m.extend(make_box(b"iprp", &iprp));
+            m.extend(make_box(b"idat", XMP));
+            make_full_box(b"meta", 0, 0, &m)
+        };
+
+        let mdat_data = (ftyp.len() + meta(0).len() + 8) as u32;
+        let mut mdat = exif[split..].to_vec();
+        mdat.extend_from_slice(&exif[..split]);
+        mdat.extend_from_slice(b"hevc");
+
+        let mut file = ftyp;
+        file.extend(meta(mdat_data));
+        file.extend(make_box(b"mdat", &mdat));
+        file
+    }
+
+    fn be32(v: u32) -> Vec<u8> {
+        vec![(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+    }
+
+    #[test]
+    fn read_items() {
+        let mut c = Cursor::new(sample());
+        assert!(is_heif(&mut c));
+
+        let h = Heif::new(&mut c).expect("read heif");
+        assert_eq!(h.primary_item, Some(1));
+        assert_eq!(h.items.len(), 3);
+        assert_eq!(h.location_sizes.version, 1);
+        assert_eq!(h.read_item(&mut c, 1).expect("image"), b"hevc".to_vec());
+
+        let e = h.exif(&mut c).expect("exif").expect("decode exif");
+        assert_eq!(e.make(), Some("Apple"));
+        assert_eq!(h.xmp(&mut c).expect("xmp").expect("read xmp"), XMP.to_vec());
+    }
+
+    fn rewrite(data: Vec<u8>, exif: Change<&Exif>, xmp: Change<&[u8]>) -> Vec<u8> {
+        let mut c = Cursor::new(data);
+        let h = Heif::new(&mut c).expect("read heif");
+        let mut out = vec![];
+        h.rewrite(&mut c, &mut out, exif, xmp).expect("rewrite heif");
+        out
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn replace_exif() {
+        let out = rewrite(sample(), Change::Set(&exif_with(&[(tag::MAKE, "Acme")])), Change::Keep);
+        assert!(!contains(&out, &exif_item()[10..]));
+
+        let mut c = Cursor::new(out);
+        let h = Heif::new(&mut c).expect("reread heif");
+        assert_eq!(h.exif_item().map(|i| i.id), Some(2));
+        assert_eq!(h.references, vec![Reference{ kind: *b"cdsc", from: 2, to: vec![1] }]);
+        assert_eq!(h.read_item(&mut c, 1).expect("image"), b"hevc".to_vec());
+        assert_eq!(h.exif(&mut c).expect("exif").expect("decode exif").make(), Some("Acme"));
+        assert_eq!(h.xmp(&mut c).expect("xmp").expect("read xmp"), XMP.to_vec());
+    }
+
+    #[test]
+    fn remove_and_add_metadata() {
+        let out = rewrite(sample(), Change::Remove, Change::Remove);
+        assert!(!contains(&out, XMP));
+
+        let mut c = Cursor::new(out.clone());
+        let h = Heif::new(&mut c).expect("reread heif");
+        assert_eq!(h.items.iter().map(|i| i.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(h.locations.len(), 1);
+        assert!(h.references.is_empty());
+        assert!(h.exif(&mut c).is_none());
+        assert_eq!(h.read_item(&mut c, 1).expect("image"), b"hevc".to_vec());
+        // the exif item's property association is gone
+        assert!(contains(&out, &make_full_box(b"ipma", 0, 0, &[0, 0, 0, 1, 0, 1, 1, 0x81])));
+
+        let out = rewrite(out, Change::Set(&exif_with(&[(tag::MAKE, "Acme")])), Change::Set(XMP));
+        let mut c = Cursor::new(out);
+        let h = Heif::new(&mut c).expect("reread heif");
+        assert_eq!(h.exif_item().map(|i| i.id), Some(2));
+        assert_eq!(h.xmp_item().map(|i| i.id), Some(3));
+        assert_eq!(h.references.iter().map(|r| (r.from, r.to.clone())).collect::<Vec<_>>(), vec![(2, vec![1]), (3, vec![1])]);
+        assert_eq!(h.read_item(&mut c, 1).expect("image"), b"hevc".to_vec());
+        assert_eq!(h.exif(&mut c).expect("exif").expect("decode exif").make(), Some("Acme"));
+        assert_eq!(h.xmp(&mut c).expect("xmp").expect("read xmp"), XMP.to_vec());
+    }
+
+    #[test]
+    fn shared_extents_kept() {
+        assert_eq!(subtract_ranges(vec![(10, 20), (30, 40)], &[(15, 18), (35, 50)]), vec![(10, 15), (18, 20), (30, 35)]);
+        assert_eq!(subtract_ranges(vec![(10, 20)], &[(0, 100)]), vec![]);
+    }
+
+    #[test]
+    fn refuse_moov() {
+        let mut f = sample();
+        f.extend(make_box(b"moov", b""));
+        let mut c = Cursor::new(f);
+        let h = Heif::new(&mut c).expect("read heif");
+        let e = h.rewrite(&mut c, &mut vec![], Change::Remove, Change::Keep).expect_err("moov");
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn exif_header_offset() {
+        assert_eq!(exif_tiff(&[0, 0, 0, 2, 0xAA, 0xBB, b'I', b'I']).expect("tiff"), b"II");
+        assert!(exif_tiff(&[0, 0, 0, 9, 0]).is_err());
+    }
+
+    #[test]
+    fn not_heif() {
+        let mut c = Cursor::new(make_box(b"ftyp", b"crx \0\0\0\x01crx isom"));
+        assert!(!is_heif(&mut c));
+        assert!(Heif::new(&mut c).is_err());
+    }
+
+    #[test]
+    fn icc_profile() {
+        let mut ipco = make_full_box(b"ispe", 0, 0, &[0, 0, 0, 1, 0, 0, 0, 1]);
+        ipco.extend(make_box(b"colr", b"nclx\0\x01\0\x0d\0\x06\x80"));
+        ipco.extend(make_box(b"colr", b"profICC!"));
+        let mut iprp = make_box(b"ipco", &ipco);
+        iprp.extend(make_full_box(b"ipma", 0, 0, &[0, 0, 0, 1, 0, 1, 3, 0x81, 0x02, 0x83]));
+        let mut m = make_full_box(b"hdlr", 0, 0, b"\0\0\0\0pict\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        m.extend(make_full_box(b"pitm", 0, 0, &[0, 1]));
+        m.extend(make_box(b"iprp", &iprp));
+        let mut f = make_box(b"ftyp", b"avif\0\0\0\0mif1avif");
+        f.extend(make_full_box(b"meta", 0, 0, &m));
+
+        let mut c = Cursor::new(f);
+        let h = Heif::new(&mut c).expect("read heif");
+        assert_eq!(h.icc_profile(&mut c).expect("icc").expect("read icc"), b"ICC!".to_vec());
+    }
+}
@@ -0,0 +1,483 @@
+// http://www.color.org/specification/ICC.1-2022-05.pdf
+//
+// an ICC profile is a 128 byte header, a tag table of (signature, offset,
+// size) and the tag data. jpeg files split profiles across APP2 segments,
+// each numbered with its sequence number and the total count.
+
+use std::io::prelude::*;
+use std::io;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use exif::{Exif, EntryData, DateTime, tag};
+use exif::jpeg::{self, Segment};
+use md5;
+use metadata;
+
+pub type FourCC = [u8; 4];
+
+pub const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+const APP2: u8 = 0xE2;
+/// profile data per APP2 segment, after the header, sequence number and count
+const MAX_CHUNK: usize = 65533 - 14;
+const HEADER_LEN: usize = 128;
+
+/// IDs of published profiles: color.org's sRGB2014 and Apple's Display P3
+const PROFILE_IDS: &[([u8; 16], WellKnown)] = &[
+    ([0x34, 0x56, 0x2a, 0xbf, 0x99, 0x4c, 0xcd, 0x06, 0x6d, 0x2c, 0x57, 0x21, 0xd0, 0xd6, 0x8c, 0x5d], WellKnown::Srgb),
+    ([0xca, 0x1a, 0x95, 0x82, 0x25, 0x7f, 0x10, 0x4d, 0x38, 0x99, 0x13, 0xd5, 0xd1, 0xea, 0x15, 0x82], WellKnown::DisplayP3),
+];
+
+/// tag signatures
+pub mod sig {
+    use super::FourCC;
+    pub const DESCRIPTION: FourCC = *b"desc";
+    pub const COPYRIGHT: FourCC = *b"cprt";
+    pub const MEDIA_WHITE_POINT: FourCC = *b"wtpt";
+    pub const RED_COLORANT: FourCC = *b"rXYZ";
+    pub const GREEN_COLORANT: FourCC = *b"gXYZ";
+    pub const BLUE_COLORANT: FourCC = *b"bXYZ";
+    pub const RED_TRC: FourCC = *b"rTRC";
+    pub const GREEN_TRC: FourCC = *b"gTRC";
+    pub const BLUE_TRC: FourCC = *b"bTRC";
+    pub const GRAY_TRC: FourCC = *b"kTRC";
+}
+
+/// a CIE XYZ value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Xyz {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// a tone reproduction curve
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trc {
+    /// a simple power function, 1.0 for the identity
+    Gamma(f64),
+    /// samples evenly spaced over 0.0..1.0
+    Table(Vec<u16>),
+    /// a parametric curve, ICC.1 table 68, with its parameters
+    Parametric(u16, Vec<f64>),
+}
+
+/// profiles identified by their colorimetry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WellKnown {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub size: u32,
+    pub cmm: FourCC,
+    /// major, minor and bug fix version
+    pub version: (u8, u8, u8),
+    /// device class, e.g. "mntr" for displays
+    pub class: FourCC,
+    /// colour space of the data, e.g. "RGB "
+    pub color_space: FourCC,
+    /// profile connection space, "XYZ " or "Lab "
+    pub pcs: FourCC,
+    pub created: Option<DateTime>,
+    pub platform: FourCC,
+    pub flags: u32,
+    pub manufacturer: FourCC,
+    pub model: u32,
+    pub rendering_intent: u32,
+    pub illuminant: Xyz,
+    pub creator: FourCC,
+    /// MD5 profile ID, all zeros if not computed
+    pub id: [u8; 16],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagEntry {
+    pub signature: FourCC,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// top level data structure representing an ICC profile
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub header: Header,
+    pub tags: Vec<TagEntry>,
+    /// the complete profile
+    pub data: Vec<u8>,
+}
+
+impl Profile {
+    pub fn parse(data: Vec<u8>) -> io::Result<Self> {
+        if data.len() < HEADER_LEN + 4 || &data[36..40] != b"acsp" {
+            return Err(invalid("not an ICC profile"));
+        }
+        let d = &data[..];
+        let created = {
+            let f = |i: usize| BigEndian::read_u16(&d[24 + i * 2..]);
+            DateTime::from_iso8601(&format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", f(0), f(1), f(2), f(3), f(4), f(5)))
+        };
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&d[84..100]);
+        let header = Header{
+            size: BigEndian::read_u32(d),
+            cmm: four_of(&d[4..]),
+            version: (d[8], d[9] >> 4, d[9] & 0xF),
+            class: four_of(&d[12..]),
+            color_space: four_of(&d[16..]),
+            pcs: four_of(&d[20..]),
+            created: created,
+            platform: four_of(&d[40..]),
+            flags: BigEndian::read_u32(&d[44..]),
+            manufacturer: four_of(&d[48..]),
+            model: BigEndian::read_u32(&d[52..]),
+            rendering_intent: BigEndian::read_u32(&d[64..]),
+            illuminant: read_xyz(&d[68..]),
+            creator: four_of(&d[80..]),
+            id: id,
+        };
+
+        let count = BigEndian::read_u32(&d[HEADER_LEN..]) as usize;
+        let table = try!(count.checked_mul(12).and_then(|n| d.get(HEADER_LEN + 4..HEADER_LEN + 4 + n))
+                         .ok_or(invalid("truncated ICC tag table")));
+        let mut tags = vec![];
+        for t in table.chunks(12) {
+            let e = TagEntry{ signature: four_of(t), offset: BigEndian::read_u32(&t[4..]), size: BigEndian::read_u32(&t[8..]) };
+            if (e.offset as u64 + e.size as u64) > d.len() as u64 {
+                return Err(invalid("ICC tag out of bounds"));
+            }
+            tags.push(e);
+        }
+        Ok(Profile{ header: header, tags: tags, data: data })
+    }
+
+    /// the data of a tag, starting with its type signature
+    pub fn tag(&self, signature: &FourCC) -> Option<&[u8]> {
+        let t = self.tags.iter().find(|t| &t.signature == signature)?;
+        self.data.get(t.offset as usize..(t.offset + t.size) as usize)
+    }
+
+    /// the profile description, from a v2 textDescriptionType or v4 multiLocalizedUnicodeType
+    pub fn description(&self) -> Option<String> {
+        text(self.tag(&sig::DESCRIPTION)?)
+    }
+
+    pub fn copyright(&self) -> Option<String> {
+        text(self.tag(&sig::COPYRIGHT)?)
+    }
+
+    pub fn white_point(&self) -> Option<Xyz> {
+        self.xyz(&sig::MEDIA_WHITE_POINT)
+    }
+
+    /// the red, green and blue colorants of a matrix/TRC profile
+    pub fn colorants(&self) -> Option<[Xyz; 3]> {
+        Some([self.xyz(&sig::RED_COLORANT)?, self.xyz(&sig::GREEN_COLORANT)?, self.xyz(&sig::BLUE_COLORANT)?])
+    }
+
+    pub fn xyz(&self, signature: &FourCC) -> Option<Xyz> {
+        let d = self.tag(signature)?;
+        if d.len() < 20 || &d[..4] != b"XYZ " {
+            return None;
+        }
+        Some(read_xyz(&d[8..]))
+    }
+
+    /// a curveType or parametricCurveType tag, such as rTRC
+    pub fn trc(&self, signature: &FourCC) -> Option<Trc> {
+        let d = self.tag(signature)?;
+        if d.len() < 12 {
+            return None;
+        }
+        match &d[..4] {
+            b"curv" => {
+                let n = BigEndian::read_u32(&d[8..]) as usize;
+                let v = d.get(12..12 + n.checked_mul(2)?)?;
+                Some(match n {
+                    0 => Trc::Gamma(1.0),
+                    1 => Trc::Gamma(BigEndian::read_u16(v) as f64 / 256.0),
+                    _ => Trc::Table(v.chunks(2).map(BigEndian::read_u16).collect()),
+                })
+            }
+            b"para" => {
+                let function = BigEndian::read_u16(&d[8..]);
+                let n = match function {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let v = d.get(12..12 + n * 4)?;
+                Some(Trc::Parametric(function, v.chunks(4).map(s15_fixed16).collect()))
+            }
+            _ => None,
+        }
+    }
+
+    /// the profile ID as defined by ICC.1: the MD5 of the profile with the
+    /// flags, rendering intent and ID fields zeroed
+    pub fn computed_id(&self) -> [u8; 16] {
+        let mut d = self.data.clone();
+        for r in &[44..48, 64..68, 84..100] {
+            for b in &mut d[r.clone()] {
+                *b = 0;
+            }
+        }
+        md5::digest(&d)
+    }
+
+    /// identify a profile from the given table of profile IDs, then those of
+    /// some published profiles or, since version 2 profiles don't have IDs
+    /// and many equivalent profiles exist, from its colorants
+    pub fn identify(&self, known: &[([u8; 16], WellKnown)]) -> Option<WellKnown> {
+        let id = if self.header.id == [0; 16] { self.computed_id() } else { self.header.id };
+        if let Some(k) = known.iter().chain(PROFILE_IDS).find(|k| k.0 == id) {
+            return Some(k.1);
+        }
+        self.well_known()
+    }
+
+    /// identify an RGB profile by its D50 adapted colorants
+    pub fn well_known(&self) -> Option<WellKnown> {
+        // the colorants of the published profiles, to four places
+        const PRIMARIES: &[(WellKnown, [[f64; 3]; 3])] = &[
+            (WellKnown::Srgb, [[0.4361, 0.2225, 0.0139], [0.3851, 0.7169, 0.0971], [0.1431, 0.0606, 0.7141]]),
+            (WellKnown::DisplayP3, [[0.5151, 0.2412, -0.0011], [0.2919, 0.6922, 0.0419], [0.1572, 0.0666, 0.7841]]),
+            (WellKnown::AdobeRgb, [[0.6097, 0.3111, 0.0195], [0.2053, 0.6257, 0.0609], [0.1492, 0.0632, 0.7446]]),
+        ];
+        if &self.header.color_space != b"RGB " {
+            return None;
+        }
+        let c = self.colorants()?;
+        PRIMARIES.iter().find(|p| p.1.iter().zip(&c).all(|(e, a)| {
+            (e[0] - a.x).abs() < 0.002 && (e[1] - a.y).abs() < 0.002 && (e[2] - a.z).abs() < 0.002
+        })).map(|p| p.0)
+    }
+}
+
+/// read and decode the ICC profile from a file of any supported format
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<Option<Profile>> {
+    match try!(metadata::read(rdr)).icc {
+        Some(d) => Profile::parse(d).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// reassemble a profile from jpeg APP2 segments, which may be out of order
+pub fn from_jpeg_segments(segments: &[Segment]) -> io::Result<Option<Vec<u8>>> {
+    let mut chunks = vec![];
+    for s in segments.iter().filter(|s| s.marker == APP2 && s.data.starts_with(ICC_HEADER)) {
+        let d = &s.data[ICC_HEADER.len()..];
+        if d.len() < 2 {
+            return Err(invalid("truncated ICC_PROFILE segment"));
+        }
+        chunks.push((d[0], d[1], &d[2..]));
+    }
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|c| c.0);
+    let count = chunks[0].1;
+    if chunks.len() != count as usize || chunks.iter().enumerate().any(|(i, c)| c.0 as usize != i + 1 || c.1 != count) {
+        return Err(invalid("missing or inconsistent ICC_PROFILE segments"));
+    }
+    Ok(Some(chunks.into_iter().flat_map(|c| c.2.iter().cloned()).collect()))
+}
+
+/// split a profile into numbered APP2 segments
+// usize::div_ceil needs rust 1.73
+#[allow(clippy::manual_div_ceil)]
+pub fn jpeg_segments(profile: &[u8]) -> io::Result<Vec<Segment>> {
+    let count = (profile.len() + MAX_CHUNK - 1) / MAX_CHUNK;
+    if count > 255 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "ICC profile too large for a jpeg file"));
+    }
+    Ok(profile.chunks(MAX_CHUNK).enumerate().map(|(i, c)| {
+        let mut d = ICC_HEADER.to_vec();
+        d.push(i as u8 + 1);
+        d.push(count as u8);
+        d.extend_from_slice(c);
+        Segment{ marker: APP2, data: d }
+    }).collect())
+}
+
+/// copy a jpeg file, embedding the given profile or stripping any existing one.
+/// new segments follow the JFIF and exif segments.
+pub fn write_jpeg<R: Read, W: Write>(rdr: &mut R, w: &mut W, profile: Option<&[u8]>) -> io::Result<()> {
+    jpeg::rewrite_segments(rdr, w, |segments| {
+        segments.retain(|s| !(s.marker == APP2 && s.data.starts_with(ICC_HEADER)));
+        if let Some(p) = profile {
+            let pos = segments.iter().take_while(|s| s.marker == 0xE0 || s.marker == 0xE1).count();
+            for (i, s) in try!(jpeg_segments(p)).into_iter().enumerate() {
+                segments.insert(pos + i, s);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// embed or strip a profile in TIFF exif, as the InterColorProfile tag of IFD0
+pub fn set_tiff(exif: &mut Exif, profile: Option<&[u8]>) {
+    let ifd = exif.ifd0_mut();
+    match profile {
+        Some(p) => ifd.set(tag::ICC_PROFILE, EntryData::Undef(p.to_vec())),
+        None => { ifd.remove(tag::ICC_PROFILE); }
+    }
+}
+
+// text from textType, textDescriptionType or multiLocalizedUnicodeType,
+// preferring English
+fn text(d: &[u8]) -> Option<String> {
+    if d.len() < 8 {
+        return None;
+    }
+    let ascii = |b: &[u8]| String::from_utf8_lossy(b.split(|&c| c == 0).next().unwrap_or(b)).into_owned();
+    match &d[..4] {
+        b"text" => Some(ascii(&d[8..])),
+        b"desc" => {
+            let n = BigEndian::read_u32(d.get(8..12)?) as usize;
+            Some(ascii(d.get(12..12usize.checked_add(n)?)?))
+        }
+        b"mluc" => {
+            let count = BigEndian::read_u32(d.get(8..12)?) as usize;
+            let size = BigEndian::read_u32(d.get(12..16)?) as usize;
+            if size < 12 {
+                return None;
+            }
+            let records: Vec<&[u8]> = (0..count).map(|i| d.get(16 + i * size..16 + i * size + 12)).collect::<Option<_>>()?;
+            let r = records.iter().find(|r| &r[..2] == b"en").or_else(|| records.first())?;
+            let (len, offset) = (BigEndian::read_u32(&r[4..]) as usize, BigEndian::read_u32(&r[8..]) as usize);
+            let units: Vec<u16> = d.get(offset..offset.checked_add(len)?)?.chunks(2).filter(|c| c.len() == 2).map(BigEndian::read_u16).collect();
+            Some(String::from_utf16_lossy(&units).trim_end_matches('\0').to_string())
+        }
+        _ => None,
+    }
+}
+
+fn four_of(d: &[u8]) -> FourCC {
+    let mut f = [0u8; 4];
+    f.copy_from_slice(&d[..4]);
+    f
+}
+
+fn s15_fixed16(d: &[u8]) -> f64 {
+    BigEndian::read_i32(d) as f64 / 65536.0
+}
+
+fn read_xyz(d: &[u8]) -> Xyz {
+    Xyz{ x: s15_fixed16(d), y: s15_fixed16(&d[4..]), z: s15_fixed16(&d[8..]) }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn xyz_tag(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut d = b"XYZ \0\0\0\0".to_vec();
+        for v in &[x, y, z] {
+            d.extend_from_slice(&((v * 65536.0).round() as i32).to_be_bytes());
+        }
+        d
+    }
+
+    /// a minimal Display P3 matrix/TRC profile
+    pub fn display_p3() -> Vec<u8> {
+        let mut desc = b"mluc\0\0\0\0\0\0\0\x01\0\0\0\x0cenUS\0\0\0\x04\0\0\0\x1c".to_vec();
+        desc.extend_from_slice(b"\0P\x003");
+        let mut para = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for v in &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            para.extend_from_slice(&((v * 65536.0f64).round() as i32).to_be_bytes());
+        }
+        let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"desc", desc),
+            (b"cprt", b"text\0\0\0\0CC0\0".to_vec()),
+            (b"wtpt", xyz_tag(0.9642, 1.0, 0.8249)),
+            (b"rXYZ", xyz_tag(0.5151, 0.2412, -0.0011)),
+            (b"gXYZ", xyz_tag(0.2919, 0.6922, 0.0419)),
+            (b"bXYZ", xyz_tag(0.1572, 0.0666, 0.7841)),
+            (b"rTRC", para),
+            (b"gTRC", b"curv\0\0\0\0\0\0\0\x01\x02\x33".to_vec()),
+        ];
+
+        let mut d = vec![0u8; HEADER_LEN];
+        d[8] = 4;
+        d[9] = 0x30;
+        d[12..16].copy_from_slice(b"mntr");
+        d[16..20].copy_from_slice(b"RGB ");
+        d[20..24].copy_from_slice(b"XYZ ");
+        for (i, v) in [2017u16, 7, 7, 13, 22, 32].iter().enumerate() {
+            d[24 + i * 2..26 + i * 2].copy_from_slice(&v.to_be_bytes());
+        }
+        d[36..40].copy_from_slice(b"acsp");
+        d.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = HEADER_LEN + 4 + tags.len() * 12;
+        let mut data = vec![];
+        for &(s, ref t) in &tags {
+            d.extend_from_slice(s);
+            d.extend_from_slice(&(offset as u32).to_be_bytes());
+            d.extend_from_slice(&(t.len() as u32).to_be_bytes());
+            data.extend_from_slice(t);
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            offset = HEADER_LEN + 4 + tags.len() * 12 + data.len();
+        }
+        d.extend(data);
+        let len = d.len() as u32;
+        d[..4].copy_from_slice(&len.to_be_bytes());
+        d
+    }
+
+    #[test]
+    fn decode_profile() {
+        let p = Profile::parse(display_p3()).expect("parse profile");
+        assert_eq!(p.header.version, (4, 3, 0));
+        assert_eq!(&p.header.class, b"mntr");
+        assert_eq!(p.header.created.expect("date").to_string(), "2017-07-07T13:22:32Z");
+        assert_eq!(p.description(), Some("P3".to_string()));
+        assert_eq!(p.copyright(), Some("CC0".to_string()));
+        assert!((p.white_point().expect("wtpt").z - 0.8249).abs() < 1e-4);
+        assert_eq!(p.trc(&sig::GREEN_TRC), Some(Trc::Gamma(2.19921875)));
+        match p.trc(&sig::RED_TRC) {
+            Some(Trc::Parametric(3, ref v)) => assert!((v[0] - 2.4).abs() < 1e-4 && v.len() == 5),
+            t => panic!("unexpected curve {:?}", t),
+        }
+        assert_eq!(p.well_known(), Some(WellKnown::DisplayP3));
+        assert_eq!(p.identify(&[(p.computed_id(), WellKnown::Srgb)]), Some(WellKnown::Srgb));
+        // a published profile ID is trusted over the colorants
+        let mut d = display_p3();
+        d[84..100].copy_from_slice(&PROFILE_IDS[0].0);
+        assert_eq!(Profile::parse(d).expect("parse profile").identify(&[]), Some(WellKnown::Srgb));
+        assert!(Profile::parse(vec![0; 200]).is_err());
+    }
+
+    #[test]
+    fn jpeg_embed_and_strip() {
+        let profile: Vec<u8> = (0..150000u32).map(|i| i as u8).collect();
+        let f = [0xFF, 0xD8, 0xFF, 0xE0, 0, 4, b'J', b'F', 0xFF, 0xDA, 0, 2, 0xFF, 0xD9];
+        let mut out = vec![];
+        write_jpeg(&mut Cursor::new(&f[..]), &mut out, Some(&profile)).expect("embed profile");
+
+        let mut segments = jpeg::read_segments(&mut Cursor::new(&out)).expect("segments");
+        assert_eq!(segments.len(), 4);
+        segments.swap(1, 3);
+        assert_eq!(from_jpeg_segments(&segments).expect("reassemble"), Some(profile));
+        segments.remove(2);
+        assert!(from_jpeg_segments(&segments).is_err());
+
+        let mut stripped = vec![];
+        write_jpeg(&mut Cursor::new(&out), &mut stripped, None).expect("strip profile");
+        assert_eq!(&stripped[..], &f[..]);
+    }
+}
@@ -0,0 +1,272 @@
+// https://www.iptc.org/std/IIM/4.2/specification/IIMV4.2.pdf
+//
+// IPTC IIM records are a sequence of datasets: a 0x1C tag marker, record
+// and dataset numbers, a length and the data. in jpeg files they're stored
+// as a Photoshop image resource in APP13.
+
+use std::io::prelude::*;
+use std::io;
+use std::str;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use photoshop;
+
+/// CodedCharacterSet value declaring UTF-8, ISO 2022 "ESC % G"
+pub const UTF8: &[u8] = b"\x1b%G";
+
+/// dataset ids, as (record, dataset)
+pub mod tag {
+    // record 1, envelope
+    pub const ENVELOPE_RECORD_VERSION: (u8, u8) = (1, 0);
+    pub const DESTINATION: (u8, u8) = (1, 5);
+    pub const FILE_FORMAT: (u8, u8) = (1, 20);
+    pub const FILE_FORMAT_VERSION: (u8, u8) = (1, 22);
+    pub const SERVICE_IDENTIFIER: (u8, u8) = (1, 30);
+    pub const ENVELOPE_NUMBER: (u8, u8) = (1, 40);
+    pub const PRODUCT_ID: (u8, u8) = (1, 50);
+    pub const ENVELOPE_PRIORITY: (u8, u8) = (1, 60);
+    pub const DATE_SENT: (u8, u8) = (1, 70);
+    pub const TIME_SENT: (u8, u8) = (1, 80);
+    pub const CODED_CHARACTER_SET: (u8, u8) = (1, 90);
+    pub const UNIQUE_OBJECT_NAME: (u8, u8) = (1, 100);
+
+    // record 2, application
+    pub const RECORD_VERSION: (u8, u8) = (2, 0);
+    pub const OBJECT_NAME: (u8, u8) = (2, 5);
+    pub const URGENCY: (u8, u8) = (2, 10);
+    pub const CATEGORY: (u8, u8) = (2, 15);
+    pub const SUPPLEMENTAL_CATEGORIES: (u8, u8) = (2, 20);
+    pub const KEYWORDS: (u8, u8) = (2, 25);
+    pub const SPECIAL_INSTRUCTIONS: (u8, u8) = (2, 40);
+    pub const DATE_CREATED: (u8, u8) = (2, 55);
+    pub const TIME_CREATED: (u8, u8) = (2, 60);
+    pub const BY_LINE: (u8, u8) = (2, 80);
+    pub const BY_LINE_TITLE: (u8, u8) = (2, 85);
+    pub const CITY: (u8, u8) = (2, 90);
+    pub const SUB_LOCATION: (u8, u8) = (2, 92);
+    pub const PROVINCE_STATE: (u8, u8) = (2, 95);
+    pub const COUNTRY_CODE: (u8, u8) = (2, 100);
+    pub const COUNTRY: (u8, u8) = (2, 101);
+    pub const ORIGINAL_TRANSMISSION_REFERENCE: (u8, u8) = (2, 103);
+    pub const HEADLINE: (u8, u8) = (2, 105);
+    pub const CREDIT: (u8, u8) = (2, 110);
+    pub const SOURCE: (u8, u8) = (2, 115);
+    pub const COPYRIGHT_NOTICE: (u8, u8) = (2, 116);
+    pub const CAPTION: (u8, u8) = (2, 120);
+    pub const WRITER: (u8, u8) = (2, 122);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSet {
+    pub record: u8,
+    pub dataset: u8,
+    pub data: Vec<u8>,
+}
+
+/// top level data structure representing IPTC IIM records, in file order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Iptc {
+    pub datasets: Vec<DataSet>,
+}
+
+impl Iptc {
+    pub fn parse(d: &[u8]) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut datasets = vec![];
+        let mut pos = 0;
+        // some writers pad the records with zeros
+        while pos < d.len() && d[pos] != 0 {
+            if d[pos] != 0x1C || pos + 5 > d.len() {
+                return Err(invalid("invalid IPTC dataset marker"));
+            }
+            let (record, dataset) = (d[pos + 1], d[pos + 2]);
+            let mut len = BigEndian::read_u16(&d[pos + 3..]) as usize;
+            pos += 5;
+            // extended datasets: the low bits give the size of the length field
+            if len & 0x8000 != 0 {
+                let n = len & 0x7FFF;
+                if n == 0 || n > 4 || pos + n > d.len() {
+                    return Err(invalid("invalid IPTC extended dataset length"));
+                }
+                len = BigEndian::read_uint(&d[pos..], n) as usize;
+                pos += n;
+            }
+            let data = try!(d.get(pos..pos + len).ok_or(invalid("truncated IPTC dataset")));
+            datasets.push(DataSet{ record: record, dataset: dataset, data: data.to_vec() });
+            pos += len;
+        }
+        Ok(Iptc{ datasets: datasets })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = vec![];
+        for ds in &self.datasets {
+            d.extend_from_slice(&[0x1C, ds.record, ds.dataset]);
+            if ds.data.len() < 0x8000 {
+                d.extend_from_slice(&(ds.data.len() as u16).to_be_bytes());
+            } else {
+                d.extend_from_slice(&[0x80, 4]);
+                d.extend_from_slice(&(ds.data.len() as u32).to_be_bytes());
+            }
+            d.extend_from_slice(&ds.data);
+        }
+        d
+    }
+
+    /// true if CodedCharacterSet declares UTF-8
+    pub fn is_utf8(&self) -> bool {
+        self.raw(tag::CODED_CHARACTER_SET).next() == Some(UTF8)
+    }
+
+    /// the data of every dataset with the given id
+    pub fn raw(&self, id: (u8, u8)) -> impl Iterator<Item = &[u8]> {
+        self.datasets.iter().filter(move |d| (d.record, d.dataset) == id).map(|d| &d.data[..])
+    }
+
+    /// the first value of a text dataset
+    pub fn get(&self, id: (u8, u8)) -> Option<String> {
+        self.raw(id).next().map(|d| self.decode(d))
+    }
+
+    /// every value of a repeatable text dataset, such as keywords
+    pub fn get_all(&self, id: (u8, u8)) -> Vec<String> {
+        self.raw(id).map(|d| self.decode(d)).collect()
+    }
+
+    /// replace all values of a text dataset. non-ASCII text switches the
+    /// records to UTF-8, re-encoding any legacy text.
+    pub fn set(&mut self, id: (u8, u8), values: &[&str]) {
+        if !self.is_utf8() && values.iter().any(|v| !v.is_ascii()) {
+            self.convert_to_utf8();
+        }
+        let pos = self.position(id);
+        self.remove(id);
+        for (i, v) in values.iter().enumerate() {
+            self.datasets.insert(pos + i, DataSet{ record: id.0, dataset: id.1, data: v.as_bytes().to_vec() });
+        }
+    }
+
+    pub fn remove(&mut self, id: (u8, u8)) {
+        self.datasets.retain(|d| (d.record, d.dataset) != id);
+    }
+
+    // text is UTF-8 if declared, or failing that if valid, and otherwise Latin-1
+    fn decode(&self, d: &[u8]) -> String {
+        if self.is_utf8() {
+            return String::from_utf8_lossy(d).into_owned();
+        }
+        match str::from_utf8(d) {
+            Ok(s) => s.to_string(),
+            Err(_) => d.iter().map(|&c| c as char).collect(),
+        }
+    }
+
+    fn convert_to_utf8(&mut self) {
+        for i in 0..self.datasets.len() {
+            if is_text(self.datasets[i].record, self.datasets[i].dataset) {
+                let s = self.decode(&self.datasets[i].data);
+                self.datasets[i].data = s.into_bytes();
+            }
+        }
+        self.remove(tag::CODED_CHARACTER_SET);
+        let pos = self.position(tag::CODED_CHARACTER_SET);
+        self.datasets.insert(pos, DataSet{ record: 1, dataset: 90, data: UTF8.to_vec() });
+    }
+
+    // where a dataset belongs: in place of existing ones, or else after
+    // those of lower ids, since records must be in ascending order
+    fn position(&self, id: (u8, u8)) -> usize {
+        self.datasets.iter().position(|d| (d.record, d.dataset) == id)
+            .unwrap_or_else(|| self.datasets.iter().take_while(|d| (d.record, d.dataset) <= id).count())
+    }
+}
+
+// datasets holding text, rather than binary values
+fn is_text(record: u8, dataset: u8) -> bool {
+    match (record, dataset) {
+        (1, 0) | (1, 20) | (1, 22) | (1, 90) => false,
+        (1, _) => true,
+        // record version, rasterized caption, preview data
+        (2, 0) | (2, 125) | (2, 200..=202) => false,
+        (2, _) => true,
+        _ => false,
+    }
+}
+
+/// read the IPTC records from a jpeg file
+pub fn read_jpeg<R: Read>(rdr: &mut R) -> io::Result<Option<Iptc>> {
+    match try!(photoshop::read_jpeg(rdr)).as_ref().and_then(|r| r.iptc()) {
+        Some(d) => Iptc::parse(d).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// copy a jpeg file, replacing or removing its IPTC records. other
+/// Photoshop image resources in APP13 are preserved, and the IPTC digest updated.
+pub fn write_jpeg<R: Read, W: Write>(rdr: &mut R, w: &mut W, iptc: Option<&Iptc>) -> io::Result<()> {
+    photoshop::write_jpeg(rdr, w, |r| r.set_iptc(iptc.map(|i| i.encode())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn datasets_and_charsets() {
+        let d = b"\x1c\x02\x00\x00\x02\x00\x04\x1c\x02\x19\x00\x03sea\x1c\x02\x19\x00\x04caf\xe9\x1c\x02\x78\x80\x02\x00\x05hello\0\0";
+        let mut i = Iptc::parse(d).expect("parse iptc");
+        assert_eq!(i.datasets.len(), 4);
+        assert!(!i.is_utf8());
+        assert_eq!(i.get_all(tag::KEYWORDS), vec!["sea", "café"]);
+        assert_eq!(i.get(tag::CAPTION), Some("hello".to_string()));
+        assert!(Iptc::parse(b"\x1c\x02\x19\x00\x09short").is_err());
+
+        // ASCII keeps the legacy encoding, anything else converts to UTF-8
+        i.set(tag::BY_LINE, &["Jane Doe"]);
+        assert!(!i.is_utf8());
+        i.set(tag::CITY, &["Zürich"]);
+        assert!(i.is_utf8());
+        assert_eq!(i.datasets[0], DataSet{ record: 1, dataset: 90, data: UTF8.to_vec() });
+        assert_eq!(i.raw(tag::KEYWORDS).nth(1), Some(&b"caf\xc3\xa9"[..]));
+        assert_eq!(i.raw(tag::RECORD_VERSION).next(), Some(&[0u8, 4][..]));
+
+        let j = Iptc::parse(&i.encode()).expect("reparse iptc");
+        assert_eq!(j, i);
+        let ids: Vec<_> = j.datasets.iter().map(|d| (d.record, d.dataset)).collect();
+        assert_eq!(ids, vec![(1, 90), (2, 0), (2, 25), (2, 25), (2, 80), (2, 90), (2, 120)]);
+    }
+
+    #[test]
+    fn jpeg_app13() {
+        let mut resources = photoshop::ImageResources::default();
+        resources.set(photoshop::id::RESOLUTION_INFO, vec![0, 0x48, 0, 0, 0, 1, 0, 1, 0, 0x48, 0, 0, 0, 1, 0, 1]);
+        resources.set(photoshop::id::IPTC, vec![0x1C, 2, 5, 0, 1, b'x']);
+        let mut irb = resources.encode();
+        let mut f = vec![0xFF, 0xD8, 0xFF, 0xED];
+        f.extend_from_slice(&((irb.len() + 16) as u16).to_be_bytes());
+        f.extend_from_slice(photoshop::PHOTOSHOP_HEADER);
+        f.append(&mut irb);
+        f.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+        let i = read_jpeg(&mut Cursor::new(&f)).expect("read iptc").expect("iptc present");
+        assert_eq!(i.get(tag::OBJECT_NAME), Some("x".to_string()));
+
+        let mut n = Iptc::default();
+        n.set(tag::KEYWORDS, &["a", "b"]);
+        let mut out = vec![];
+        write_jpeg(&mut Cursor::new(&f), &mut out, Some(&n)).expect("write iptc");
+        assert_eq!(read_jpeg(&mut Cursor::new(&out)).expect("reread iptc"), Some(n));
+
+        let resources = photoshop::read_jpeg(&mut Cursor::new(&out)).expect("resources").expect("resources present");
+        assert_eq!(resources.resources[0].id, photoshop::id::RESOLUTION_INFO);
+        assert_eq!(resources.resources[0].data.len(), 16);
+        assert!(resources.iptc_digest().is_some());
+
+        let mut stripped = vec![];
+        write_jpeg(&mut Cursor::new(&out), &mut stripped, None).expect("remove iptc");
+        assert_eq!(read_jpeg(&mut Cursor::new(&stripped)).expect("read stripped"), None);
+        assert_eq!(photoshop::read_jpeg(&mut Cursor::new(&stripped)).expect("resources").expect("resources present").resources.len(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+// https://www.iso.org/standard/85253.html (ISO/IEC 18181-2)
+//
+// a JPEG XL file is either a bare codestream, or an ISO base media file
+// starting with a signature box. metadata lives in "Exif" and "xml " boxes,
+// either of which may be Brotli compressed inside a "brob" box.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::Cursor;
+
+use bmff;
+use bmff::FourCC;
+use exif::Exif;
+use heif;
+
+/// the signature box which starts every container
+pub const SIGNATURE: [u8; 12] = [0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+pub const CODESTREAM_SIGNATURE: [u8; 2] = [0xFF, 0x0A];
+
+/// the payload of a single box
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxData {
+    pub kind: FourCC,
+    pub data: Vec<u8>,
+}
+
+/// top level data structure representing a JPEG XL file
+#[derive(Clone, Debug)]
+pub struct Jxl {
+    /// all boxes, including the signature and ftyp. a bare codestream is
+    /// represented as a container holding a single jxlc box.
+    pub boxes: Vec<BoxData>,
+    /// false if the file was read as, and will be written as, a bare codestream
+    pub container: bool,
+}
+
+/// return true if the reader appears to contain a JPEG XL file
+pub fn is_jxl<R: Read>(rdr: &mut R) -> bool {
+    let mut h = [0u8; 12];
+    match rdr.read_exact(&mut h[..2]) {
+        Ok(()) if h[..2] == CODESTREAM_SIGNATURE => return true,
+        Ok(()) => {}
+        Err(_) => return false,
+    }
+    rdr.read_exact(&mut h[2..]).is_ok() && h == SIGNATURE
+}
+
+impl Jxl {
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let mut data = vec![];
+        try!(rdr.read_to_end(&mut data));
+
+        if data.starts_with(&CODESTREAM_SIGNATURE) {
+            let mut j = Jxl{ boxes: vec![BoxData{ kind: *b"jxlc", data: data }], container: false };
+            j.add_container_boxes();
+            return Ok(j);
+        }
+        if !data.starts_with(&SIGNATURE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a JPEG XL file"));
+        }
+
+        let mut c = Cursor::new(&data[..]);
+        let headers = try!(bmff::read_boxes(&mut c, 0, data.len() as u64));
+        let mut boxes = Vec::with_capacity(headers.len());
+        for h in headers {
+            boxes.push(BoxData{ kind: h.kind, data: try!(h.read_data(&mut c)) });
+        }
+        Ok(Jxl{ boxes: boxes, container: true })
+    }
+
+    pub fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        if !self.container {
+            let cs = try!(self.boxes.iter().find(|b| &b.kind == b"jxlc")
+                          .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "missing jxlc box")));
+            return w.write_all(&cs.data);
+        }
+        for b in &self.boxes {
+            try!(bmff::write_box(w, &b.kind, &b.data));
+        }
+        Ok(())
+    }
+
+    /// the contents of the first box of the given kind, decompressing brob boxes as needed
+    pub fn read_box(&self, kind: &FourCC) -> Option<io::Result<Vec<u8>>> {
+        for b in &self.boxes {
+            if &b.kind == kind {
+                return Some(Ok(b.data.clone()));
+            }
+            if &b.kind == b"brob" && b.data.len() >= 4 && &b.data[..4] == kind {
+                return Some(decompress(&b.data[4..]));
+            }
+        }
+        None
+    }
+
+    pub fn exif(&self) -> Option<io::Result<Exif>> {
+        Some(self.read_box(b"Exif")?.and_then(|d| {
+            let tiff = try!(heif::exif_tiff(&d));
+            Exif::new(&mut Cursor::new(tiff))
+        }))
+    }
+
+    pub fn xmp(&self) -> Option<io::Result<Vec<u8>>> {
+        self.read_box(b"xml ")
+    }
+
+    pub fn set_exif(&mut self, exif: &Exif) -> io::Result<()> {
+        let mut c = Cursor::new(vec![0, 0, 0, 0]);
+        c.set_position(4);
+        try!(exif.encode(&mut c));
+        self.set_box(*b"Exif", c.into_inner(), false)
+    }
+
+    pub fn set_xmp(&mut self, xmp: &[u8]) -> io::Result<()> {
+        self.set_box(*b"xml ", xmp.to_vec(), false)
+    }
+
+    pub fn remove_exif(&mut self) {
+        self.remove_box(b"Exif");
+    }
+
+    pub fn remove_xmp(&mut self) {
+        self.remove_box(b"xml ");
+    }
+
+    /// replace or add a metadata box before the codestream, optionally Brotli
+    /// compressed. a bare codestream is converted to the container format.
+    pub fn set_box(&mut self, kind: FourCC, data: Vec<u8>, compress: bool) -> io::Result<()> {
+        let b = if compress {
+            let mut d = kind.to_vec();
+            d.extend(try!(self::compress(&data)));
+            BoxData{ kind: *b"brob", data: d }
+        } else {
+            BoxData{ kind: kind, data: data }
+        };
+
+        self.container = true;
+        let pos = self.boxes.iter().position(|b| is_kind(b, &kind))
+            .or_else(|| self.boxes.iter().position(|b| &b.kind == b"jxlc" || &b.kind == b"jxlp"))
+            .unwrap_or(self.boxes.len());
+        self.remove_box(&kind);
+        self.boxes.insert(pos, b);
+        Ok(())
+    }
+
+    /// remove all boxes of the given kind, compressed or not
+    pub fn remove_box(&mut self, kind: &FourCC) {
+        self.boxes.retain(|b| !is_kind(b, kind));
+    }
+
+    // signature and ftyp boxes, required before any other
+    fn add_container_boxes(&mut self) {
+        if self.boxes.first().map(|b| &b.kind) != Some(b"JXL ") {
+            self.boxes.insert(0, BoxData{ kind: *b"JXL ", data: SIGNATURE[8..].to_vec() });
+        }
+        if self.boxes.get(1).map(|b| &b.kind) != Some(b"ftyp") {
+            self.boxes.insert(1, BoxData{ kind: *b"ftyp", data: b"jxl \0\0\0\0jxl ".to_vec() });
+        }
+    }
+}
+
+fn is_kind(b: &BoxData, kind: &FourCC) -> bool {
+    &b.kind == kind || (&b.kind == b"brob" && b.data.len() >= 4 && &b.data[..4] == kind)
+}
+
+#[cfg(feature = "brotli")]
+fn decompress(d: &[u8]) -> io::Result<Vec<u8>> {
+    let mut v = vec![];
+    try!(::brotli::Decompressor::new(d, 4096).read_to_end(&mut v));
+    Ok(v)
+}
+
+#[cfg(feature = "brotli")]
+fn compress(d: &[u8]) -> io::Result<Vec<u8>> {
+    let mut w = ::brotli::CompressorWriter::new(vec![], 4096, 9, 22);
+    try!(w.write_all(d));
+    Ok(w.into_inner())
+}
+
+#[cfg(not(feature = "brotli"))]
+fn decompress(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "brob boxes require the \"brotli\" feature"))
+}
+
+#[cfg(not(feature = "brotli"))]
+fn compress(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "brob boxes require the \"brotli\" feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use exif::tag;
+    use exif::tests::{exif_with, roundtrip};
+
+    const CODESTREAM: &[u8] = &[0xFF, 0x0A, 0xFA, 0x1F, 0x01];
+
+    #[test]
+    fn bare_codestream() {
+        let j = Jxl::new(&mut Cursor::new(CODESTREAM)).expect("read codestream");
+        assert!(!j.container);
+        let mut buf = vec![];
+        j.encode(&mut buf).expect("encode codestream");
+        assert_eq!(buf, CODESTREAM.to_vec());
+
+        let mut j = j;
+        j.set_exif(&exif_with(&[(tag::IMG_DESCRIPTION, "jxl")])).expect("set exif");
+        j.set_xmp(b"<x:xmpmeta/>").expect("set xmp");
+        let j = roundtrip(&j, Jxl::encode, |c| {
+            assert!(is_jxl(c));
+            c.set_position(0);
+            Jxl::new(c)
+        });
+        assert!(j.container);
+        assert_eq!(j.boxes.iter().map(|b| b.kind).collect::<Vec<_>>(), vec![*b"JXL ", *b"ftyp", *b"Exif", *b"xml ", *b"jxlc"]);
+        assert_eq!(j.boxes[4].data, CODESTREAM.to_vec());
+
+        let e = j.exif().expect("exif").expect("decode exif");
+        assert_eq!(e.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("jxl"));
+        assert_eq!(j.xmp().expect("xmp").expect("read xmp"), b"<x:xmpmeta/>".to_vec());
+
+        let mut j = j;
+        j.remove_exif();
+        assert!(j.exif().is_none());
+    }
+
+    #[test]
+    fn exif_offset_prefix() {
+        let mut j = Jxl::new(&mut Cursor::new(CODESTREAM)).expect("read codestream");
+        let mut d = vec![0, 0, 0, 6];
+        d.extend_from_slice(b"Exif\0\0");
+        d.extend(heif::encode_exif_item(&exif_with(&[(tag::IMG_DESCRIPTION, "jxl")])).expect("encode exif")[10..].to_vec());
+        j.set_box(*b"Exif", d, false).expect("set box");
+        assert!(j.exif().expect("exif").is_ok());
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_boxes() {
+        let mut j = Jxl::new(&mut Cursor::new(CODESTREAM)).expect("read codestream");
+        let xmp = b"<x:xmpmeta>                                  </x:xmpmeta>".to_vec();
+        j.set_box(*b"xml ", xmp.clone(), true).expect("set compressed xmp");
+        let j = roundtrip(&j, Jxl::encode, Jxl::new);
+        assert_eq!(j.boxes[2].kind, *b"brob");
+        assert_eq!(j.xmp().expect("xmp").expect("decompress xmp"), xmp);
+
+        let mut j = j;
+        j.set_xmp(b"plain").expect("replace xmp");
+        assert_eq!(j.boxes[2].kind, *b"xml ");
+        assert_eq!(j.boxes.len(), 4);
+    }
+}
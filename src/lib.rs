@@ -1,4 +1,25 @@
-
 extern crate byteorder;
+#[cfg(feature = "deflate")]
+extern crate flate2;
+#[cfg(feature = "brotli")]
+extern crate brotli;
 
 pub mod exif;
+pub mod bmff;
+pub mod tiff;
+pub mod dng;
+pub mod raw;
+pub mod png;
+pub mod webp;
+pub mod heif;
+pub mod jxl;
+pub mod quicktime;
+pub mod metadata;
+pub mod xmp;
+pub mod mwg;
+pub mod iptc;
+pub mod photoshop;
+pub mod icc;
+mod md5;
+
+pub use metadata::{read, Metadata, Format};
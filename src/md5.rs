@@ -0,0 +1,84 @@
+// https://www.ietf.org/rfc/rfc1321.txt
+//
+// used for the GUIDs of extended XMP in jpeg files and for ICC profile IDs,
+// neither of which has any security requirement.
+
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// the MD5 digest of the given data
+pub fn digest(data: &[u8]) -> [u8; 16] {
+    // K[i] = floor(abs(sin(i + 1)) * 2^32)
+    let mut k = [0u32; 64];
+    for (i, v) in k.iter_mut().enumerate() {
+        *v = (((i + 1) as f64).sin().abs() * 4294967296.0) as u32;
+    }
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in 0..8 {
+        msg.push((bits >> (8 * i)) as u8);
+    }
+
+    let mut h = [0x67452301u32, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in msg.chunks(64) {
+        let mut m = [0u32; 16];
+        for (i, w) in m.iter_mut().enumerate() {
+            let b = &block[i * 4..];
+            *w = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+        }
+
+        let (mut a, mut b, mut c, mut d) = (h[0], h[1], h[2], h[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+    }
+
+    let mut out = [0u8; 16];
+    for (i, v) in h.iter().enumerate() {
+        for j in 0..4 {
+            out[i * 4 + j] = (v >> (8 * j)) as u8;
+        }
+    }
+    out
+}
+
+/// the digest as uppercase hex, as used for extended XMP GUIDs
+pub fn hex_digest(data: &[u8]) -> String {
+    digest(data).iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_vectors() {
+        assert_eq!(hex_digest(b""), "D41D8CD98F00B204E9800998ECF8427E");
+        assert_eq!(hex_digest(b"abc"), "900150983CD24FB0D6963F7D28E17F72");
+        assert_eq!(hex_digest(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+                   "57EDF4A22BE3C955AC49DA2E2107B67A");
+    }
+}
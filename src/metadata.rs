@@ -0,0 +1,325 @@
+// format independent access to the metadata of any supported file. the
+// format is identified from its leading bytes, then the exif, XMP, IPTC and
+// ICC blocks are extracted in whatever way that format stores them.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use exif::{Exif, EntryData};
+use exif::jpeg::{self, EXIF_HEADER, XMP_HEADER, XMP_EXTENSION_HEADER};
+use exif::tag;
+use heif::Heif;
+use icc;
+use jxl::{self, Jxl};
+use photoshop::ImageResources;
+use png::{self, Png};
+use raw;
+use webp::WebP;
+
+const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// the kind of file metadata was read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Jpeg,
+    /// TIFF or BigTIFF
+    Tiff,
+    Dng,
+    Png,
+    WebP,
+    Heif,
+    Avif,
+    Jxl,
+    Raw(raw::Format),
+}
+
+/// the metadata blocks found in a file. xmp, iptc and icc are returned
+/// as stored, as an XMP packet, IPTC IIM records and an ICC profile.
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub format: Format,
+    pub exif: Option<Exif>,
+    pub xmp: Option<Vec<u8>>,
+    pub iptc: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    fn new(format: Format) -> Self {
+        Metadata{ format: format, exif: None, xmp: None, iptc: None, icc: None }
+    }
+}
+
+/// identify the format of a file and read all the metadata it contains.
+/// blocks which can't be decoded without an optional feature are skipped.
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    let mut h = vec![];
+    try!(rdr.seek(SeekFrom::Start(0)));
+    try!(rdr.by_ref().take(16).read_to_end(&mut h));
+    try!(rdr.seek(SeekFrom::Start(0)));
+
+    if h.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg(rdr)
+    } else if h.starts_with(&png::SIGNATURE) {
+        read_png(rdr)
+    } else if h.len() >= 12 && &h[..4] == b"RIFF" && &h[8..12] == b"WEBP" {
+        read_webp(rdr)
+    } else if h.starts_with(&jxl::CODESTREAM_SIGNATURE) || h.starts_with(&jxl::SIGNATURE) {
+        read_jxl(rdr)
+    } else if h.starts_with(b"II") || h.starts_with(b"MM") || h.starts_with(b"FUJIFILM") {
+        read_tiff(rdr)
+    } else if h.len() >= 8 && &h[4..8] == b"ftyp" {
+        read_bmff(rdr)
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognised file format"))
+    }
+}
+
+fn read_jpeg<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    let mut m = Metadata::new(Format::Jpeg);
+
+    // a block which can't be decoded is treated as absent, so that the others are still returned
+    let segments = try!(jpeg::read_segments(rdr));
+    for s in &segments {
+        match s.marker {
+            0xE1 if s.data.starts_with(EXIF_HEADER) && m.exif.is_none() => {
+                m.exif = Exif::new(&mut Cursor::new(&s.data[EXIF_HEADER.len()..])).ok();
+            }
+            0xE1 if s.data.starts_with(XMP_HEADER) && m.xmp.is_none() => {
+                m.xmp = Some(s.data[XMP_HEADER.len()..].to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    // profiles larger than a segment are split, each chunk numbered from 1
+    m.icc = icc::from_jpeg_segments(&segments).ok().and_then(|d| d);
+    m.iptc = ImageResources::from_segments(&segments).ok().and_then(|r| r).and_then(|r| r.iptc().map(|d| d.to_vec()));
+    // extended XMP is merged into a single packet
+    if segments.iter().any(|s| s.marker == 0xE1 && s.data.starts_with(XMP_EXTENSION_HEADER)) {
+        if let Ok(Some(x)) = jpeg::xmp_from_segments(&segments) {
+            let mut buf = vec![];
+            try!(x.encode(&mut buf, 0));
+            m.xmp = Some(buf);
+        }
+    }
+    Ok(m)
+}
+
+fn read_png<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    let p = try!(Png::new(rdr));
+    let mut m = Metadata::new(Format::Png);
+    m.exif = try!(optional(p.exif()));
+    m.icc = try!(optional(p.icc_profile()));
+    // unrelated text chunks are never decoded, so can't cause an error
+    if let Some(c) = p.chunks.iter().find(|c| png::text_keyword(c).as_deref() == Some(PNG_XMP_KEYWORD)) {
+        if let Some(t) = try!(optional(png::Text::from_chunk(c).transpose())) {
+            m.xmp = Some(t.text.into_bytes());
+        }
+    }
+    Ok(m)
+}
+
+fn read_webp<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    let w = try!(WebP::new(rdr));
+    let mut m = Metadata::new(Format::WebP);
+    m.exif = try!(w.exif().transpose());
+    m.xmp = w.xmp().map(|x| x.to_vec());
+    m.icc = w.icc_profile().map(|d| d.to_vec());
+    Ok(m)
+}
+
+fn read_jxl<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    // the ICC profile, if any, is entropy coded within the codestream
+    let j = try!(Jxl::new(rdr));
+    let mut m = Metadata::new(Format::Jxl);
+    m.exif = try!(optional(j.exif()));
+    m.xmp = try!(optional(j.xmp()));
+    Ok(m)
+}
+
+fn read_bmff<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    if raw::cr3::is_cr3(rdr) {
+        return read_tiff(rdr);
+    }
+    let h = try!(Heif::new(rdr));
+    let avif = h.brands.iter().take(1).any(|b| b == b"avif" || b == b"avis");
+    let mut m = Metadata::new(if avif { Format::Avif } else { Format::Heif });
+    m.exif = try!(h.exif(rdr).transpose());
+    m.xmp = try!(h.xmp(rdr).transpose());
+    m.icc = try!(h.icc_profile(rdr).transpose());
+    Ok(m)
+}
+
+// TIFF based files, including BigTIFF, DNG and raw formats
+fn read_tiff<R: Read + Seek>(rdr: &mut R) -> io::Result<Metadata> {
+    // DNG files carry the Make of the camera, so must be identified before raw formats
+    let exif = Exif::new(rdr).ok();
+    let dng = exif.as_ref().and_then(|e| e.ifds.first()).and_then(|ifd| ifd.entry(tag::dng::DNG_VERSION)).is_some();
+    let (format, exif) = match (dng, exif) {
+        (true, Some(e)) => (Format::Dng, e),
+        (_, exif) => match raw::read(rdr) {
+            Ok(r) => (Format::Raw(r.format), r.exif),
+            Err(e) => (Format::Tiff, try!(exif.ok_or(e))),
+        },
+    };
+
+    let mut m = Metadata::new(format);
+    if let Some(ifd) = exif.ifds.first() {
+        let bytes = |t| ifd.entry(t).and_then(|e| entry_bytes(&e.data, exif.big_endian));
+        m.xmp = bytes(tag::XMP);
+        m.iptc = bytes(tag::IPTC);
+        m.icc = bytes(tag::ICC_PROFILE);
+    }
+    m.exif = Some(exif);
+    Ok(m)
+}
+
+// the data of an entry holding an opaque block. IPTC is often stored as
+// Long values, so must be reassembled in the document's byte order.
+fn entry_bytes(d: &EntryData, big_endian: bool) -> Option<Vec<u8>> {
+    match *d {
+        EntryData::Byte(ref v) | EntryData::Undef(ref v) => Some(v.clone()),
+        EntryData::ULong(ref v) => {
+            let mut b = vec![];
+            for &x in v {
+                if big_endian { b.write_u32::<BigEndian>(x).ok()?; }
+                else          { b.write_u32::<LittleEndian>(x).ok()?; }
+            }
+            Some(b)
+        }
+        _ => None,
+    }
+}
+
+// treat blocks which need a disabled feature as absent
+fn optional<T>(r: Option<io::Result<T>>) -> io::Result<Option<T>> {
+    match r {
+        Some(Err(ref e)) if e.kind() == io::ErrorKind::Unsupported => Ok(None),
+        r => r.transpose(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Cursor;
+    use exif::EntryData;
+    use exif::tag;
+    use exif::tests::exif_with;
+    use heif;
+    use png;
+    use webp::{WebP, Chunk};
+    use photoshop::PHOTOSHOP_HEADER;
+
+    fn segment(marker: u8, header: &[u8], data: &[u8]) -> Vec<u8> {
+        let len = 2 + header.len() + data.len();
+        let mut v = vec![0xFF, marker, (len >> 8) as u8, len as u8];
+        v.extend_from_slice(header);
+        v.extend_from_slice(data);
+        v
+    }
+
+    #[test]
+    fn jpeg_blocks() {
+        let mut tiff = Cursor::new(vec![]);
+        exif_with(&[(tag::MAKE, "Canon")]).encode(&mut tiff).expect("encode exif");
+
+        let mut irb = b"8BIM\x03\xed\0\0\0\0\0\x01\x01\x00".to_vec();
+        irb.extend_from_slice(b"8BIM\x04\x04\x03abc\0\0\0\x05\x1c\x02\x00\x00\x00\x00");
+
+        let jpeg = |icc: &[&[u8]]| {
+            let mut f = vec![0xFF, 0xD8];
+            f.extend(segment(0xE1, EXIF_HEADER, tiff.get_ref()));
+            f.extend(segment(0xE1, XMP_HEADER, b"<x:xmpmeta/>"));
+            for s in icc {
+                f.extend(segment(0xE2, b"ICC_PROFILE\0", s));
+            }
+            f.extend(segment(0xED, PHOTOSHOP_HEADER, &irb));
+            f.extend_from_slice(&[0xFF, 0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+            f
+        };
+
+        let m = read(&mut Cursor::new(jpeg(&[b"\x02\x02def", b"\x01\x02abc"]))).expect("read jpeg");
+        assert_eq!(m.format, Format::Jpeg);
+        assert_eq!(m.exif.expect("exif").make(), Some("Canon"));
+        assert_eq!(m.xmp, Some(b"<x:xmpmeta/>".to_vec()));
+        assert_eq!(m.icc, Some(b"abcdef".to_vec()));
+        assert_eq!(m.iptc, Some(vec![0x1c, 2, 0, 0, 0]));
+
+        // a profile missing a chunk doesn't hide the other blocks
+        let m = read(&mut Cursor::new(jpeg(&[b"\x01\x02abc"]))).expect("read jpeg with broken profile");
+        assert_eq!((m.icc, m.xmp.is_some(), m.iptc.is_some()), (None, true, true));
+    }
+
+    #[test]
+    fn jpeg_corrupt_blocks() {
+        let mut tiff = Cursor::new(vec![]);
+        exif_with(&[(tag::MAKE, "Canon")]).encode(&mut tiff).expect("encode exif");
+        let tiff = tiff.into_inner();
+
+        let mut f = vec![0xFF, 0xD8];
+        f.extend(segment(0xE1, EXIF_HEADER, &tiff[..12]));
+        f.extend(segment(0xE1, XMP_HEADER, b"<x:xmpmeta/>"));
+        f.extend(segment(0xED, PHOTOSHOP_HEADER, b"8BIM\x04\x04\0\0\0\0\0\x09ab"));
+        f.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+        let m = read(&mut Cursor::new(f)).expect("read jpeg with corrupt exif");
+        assert!(m.exif.is_none() && m.iptc.is_none());
+        assert_eq!(m.xmp, Some(b"<x:xmpmeta/>".to_vec()));
+    }
+
+    #[test]
+    fn sniff_formats() {
+        let mut e = exif_with(&[(tag::MAKE, "Canon")]);
+        e.big_endian = true;
+        e.ifd0_mut().set(tag::XMP, EntryData::Byte(b"<x:xmpmeta/>".to_vec()));
+        let mut tiff = Cursor::new(vec![]);
+        e.encode(&mut tiff).expect("encode exif");
+        let m = read(&mut Cursor::new(tiff.into_inner())).expect("read tiff");
+        assert_eq!(m.format, Format::Tiff);
+        assert_eq!(m.xmp, Some(b"<x:xmpmeta/>".to_vec()));
+
+        // a camera maker's Make alone doesn't make a raw file
+        let mut nikon = e;
+        nikon.ifd0_mut().set(tag::MAKE, EntryData::Ascii("NIKON CORPORATION".to_string()));
+        let mut tiff = Cursor::new(vec![]);
+        nikon.encode(&mut tiff).expect("encode exif");
+        assert_eq!(read(&mut Cursor::new(tiff.into_inner())).expect("read tiff").format, Format::Tiff);
+
+        // 3x2 lossless image, truncated after the header
+        let mut w = WebP{ chunks: vec![Chunk{ kind: *b"VP8L", data: vec![0x2F, 0x02, 0x40, 0x00, 0x10] }] };
+        w.set_xmp(b"<x/>").expect("set xmp");
+        let mut buf = vec![];
+        w.encode(&mut buf).expect("encode webp");
+        let m = read(&mut Cursor::new(buf)).expect("read webp");
+        assert_eq!((m.format, m.xmp), (Format::WebP, Some(b"<x/>".to_vec())));
+
+        let m = read(&mut Cursor::new(heif::tests::sample())).expect("read heif");
+        assert_eq!(m.format, Format::Heif);
+        assert!(m.exif.is_some() && m.xmp.is_some());
+
+        let m = read(&mut File::open("src/fixtures/IMG_2222.JPG").expect("open jpeg")).expect("read jpeg");
+        assert_eq!(m.format, Format::Jpeg);
+        assert!(m.exif.is_some());
+
+        assert!(read(&mut Cursor::new(b"GIF89a")).is_err());
+    }
+
+    #[test]
+    fn png_xmp_beside_bad_text() {
+        let mut p = png::tests::sample();
+        p.chunks.insert(1, png::Chunk{ kind: *b"iTXt", data: b"Comment\0\0".to_vec() });
+        p.add_text(&png::Text{ keyword: PNG_XMP_KEYWORD.to_string(), text: "<x:xmpmeta/>".to_string(), compressed: false, international: Some(("".to_string(), "".to_string())) }).expect("add xmp");
+        let mut buf = vec![];
+        p.encode(&mut buf).expect("encode png");
+
+        let m = read(&mut Cursor::new(buf)).expect("read png");
+        assert_eq!(m.format, Format::Png);
+        assert_eq!(m.xmp, Some(b"<x:xmpmeta/>".to_vec()));
+    }
+}
@@ -0,0 +1,436 @@
+// http://www.metadataworkinggroup.org/specs/ (Guidelines for Handling Image Metadata 2.0)
+//
+// reconciliation of values stored in both exif and XMP. exif is preferred
+// when the two disagree, unless digests of the exif stored in the XMP show
+// that it was written from the exif as it is now, in which case the XMP's
+// unicode and time zone aware values are used.
+//
+// the digests are in a private namespace: Adobe's tiff:NativeDigest and
+// exif:NativeDigest use a different algorithm, and are left untouched.
+
+use std::str;
+
+use exif::{Exif, Ifd, Entry, EntryData, DateTime, GpsPosition, tag};
+use md5;
+use xmp::{Xmp, Property, Value, ArrayKind, ns};
+
+// tags covered by imagemeta:TiffDigest, from IFD0
+const TIFF_DIGEST_TAGS: &[u16] = &[tag::IMG_DESCRIPTION, tag::ARTIST, tag::COPYRIGHT];
+// tags covered by imagemeta:ExifDigest, from the Exif IFD then the GPS IFD
+const EXIF_DIGEST_TAGS: &[u16] = &[tag::DATE_TIME_ORIGINAL, tag::OFFSET_TIME_ORIGINAL, tag::USER_COMMENT];
+const GPS_DIGEST_TAGS: &[u16] = &[tag::gps::LATITUDE_REF, tag::gps::LATITUDE, tag::gps::LONGITUDE_REF,
+                                  tag::gps::LONGITUDE, tag::gps::ALTITUDE_REF, tag::gps::ALTITUDE];
+
+/// values which the guidelines reconcile between exif and XMP
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Values {
+    /// exif DateTimeOriginal and OffsetTimeOriginal, XMP exif:DateTimeOriginal
+    pub date_time_original: Option<DateTime>,
+    /// exif Artist, split on semicolons, XMP dc:creator
+    pub creator: Vec<String>,
+    /// exif Copyright, XMP dc:rights
+    pub copyright: Option<String>,
+    /// exif ImageDescription, XMP dc:description
+    pub description: Option<String>,
+    /// exif UserComment, XMP exif:UserComment
+    pub user_comment: Option<String>,
+    /// the exif GPS IFD, XMP exif:GPSLatitude etc.
+    pub gps: Option<GpsPosition>,
+}
+
+impl Values {
+    pub fn from_exif(exif: &Exif) -> Self {
+        let ifd0 = exif.ifds.first();
+        let ascii = |t| ifd0.and_then(|i| i.ascii(t)).map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string());
+        let user_comment = match child(exif, tag::EXIF_IFD_POINTER).and_then(|i| i.entry(tag::USER_COMMENT)) {
+            Some(&Entry{ data: EntryData::Undef(ref d), .. }) => decode_user_comment(d, exif.big_endian),
+            _ => None,
+        };
+        Values{
+            date_time_original: exif.date_time_original(),
+            creator: ascii(tag::ARTIST).map(|a| split_artist(&a)).unwrap_or_default(),
+            copyright: ascii(tag::COPYRIGHT),
+            description: ascii(tag::IMG_DESCRIPTION),
+            user_comment: user_comment,
+            gps: exif.gps(),
+        }
+    }
+
+    pub fn from_xmp(xmp: &Xmp) -> Self {
+        let creator = match xmp.property(ns::DC, "creator").map(|p| &p.value) {
+            Some(Value::Array(_, items)) => items.iter().filter_map(|i| match i.value {
+                Value::Text(ref s) if !s.is_empty() => Some(s.clone()),
+                _ => None,
+            }).collect(),
+            Some(Value::Text(s)) if !s.is_empty() => vec![s.clone()],
+            _ => vec![],
+        };
+        let x_default = |n: &str, name| xmp.lang_alt(n, name, "x-default").filter(|s| !s.is_empty()).map(|s| s.to_string());
+        Values{
+            date_time_original: xmp.text(ns::EXIF, "DateTimeOriginal").and_then(DateTime::from_iso8601),
+            creator: creator,
+            copyright: x_default(ns::DC, "rights"),
+            description: x_default(ns::DC, "description"),
+            user_comment: x_default(ns::EXIF, "UserComment"),
+            gps: xmp_gps(xmp),
+        }
+    }
+}
+
+/// reconcile the values of the exif and XMP from a single file
+pub fn read(exif: Option<&Exif>, xmp: Option<&Xmp>) -> Values {
+    let e = exif.map(Values::from_exif).unwrap_or_default();
+    let x = xmp.map(Values::from_xmp).unwrap_or_default();
+    let prefer_xmp = match (exif, xmp) {
+        (Some(e), Some(x)) => is_xmp_current(e, x),
+        _ => false,
+    };
+    fn pick<T>(e: Option<T>, x: Option<T>, prefer_xmp: bool) -> Option<T> {
+        match (e, x) {
+            (Some(_), Some(x)) if prefer_xmp => Some(x),
+            (Some(e), _) => Some(e),
+            (None, x) => x,
+        }
+    }
+
+    // an XMP date for the same local time adds the offset which older exif lacks
+    let mut date = pick(e.date_time_original, x.date_time_original, prefer_xmp);
+    if let (Some(d), Some(xd)) = (date.as_mut(), x.date_time_original) {
+        if d.offset.is_none() && *d == (DateTime{ offset: None, ..xd }) {
+            d.offset = xd.offset;
+        }
+    }
+
+    Values{
+        date_time_original: date,
+        creator: pick(Some(e.creator).filter(|c| !c.is_empty()), Some(x.creator).filter(|c| !c.is_empty()), prefer_xmp).unwrap_or_default(),
+        copyright: pick(e.copyright, x.copyright, prefer_xmp),
+        description: pick(e.description, x.description, prefer_xmp),
+        user_comment: pick(e.user_comment, x.user_comment, prefer_xmp),
+        gps: pick(e.gps, x.gps, prefer_xmp),
+    }
+}
+
+/// true if the XMP's digests match the exif, meaning it hasn't been
+/// changed by software unaware of XMP since the XMP was written
+pub fn is_xmp_current(exif: &Exif, xmp: &Xmp) -> bool {
+    let (tiff, exif_digest) = digests(exif);
+    let stored = [xmp.text(ns::IMAGEMETA, "TiffDigest"), xmp.text(ns::IMAGEMETA, "ExifDigest")];
+    stored.iter().any(|s| s.is_some()) && stored[0].iter().all(|&s| s == tiff) && stored[1].iter().all(|&s| s == exif_digest)
+}
+
+/// the imagemeta:TiffDigest and imagemeta:ExifDigest values for the given
+/// exif: the tags covered, then an MD5 digest of their values
+pub fn digests(exif: &Exif) -> (String, String) {
+    let ifd0 = exif.ifds.first();
+    let tiff = digest(TIFF_DIGEST_TAGS.iter().map(|&t| (t, ifd0.and_then(|i| i.entry(t)))).collect());
+    let exif_ifd = child(exif, tag::EXIF_IFD_POINTER);
+    let gps_ifd = child(exif, tag::GPS_INFO_IFD_POINTER);
+    let exif_digest = digest(EXIF_DIGEST_TAGS.iter().map(|&t| (t, exif_ifd.and_then(|i| i.entry(t))))
+        .chain(GPS_DIGEST_TAGS.iter().map(|&t| (t, gps_ifd.and_then(|i| i.entry(t))))).collect());
+    (tiff, exif_digest)
+}
+
+/// store values in both exif and XMP, removing those which are absent,
+/// then update the XMP's digests to match the exif
+pub fn write(v: &Values, exif: &mut Exif, xmp: &mut Xmp) {
+    let big_endian = exif.big_endian;
+
+    // IFD0 text is written as UTF-8, as the guidelines recommend
+    let ascii = |ifd: &mut Ifd, t, s: Option<&str>| match s {
+        Some(s) if !s.is_empty() => ifd.set(t, EntryData::Ascii(s.to_string())),
+        _ => { ifd.remove(t); }
+    };
+    let artist = if v.creator.is_empty() { None } else { Some(v.creator.join("; ")) };
+    let ifd0 = exif.ifd0_mut();
+    ascii(ifd0, tag::ARTIST, artist.as_ref().map(|s| &s[..]));
+    ascii(ifd0, tag::COPYRIGHT, v.copyright.as_ref().map(|s| &s[..]));
+    ascii(ifd0, tag::IMG_DESCRIPTION, v.description.as_ref().map(|s| &s[..]));
+
+    if v.date_time_original.is_some() || v.user_comment.is_some() || child(exif, tag::EXIF_IFD_POINTER).is_some() {
+        let ifd = exif.child_mut(tag::EXIF_IFD_POINTER);
+        match v.date_time_original {
+            Some(d) => {
+                ifd.set(tag::DATE_TIME_ORIGINAL, EntryData::Ascii(d.to_exif()));
+                ascii(ifd, tag::OFFSET_TIME_ORIGINAL, d.exif_offset().as_ref().map(|s| &s[..]));
+            }
+            None => {
+                ifd.remove(tag::DATE_TIME_ORIGINAL);
+                ifd.remove(tag::OFFSET_TIME_ORIGINAL);
+            }
+        }
+        match v.user_comment {
+            Some(ref c) => ifd.set(tag::USER_COMMENT, EntryData::Undef(encode_user_comment(c, big_endian))),
+            None => { ifd.remove(tag::USER_COMMENT); }
+        }
+    }
+
+    match v.gps {
+        Some(g) => {
+            let ifd = exif.child_mut(tag::GPS_INFO_IFD_POINTER);
+            if ifd.entry(tag::gps::VERSION_ID).is_none() {
+                ifd.set(tag::gps::VERSION_ID, EntryData::Byte(vec![2, 3, 0, 0]));
+            }
+            ifd.set(tag::gps::LATITUDE_REF, EntryData::Ascii((if g.latitude < 0.0 { "S" } else { "N" }).to_string()));
+            ifd.set(tag::gps::LATITUDE, EntryData::URational(dms(g.latitude)));
+            ifd.set(tag::gps::LONGITUDE_REF, EntryData::Ascii((if g.longitude < 0.0 { "W" } else { "E" }).to_string()));
+            ifd.set(tag::gps::LONGITUDE, EntryData::URational(dms(g.longitude)));
+            match g.altitude {
+                Some(a) => {
+                    ifd.set(tag::gps::ALTITUDE_REF, EntryData::Byte(vec![if a < 0.0 { 1 } else { 0 }]));
+                    ifd.set(tag::gps::ALTITUDE, EntryData::URational(vec![rational(a.abs())]));
+                }
+                None => {
+                    ifd.remove(tag::gps::ALTITUDE_REF);
+                    ifd.remove(tag::gps::ALTITUDE);
+                }
+            }
+        }
+        None => if let Some(ifd) = exif.ifds.first_mut().and_then(|i| i.children.iter_mut().find(|c| c.id == tag::GPS_INFO_IFD_POINTER)) {
+            for &t in GPS_DIGEST_TAGS {
+                ifd.remove(t);
+            }
+        },
+    }
+
+    match v.date_time_original {
+        Some(d) => xmp.set(ns::EXIF, "DateTimeOriginal", Value::Text(d.to_string())),
+        None => { xmp.remove(ns::EXIF, "DateTimeOriginal"); }
+    }
+    if v.creator.is_empty() {
+        xmp.remove(ns::DC, "creator");
+    } else {
+        let items = v.creator.iter().map(|c| Property::item(Value::Text(c.clone()))).collect();
+        xmp.set(ns::DC, "creator", Value::Array(ArrayKind::Seq, items));
+    }
+    set_x_default(xmp, ns::DC, "rights", v.copyright.as_ref());
+    set_x_default(xmp, ns::DC, "description", v.description.as_ref());
+    set_x_default(xmp, ns::EXIF, "UserComment", v.user_comment.as_ref());
+
+    for name in &["GPSLatitude", "GPSLongitude", "GPSAltitudeRef", "GPSAltitude"] {
+        xmp.remove(ns::EXIF, name);
+    }
+    if let Some(g) = v.gps {
+        xmp.set(ns::EXIF, "GPSLatitude", Value::Text(xmp_coordinate(g.latitude, 'N', 'S')));
+        xmp.set(ns::EXIF, "GPSLongitude", Value::Text(xmp_coordinate(g.longitude, 'E', 'W')));
+        if let Some(a) = g.altitude {
+            let r = rational(a.abs());
+            xmp.set(ns::EXIF, "GPSAltitudeRef", Value::Text((if a < 0.0 { "1" } else { "0" }).to_string()));
+            xmp.set(ns::EXIF, "GPSAltitude", Value::Text(format!("{}/{}", r >> 32, r as u32)));
+        }
+    }
+
+    let (tiff, exif_digest) = digests(exif);
+    xmp.set(ns::IMAGEMETA, "TiffDigest", Value::Text(tiff));
+    xmp.set(ns::IMAGEMETA, "ExifDigest", Value::Text(exif_digest));
+}
+
+/// decode a UserComment: an 8 byte character code followed by the text
+pub fn decode_user_comment(d: &[u8], big_endian: bool) -> Option<String> {
+    if d.len() < 8 {
+        return None;
+    }
+    let (code, text) = d.split_at(8);
+    let s = match code {
+        // undefined, in practice ASCII or UTF-8
+        b"ASCII\0\0\0" | b"\0\0\0\0\0\0\0\0" => match str::from_utf8(text) {
+            Ok(s) => s.to_string(),
+            Err(_) => text.iter().map(|&c| c as char).collect(),
+        },
+        // UCS-2 in the byte order of the document, though a BOM is sometimes present
+        b"UNICODE\0" => {
+            let (text, be) = if text.starts_with(&[0xFE, 0xFF]) {
+                (&text[2..], true)
+            } else if text.starts_with(&[0xFF, 0xFE]) {
+                (&text[2..], false)
+            } else {
+                (text, big_endian)
+            };
+            let units: Vec<u16> = text.chunks(2).filter(|c| c.len() == 2)
+                .map(|c| if be { (c[0] as u16) << 8 | c[1] as u16 } else { (c[1] as u16) << 8 | c[0] as u16 })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // JIS isn't supported
+        _ => return None,
+    };
+    let s = s.trim_end_matches(&['\0', ' '][..]);
+    if s.is_empty() { None } else { Some(s.to_string()) }
+}
+
+/// encode a UserComment, as ASCII if possible and otherwise as UCS-2
+pub fn encode_user_comment(s: &str, big_endian: bool) -> Vec<u8> {
+    if s.is_ascii() {
+        let mut d = b"ASCII\0\0\0".to_vec();
+        d.extend_from_slice(s.as_bytes());
+        return d;
+    }
+    let mut d = b"UNICODE\0".to_vec();
+    for u in s.encode_utf16() {
+        if big_endian {
+            d.extend_from_slice(&[(u >> 8) as u8, u as u8]);
+        } else {
+            d.extend_from_slice(&[u as u8, (u >> 8) as u8]);
+        }
+    }
+    d
+}
+
+fn child(exif: &Exif, id: u16) -> Option<&Ifd> {
+    exif.ifds.first().and_then(|ifd| ifd.children.iter().find(|c| c.id == id))
+}
+
+// Artist holds several creators separated by semicolons
+fn split_artist(a: &str) -> Vec<String> {
+    a.split(';').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
+
+fn digest(entries: Vec<(u16, Option<&Entry>)>) -> String {
+    let tags: Vec<String> = entries.iter().map(|e| e.0.to_string()).collect();
+    let mut d = vec![];
+    for (_, e) in entries {
+        let v = match e.map(|e| &e.data) {
+            Some(EntryData::Ascii(s)) => s.as_bytes().to_vec(),
+            Some(EntryData::Byte(b)) | Some(EntryData::Undef(b)) => b.clone(),
+            Some(data) => data.floats().unwrap_or_default().iter().map(|f| f.to_string()).collect::<Vec<_>>().join(",").into_bytes(),
+            None => {
+                d.push(0);
+                continue;
+            }
+        };
+        d.push(1);
+        d.extend_from_slice(&(v.len() as u32).to_be_bytes());
+        d.extend(v);
+    }
+    format!("{};{}", tags.join(","), md5::hex_digest(&d))
+}
+
+// replace the x-default item of a language alternative, keeping any translations
+fn set_x_default(xmp: &mut Xmp, n: &str, name: &str, text: Option<&String>) {
+    let text = match text {
+        Some(t) => t,
+        None => {
+            xmp.remove(n, name);
+            return;
+        }
+    };
+    let mut item = Property::item(Value::Text(text.clone()));
+    item.lang = Some("x-default".to_string());
+    let mut items = match xmp.property(n, name).map(|p| &p.value) {
+        Some(Value::Array(ArrayKind::Alt, items)) => items.clone(),
+        _ => vec![],
+    };
+    items.retain(|i| i.lang.as_ref().map(|l| l.eq_ignore_ascii_case("x-default")) != Some(true));
+    items.insert(0, item);
+    xmp.set(n, name, Value::Array(ArrayKind::Alt, items));
+}
+
+// degrees, minutes and seconds to the nearest thousandth
+fn dms(degrees: f64) -> Vec<u64> {
+    let d = degrees.abs();
+    let m = (d - d.floor()) * 60.0;
+    let s = (m - m.floor()) * 60.0;
+    vec![(d.floor() as u64) << 32 | 1, (m.floor() as u64) << 32 | 1, rational(s)]
+}
+
+fn rational(v: f64) -> u64 {
+    ((v * 1000.0).round() as u64) << 32 | 1000
+}
+
+// "DDD,MM.mmmmmmk"
+fn xmp_coordinate(degrees: f64, pos: char, neg: char) -> String {
+    let d = degrees.abs();
+    format!("{},{:.6}{}", d.floor(), (d - d.floor()) * 60.0, if degrees < 0.0 { neg } else { pos })
+}
+
+// "DDD,MM,SSk" or "DDD,MM.mmk"
+fn parse_xmp_coordinate(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let reference = s.get(s.len().checked_sub(1)?..)?;
+    let parts = s[..s.len() - 1].split(',').map(|p| p.trim().parse::<f64>().ok()).collect::<Option<Vec<_>>>()?;
+    GpsPosition::degrees_from_dms(&parts, reference)
+}
+
+fn xmp_gps(xmp: &Xmp) -> Option<GpsPosition> {
+    let latitude = parse_xmp_coordinate(xmp.text(ns::EXIF, "GPSLatitude")?)?;
+    let longitude = parse_xmp_coordinate(xmp.text(ns::EXIF, "GPSLongitude")?)?;
+    let altitude = xmp.text(ns::EXIF, "GPSAltitude").and_then(|a| {
+        let mut parts = a.splitn(2, '/');
+        let n = parts.next()?.trim().parse::<f64>().ok()?;
+        let d = match parts.next() {
+            Some(d) => d.trim().parse::<f64>().ok().filter(|&d| d != 0.0)?,
+            None => 1.0,
+        };
+        Some(if xmp.text(ns::EXIF, "GPSAltitudeRef") == Some("1") { -n / d } else { n / d })
+    });
+    Some(GpsPosition{ latitude: latitude, longitude: longitude, altitude: altitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::tests::exif_with;
+
+    #[test]
+    fn write_and_reconcile() {
+        let v = Values{
+            date_time_original: DateTime::from_iso8601("2021-06-01T08:30:00+02:00"),
+            creator: vec!["Jane Doe".to_string(), "John Roe".to_string()],
+            copyright: Some("© 2021 Jane Doe".to_string()),
+            description: Some("Plage à Nice".to_string()),
+            user_comment: Some("très bien".to_string()),
+            gps: Some(GpsPosition{ latitude: 43.6955, longitude: -7.2655, altitude: Some(-1.5) }),
+        };
+        let (mut e, mut x) = (exif_with(&[]), Xmp::default());
+        e.big_endian = true;
+        write(&v, &mut e, &mut x);
+
+        assert_eq!(e.ifds[0].ascii(tag::ARTIST), Some("Jane Doe; John Roe"));
+        assert_eq!(x.lang_alt(ns::DC, "description", "fr"), Some("Plage à Nice"));
+        assert_eq!(x.text(ns::EXIF, "GPSLongitude"), Some("7,15.930000W"));
+        assert!(is_xmp_current(&e, &x));
+        assert_eq!(x.text(ns::TIFF, "NativeDigest"), None);
+
+        let from_exif = Values::from_exif(&e);
+        assert_eq!(from_exif.creator, v.creator);
+        assert_eq!(from_exif.user_comment, v.user_comment);
+        assert_eq!(from_exif.date_time_original, v.date_time_original);
+        let g = Values::from_xmp(&x).gps.expect("xmp gps");
+        assert!((g.longitude - -7.2655).abs() < 1e-9 && g.altitude == Some(-1.5));
+        assert_eq!(read(Some(&e), Some(&x)).description, v.description);
+
+        // a change by software unaware of XMP makes the exif win
+        e.ifds[0].set(tag::IMG_DESCRIPTION, EntryData::Ascii("edited".to_string()));
+        x.set(ns::DC, "rights", Value::Text("xmp only".to_string()));
+        assert!(!is_xmp_current(&e, &x));
+        let r = read(Some(&e), Some(&x));
+        assert_eq!(r.description, Some("edited".to_string()));
+        assert_eq!(r.copyright, v.copyright);
+
+        // while current XMP wins, and supplies a missing offset
+        let mut e = exif_with(&[]);
+        e.big_endian = true;
+        e.child_mut(tag::EXIF_IFD_POINTER).set(tag::DATE_TIME_ORIGINAL, EntryData::Ascii("2021:06:01 08:30:00".to_string()));
+        e.ifd0_mut().set(tag::COPYRIGHT, EntryData::Ascii("(c) Jane".to_string()));
+        let mut x = Xmp::default();
+        set_x_default(&mut x, ns::DC, "rights", Some(&"© Jane".to_string()));
+        x.set(ns::EXIF, "DateTimeOriginal", Value::Text("2021-06-01T08:30:00+02:00".to_string()));
+        assert_eq!(read(Some(&e), Some(&x)).copyright, Some("(c) Jane".to_string()));
+        assert_eq!(read(Some(&e), Some(&x)).date_time_original, v.date_time_original);
+        let (tiff, exif_digest) = digests(&e);
+        x.set(ns::IMAGEMETA, "TiffDigest", Value::Text(tiff));
+        x.set(ns::IMAGEMETA, "ExifDigest", Value::Text(exif_digest));
+        assert_eq!(read(Some(&e), Some(&x)).copyright, Some("© Jane".to_string()));
+    }
+
+    #[test]
+    fn user_comment_charsets() {
+        assert_eq!(decode_user_comment(b"ASCII\0\0\0hello   ", false), Some("hello".to_string()));
+        assert_eq!(decode_user_comment(b"\0\0\0\0\0\0\0\0caf\xe9", false), Some("café".to_string()));
+        assert_eq!(decode_user_comment(b"UNICODE\0\0h\0i", true), Some("hi".to_string()));
+        assert_eq!(decode_user_comment(b"UNICODE\0\xff\xfeh\0i\0", true), Some("hi".to_string()));
+        assert_eq!(decode_user_comment(b"JIS\0\0\0\0\0\x1b$B", true), None);
+        assert_eq!(decode_user_comment(&encode_user_comment("日本", false), false), Some("日本".to_string()));
+        assert_eq!(encode_user_comment("ok", true), b"ASCII\0\0\0ok");
+    }
+}
@@ -0,0 +1,371 @@
+// https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/ (Image Resource Blocks)
+//
+// an image resource block is a sequence of resources: a signature, u16 id,
+// pascal string name padded to an even length, u32 data length and data,
+// also padded. jpeg files store it in APP13 after a "Photoshop 3.0" header,
+// split across several segments if needed.
+
+use std::io::prelude::*;
+use std::io;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use exif::jpeg::{self, Segment};
+use md5;
+
+pub const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+pub const SIGNATURE: &[u8; 4] = b"8BIM";
+
+const APP13: u8 = 0xED;
+/// the largest payload of an APP13 segment after the Photoshop header
+const MAX_APP13_DATA: usize = 65533 - 14;
+
+/// resource ids
+pub mod id {
+    pub const RESOLUTION_INFO: u16 = 0x03ED;
+    pub const IPTC: u16 = 0x0404;
+    /// Photoshop 4.0 thumbnail, with BGR pixel order
+    pub const THUMBNAIL_PS4: u16 = 0x0409;
+    pub const COPYRIGHT_FLAG: u16 = 0x040A;
+    pub const URL: u16 = 0x040B;
+    pub const THUMBNAIL: u16 = 0x040C;
+    pub const ICC_PROFILE: u16 = 0x040F;
+    pub const SLICES: u16 = 0x041A;
+    pub const XMP: u16 = 0x0424;
+    /// MD5 of the IPTC resource, used to detect changes by other software
+    pub const IPTC_DIGEST: u16 = 0x0425;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resource {
+    /// usually "8BIM", though some writers use others such as "MeSa"
+    pub signature: [u8; 4],
+    pub id: u16,
+    /// the pascal string name without its length, usually empty
+    pub name: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// top level data structure representing an image resource block, in file order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageResources {
+    pub resources: Vec<Resource>,
+    /// bytes after the last resource which don't start one, such as padding
+    /// or an unknown signature. kept so they are written back unchanged.
+    pub trailing: Vec<u8>,
+}
+
+/// ResolutionInfo: pixels per inch or per centimetre, with display units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResolutionInfo {
+    pub h_res: f64,
+    /// 1 for pixels per inch, 2 for pixels per centimetre
+    pub h_res_unit: u16,
+    pub width_unit: u16,
+    pub v_res: f64,
+    pub v_res_unit: u16,
+    pub height_unit: u16,
+}
+
+/// a thumbnail resource: a 28 byte header followed by JFIF data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail<'a> {
+    /// 1 for JFIF, 0 for raw RGB
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u16,
+    pub data: &'a [u8],
+}
+
+/// the header of a version 6 Slices resource
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slices {
+    /// top, left, bottom, right
+    pub bounds: [i32; 4],
+    pub name: String,
+    pub count: u32,
+}
+
+impl ImageResources {
+    pub fn parse(d: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated Photoshop resource");
+        let mut resources = vec![];
+        let mut pos = 0;
+        while pos + 4 <= d.len() && is_signature(&d[pos..pos + 4]) {
+            let mut signature = [0u8; 4];
+            signature.copy_from_slice(&d[pos..pos + 4]);
+            let id = BigEndian::read_u16(try!(d.get(pos + 4..pos + 6).ok_or_else(invalid)));
+            let name_len = *try!(d.get(pos + 6).ok_or_else(invalid)) as usize;
+            let name = try!(d.get(pos + 7..pos + 7 + name_len).ok_or_else(invalid)).to_vec();
+            pos += 6 + ((name_len + 2) & !1);
+            let len = BigEndian::read_u32(try!(d.get(pos..pos + 4).ok_or_else(invalid))) as usize;
+            let data = try!(d.get(pos + 4..pos + 4 + len).ok_or_else(invalid)).to_vec();
+            resources.push(Resource{ signature: signature, id: id, name: name, data: data });
+            pos += 4 + ((len + 1) & !1);
+        }
+        Ok(ImageResources{ resources: resources, trailing: d[pos.min(d.len())..].to_vec() })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut d = vec![];
+        for r in &self.resources {
+            d.extend_from_slice(&r.signature);
+            d.extend_from_slice(&r.id.to_be_bytes());
+            let name = &r.name[..r.name.len().min(255)];
+            d.push(name.len() as u8);
+            d.extend_from_slice(name);
+            if name.len() % 2 == 0 {
+                d.push(0);
+            }
+            d.extend_from_slice(&(r.data.len() as u32).to_be_bytes());
+            d.extend_from_slice(&r.data);
+            if r.data.len() % 2 == 1 {
+                d.push(0);
+            }
+        }
+        d.extend_from_slice(&self.trailing);
+        d
+    }
+
+    pub fn get(&self, id: u16) -> Option<&[u8]> {
+        self.resources.iter().find(|r| r.id == id).map(|r| &r.data[..])
+    }
+
+    /// add a resource, replacing the data of any existing one with the same id
+    pub fn set(&mut self, id: u16, data: Vec<u8>) {
+        match self.resources.iter_mut().find(|r| r.id == id) {
+            Some(r) => r.data = data,
+            None => self.resources.push(Resource{ signature: *SIGNATURE, id: id, name: vec![], data: data }),
+        }
+    }
+
+    pub fn remove(&mut self, id: u16) {
+        self.resources.retain(|r| r.id != id);
+    }
+
+    pub fn resolution_info(&self) -> Option<ResolutionInfo> {
+        let d = self.get(id::RESOLUTION_INFO)?;
+        if d.len() < 16 {
+            return None;
+        }
+        let fixed = |b: &[u8]| BigEndian::read_u32(b) as f64 / 65536.0;
+        Some(ResolutionInfo{
+            h_res: fixed(&d[0..]),
+            h_res_unit: BigEndian::read_u16(&d[4..]),
+            width_unit: BigEndian::read_u16(&d[6..]),
+            v_res: fixed(&d[8..]),
+            v_res_unit: BigEndian::read_u16(&d[12..]),
+            height_unit: BigEndian::read_u16(&d[14..]),
+        })
+    }
+
+    pub fn set_resolution_info(&mut self, r: &ResolutionInfo) {
+        let fixed = |v: f64| ((v * 65536.0).round() as u32).to_be_bytes();
+        let mut d = vec![];
+        d.extend_from_slice(&fixed(r.h_res));
+        d.extend_from_slice(&r.h_res_unit.to_be_bytes());
+        d.extend_from_slice(&r.width_unit.to_be_bytes());
+        d.extend_from_slice(&fixed(r.v_res));
+        d.extend_from_slice(&r.v_res_unit.to_be_bytes());
+        d.extend_from_slice(&r.height_unit.to_be_bytes());
+        self.set(id::RESOLUTION_INFO, d);
+    }
+
+    /// the thumbnail, preferring the RGB form over the older BGR one
+    pub fn thumbnail(&self) -> Option<Thumbnail<'_>> {
+        let d = self.get(id::THUMBNAIL).or_else(|| self.get(id::THUMBNAIL_PS4))?;
+        if d.len() < 28 {
+            return None;
+        }
+        Some(Thumbnail{
+            format: BigEndian::read_u32(&d[0..]),
+            width: BigEndian::read_u32(&d[4..]),
+            height: BigEndian::read_u32(&d[8..]),
+            bits_per_pixel: BigEndian::read_u16(&d[24..]),
+            data: &d[28..],
+        })
+    }
+
+    /// true if the image is marked as copyrighted
+    pub fn copyright_flag(&self) -> Option<bool> {
+        self.get(id::COPYRIGHT_FLAG).and_then(|d| d.first()).map(|&f| f != 0)
+    }
+
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.get(id::ICC_PROFILE)
+    }
+
+    pub fn xmp(&self) -> Option<&[u8]> {
+        self.get(id::XMP)
+    }
+
+    pub fn iptc(&self) -> Option<&[u8]> {
+        self.get(id::IPTC)
+    }
+
+    pub fn iptc_digest(&self) -> Option<&[u8]> {
+        self.get(id::IPTC_DIGEST).filter(|d| d.len() == 16)
+    }
+
+    /// false if the IPTC has been changed by software which didn't update its digest
+    pub fn is_iptc_digest_current(&self) -> bool {
+        match (self.iptc(), self.iptc_digest()) {
+            (Some(i), Some(d)) => md5::digest(i)[..] == *d,
+            _ => true,
+        }
+    }
+
+    /// replace or remove the IPTC records, keeping the digest in sync
+    pub fn set_iptc(&mut self, iptc: Option<Vec<u8>>) {
+        match iptc {
+            Some(i) => {
+                self.set(id::IPTC_DIGEST, md5::digest(&i).to_vec());
+                self.set(id::IPTC, i);
+            }
+            None => {
+                self.remove(id::IPTC);
+                self.remove(id::IPTC_DIGEST);
+            }
+        }
+    }
+
+    /// the header of a version 6 Slices resource. later versions are descriptors.
+    pub fn slices(&self) -> Option<Slices> {
+        let d = self.get(id::SLICES)?;
+        if d.len() < 24 || BigEndian::read_u32(d) != 6 {
+            return None;
+        }
+        let mut bounds = [0i32; 4];
+        for (i, b) in bounds.iter_mut().enumerate() {
+            *b = BigEndian::read_i32(&d[4 + i * 4..]);
+        }
+        // a unicode string: u32 count of UTF-16 units, then the units
+        let n = BigEndian::read_u32(&d[20..]) as usize;
+        let units = d.get(24..n.checked_mul(2)?.checked_add(24)?)?;
+        let units: Vec<u16> = units.chunks(2).map(BigEndian::read_u16).collect();
+        let count = d.get(24 + n * 2..28 + n * 2).map(BigEndian::read_u32)?;
+        Some(Slices{
+            bounds: bounds,
+            name: String::from_utf16_lossy(&units).trim_end_matches('\0').to_string(),
+            count: count,
+        })
+    }
+
+    /// the resources from a jpeg file's APP13 segments
+    pub fn from_segments(segments: &[Segment]) -> io::Result<Option<Self>> {
+        let mut d = vec![];
+        let mut found = false;
+        for s in segments.iter().filter(|s| s.marker == APP13 && s.data.starts_with(PHOTOSHOP_HEADER)) {
+            d.extend_from_slice(&s.data[PHOTOSHOP_HEADER.len()..]);
+            found = true;
+        }
+        if found { ImageResources::parse(&d).map(Some) } else { Ok(None) }
+    }
+
+    /// replace the APP13 segments of a jpeg file with these resources, or
+    /// remove them if there are none. new segments follow any other APPn segments.
+    pub fn update_segments(&self, segments: &mut Vec<Segment>) {
+        let is_app13 = |s: &Segment| s.marker == APP13 && s.data.starts_with(PHOTOSHOP_HEADER);
+        let pos = segments.iter().position(is_app13)
+            .unwrap_or_else(|| segments.iter().take_while(|s| s.marker >= 0xE0 && s.marker <= 0xEF).count());
+        segments.retain(|s| !is_app13(s));
+        if self.resources.is_empty() && self.trailing.is_empty() {
+            return;
+        }
+        for (i, chunk) in self.encode().chunks(MAX_APP13_DATA).enumerate() {
+            let mut d = PHOTOSHOP_HEADER.to_vec();
+            d.extend_from_slice(chunk);
+            segments.insert(pos + i, Segment{ marker: APP13, data: d });
+        }
+    }
+}
+
+/// read the image resources of a jpeg file
+pub fn read_jpeg<R: Read>(rdr: &mut R) -> io::Result<Option<ImageResources>> {
+    ImageResources::from_segments(&try!(jpeg::read_segments(rdr)))
+}
+
+/// copy a jpeg file, modifying its image resources with `f`. resources
+/// which aren't modified are copied byte for byte.
+pub fn write_jpeg<R: Read, W: Write, F: FnOnce(&mut ImageResources)>(rdr: &mut R, w: &mut W, f: F) -> io::Result<()> {
+    jpeg::rewrite_segments(rdr, w, |segments| {
+        let mut r = try!(ImageResources::from_segments(segments)).unwrap_or_default();
+        f(&mut r);
+        r.update_segments(segments);
+        Ok(())
+    })
+}
+
+fn is_signature(s: &[u8]) -> bool {
+    s == SIGNATURE || s == b"MeSa" || s == b"PHUT" || s == b"AgHg" || s == b"DCSR"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn typed_resources() {
+        let mut d = vec![];
+        d.extend_from_slice(b"8BIM\x03\xed\0\0\0\0\0\x10\0\x48\0\0\0\x01\0\x01\0\x48\x80\0\0\x01\0\x02");
+        d.extend_from_slice(b"8BIM\x04\x0a\x03abc\0\0\0\x01\x01\0");
+        d.extend_from_slice(b"MeSa\x27\x10\0\0\0\0\0\x03xyz\0");
+        let mut slices = b"8BIM\x04\x1a\0\0\0\0\0\x20\0\0\0\x06\0\0\0\0\0\0\0\0\0\0\0\x10\0\0\0\x20\0\0\0\x02\0a\0b".to_vec();
+        slices.extend_from_slice(&[0, 0, 0, 1]);
+        d.extend_from_slice(&slices);
+
+        let r = ImageResources::parse(&d).expect("parse resources");
+        assert_eq!(r.resources.len(), 4);
+        assert_eq!(r.resources[1].name, b"abc");
+        assert_eq!(r.resources[2].signature, *b"MeSa");
+        assert_eq!(r.encode(), d);
+        assert!(r.trailing.is_empty());
+
+        let res = r.resolution_info().expect("resolution");
+        assert_eq!((res.h_res, res.v_res, res.height_unit), (72.0, 72.5, 2));
+        assert_eq!(r.copyright_flag(), Some(true));
+        assert_eq!(r.slices(), Some(Slices{ bounds: [0, 0, 16, 32], name: "ab".to_string(), count: 1 }));
+        assert!(r.thumbnail().is_none());
+
+        let mut r2 = r.clone();
+        r2.set_resolution_info(&res);
+        assert_eq!(r2, r);
+        assert!(ImageResources::parse(b"8BIM\x04\x04\0\0\0\0\0\x09ab").is_err());
+
+        // an unknown signature ends parsing, but the rest is written back
+        d.extend_from_slice(b"XXXX\x04\x04\0\0\0\0\0\x01\x01\0\0");
+        let r = ImageResources::parse(&d).expect("parse with trailing bytes");
+        assert_eq!(r.resources.len(), 4);
+        assert_eq!(r.trailing, b"XXXX\x04\x04\0\0\0\0\0\x01\x01\0\0");
+        assert_eq!(r.encode(), d);
+    }
+
+    #[test]
+    fn iptc_digest_in_jpeg() {
+        let mut f = vec![0xFF, 0xD8];
+        f.extend_from_slice(&[0xFF, 0xE0, 0, 4, b'J', b'F']);
+        f.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+        let mut out = vec![];
+        write_jpeg(&mut Cursor::new(&f), &mut out, |r| {
+            r.set(id::COPYRIGHT_FLAG, vec![1]);
+            r.set_iptc(Some(vec![0x1C, 2, 5, 0, 1, b'x']));
+        }).expect("write resources");
+        let segments = jpeg::read_segments(&mut Cursor::new(&out)).expect("segments");
+        assert_eq!(segments[1].marker, APP13);
+
+        let mut r = read_jpeg(&mut Cursor::new(&out)).expect("read resources").expect("resources present");
+        assert_eq!(r.iptc_digest(), Some(&md5::digest(&[0x1C, 2, 5, 0, 1, b'x'])[..]));
+        assert!(r.is_iptc_digest_current());
+        r.set(id::IPTC, vec![0x1C, 2, 5, 0, 1, b'y']);
+        assert!(!r.is_iptc_digest_current());
+
+        r.set_iptc(None);
+        r.remove(id::COPYRIGHT_FLAG);
+        let mut segments = segments;
+        r.update_segments(&mut segments);
+        assert_eq!(segments.len(), 1);
+    }
+}
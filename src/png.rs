@@ -0,0 +1,483 @@
+// https://www.w3.org/TR/png/
+// http://ftp-osl.osuosl.org/pub/libpng/documents/pngext-1.5.0.html#C.eXIf
+//
+// a PNG file is a signature followed by chunks of (length, type, data, crc).
+// exif is stored as a bare TIFF structure in eXIf, or by older software as
+// hex encoded text in a "Raw profile type exif" text chunk.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::Cursor;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+use exif::Exif;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// keywords used by ImageMagick for hex encoded exif profiles
+const RAW_PROFILE_KEYWORDS: &[&str] = &["Raw profile type exif", "Raw profile type APP1"];
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+pub type ChunkType = [u8; 4];
+
+/// a single chunk. the crc is computed when writing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub kind: ChunkType,
+    pub data: Vec<u8>,
+}
+
+/// the contents of a tEXt, zTXt or iTXt chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+    /// stored deflate compressed, in a zTXt or iTXt chunk
+    pub compressed: bool,
+    /// language tag and translated keyword, present for iTXt chunks
+    pub international: Option<(String, String)>,
+}
+
+/// top level data structure representing a PNG file
+#[derive(Clone, Debug)]
+pub struct Png {
+    pub chunks: Vec<Chunk>,
+}
+
+impl Chunk {
+    pub fn read<R: Read>(rdr: &mut R) -> io::Result<Self> {
+        let len = try!(rdr.read_u32::<BigEndian>());
+        let mut kind = [0u8; 4];
+        try!(rdr.read_exact(&mut kind));
+        let mut data = vec![0u8; len as usize];
+        try!(rdr.read_exact(&mut data));
+
+        let c = Chunk{ kind: kind, data: data };
+        if try!(rdr.read_u32::<BigEndian>()) != c.crc() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("crc mismatch in {} chunk", String::from_utf8_lossy(&kind))));
+        }
+        Ok(c)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_u32::<BigEndian>(self.data.len() as u32));
+        try!(w.write_all(&self.kind));
+        try!(w.write_all(&self.data));
+        w.write_u32::<BigEndian>(self.crc())
+    }
+
+    /// crc of the chunk type and data
+    pub fn crc(&self) -> u32 {
+        !update_crc(update_crc(!0, &self.kind), &self.data)
+    }
+}
+
+impl Png {
+    pub fn new<R: Read>(rdr: &mut R) -> io::Result<Self> {
+        let mut sig = [0u8; 8];
+        try!(rdr.read_exact(&mut sig));
+        if sig != SIGNATURE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a PNG file"));
+        }
+
+        let mut chunks = vec![];
+        loop {
+            let c = try!(Chunk::read(rdr));
+            let end = &c.kind == b"IEND";
+            chunks.push(c);
+            if end {
+                break;
+            }
+        }
+        Ok(Png{ chunks: chunks })
+    }
+
+    pub fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(&SIGNATURE));
+        for c in &self.chunks {
+            try!(c.write(w));
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, kind: &ChunkType) -> Option<&Chunk> {
+        self.chunks.iter().find(|c| &c.kind == kind)
+    }
+
+    /// the exif data from the eXIf chunk, or failing that, from a legacy
+    /// ImageMagick raw profile text chunk
+    pub fn exif(&self) -> Option<io::Result<Exif>> {
+        if let Some(c) = self.find(b"eXIf") {
+            return Some(Exif::new(&mut Cursor::new(&c.data)));
+        }
+
+        // only the raw profile itself is decoded, other text chunks may be malformed
+        let c = self.chunks.iter().find(|c| is_raw_profile(c))?;
+        let t = match Text::from_chunk(c) {
+            Ok(Some(t)) => t,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(decode_raw_profile(&t.text).and_then(|d| Exif::new(&mut Cursor::new(d))))
+    }
+
+    /// store exif in an eXIf chunk, replacing any existing exif data
+    pub fn set_exif(&mut self, exif: &Exif) -> io::Result<()> {
+        let mut buf = Cursor::new(vec![]);
+        try!(exif.encode(&mut buf));
+
+        self.remove_exif();
+        // eXIf must precede IDAT to be considered valid by all decoders
+        let pos = self.chunks.iter().position(|c| &c.kind == b"IDAT" || &c.kind == b"IEND").unwrap_or(self.chunks.len());
+        self.chunks.insert(pos, Chunk{ kind: *b"eXIf", data: buf.into_inner() });
+        Ok(())
+    }
+
+    /// remove the eXIf chunk and any legacy exif text chunks
+    pub fn remove_exif(&mut self) {
+        self.chunks.retain(|c| {
+            if &c.kind == b"eXIf" {
+                return false;
+            }
+            !is_raw_profile(c)
+        });
+    }
+
+    /// the embedded ICC profile from the iCCP chunk. this is always
+    /// compressed, so requires the "deflate" feature.
+    pub fn icc_profile(&self) -> Option<io::Result<Vec<u8>>> {
+        let c = self.find(b"iCCP")?;
+        Some(split_null(&c.data).and_then(|(_, d)| match d.split_first() {
+            Some((&0, d)) => inflate(d),
+            _ => Err(invalid("unsupported iCCP compression method")),
+        }))
+    }
+
+    /// store a profile in an iCCP chunk, which must precede PLTE and IDAT.
+    /// any sRGB chunk is removed, as the two are mutually exclusive.
+    pub fn set_icc_profile(&mut self, name: &str, profile: &[u8]) -> io::Result<()> {
+        if name.is_empty() || name.len() > 79 || name.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid iCCP profile name"));
+        }
+        let mut data = name.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0]);
+        data.extend(try!(deflate(profile)));
+
+        self.remove_icc_profile();
+        let pos = self.chunks.iter().position(|c| &c.kind == b"PLTE" || &c.kind == b"IDAT" || &c.kind == b"IEND").unwrap_or(self.chunks.len());
+        self.chunks.insert(pos, Chunk{ kind: *b"iCCP", data: data });
+        Ok(())
+    }
+
+    pub fn remove_icc_profile(&mut self) {
+        self.chunks.retain(|c| &c.kind != b"iCCP" && &c.kind != b"sRGB");
+    }
+
+    /// all textual chunks. compressed chunks require the "deflate" feature.
+    pub fn text(&self) -> io::Result<Vec<Text>> {
+        let mut v = vec![];
+        for c in &self.chunks {
+            if let Some(t) = try!(Text::from_chunk(c)) {
+                v.push(t);
+            }
+        }
+        Ok(v)
+    }
+
+    /// add a textual chunk, after any others but before the image data
+    pub fn add_text(&mut self, text: &Text) -> io::Result<()> {
+        let c = try!(text.to_chunk());
+        // readers may stop at the first IDAT
+        let pos = self.chunks.iter().position(|c| &c.kind == b"IDAT" || &c.kind == b"IEND").unwrap_or(self.chunks.len());
+        self.chunks.insert(pos, c);
+        Ok(())
+    }
+}
+
+/// the keyword of a tEXt, zTXt or iTXt chunk, without decoding its text.
+/// returns None for other chunk types, or if there is no keyword.
+pub fn text_keyword(c: &Chunk) -> Option<String> {
+    match &c.kind {
+        b"tEXt" | b"zTXt" | b"iTXt" => split_null(&c.data).ok().map(|(k, _)| latin1(k)),
+        _ => None,
+    }
+}
+
+fn is_raw_profile(c: &Chunk) -> bool {
+    match text_keyword(c) {
+        Some(k) => RAW_PROFILE_KEYWORDS.contains(&&k[..]),
+        None => false,
+    }
+}
+
+impl Text {
+    /// decode a tEXt, zTXt or iTXt chunk, returning None for other chunk types
+    pub fn from_chunk(c: &Chunk) -> io::Result<Option<Text>> {
+        let (keyword, rest) = match &c.kind {
+            b"tEXt" | b"zTXt" | b"iTXt" => try!(split_null(&c.data)),
+            _ => return Ok(None),
+        };
+        let keyword = latin1(keyword);
+
+        let t = match &c.kind {
+            b"tEXt" => Text{ keyword: keyword, text: latin1(rest), compressed: false, international: None },
+            b"zTXt" => {
+                let data = try!(rest.split_first().map(|(_, d)| d).ok_or(invalid("truncated zTXt chunk")));
+                Text{ keyword: keyword, text: latin1(&try!(inflate(data))), compressed: true, international: None }
+            }
+            _ => {
+                if rest.len() < 2 {
+                    return Err(invalid("truncated iTXt chunk"));
+                }
+                let compressed = rest[0] == 1;
+                let (language, rest) = try!(split_null(&rest[2..]));
+                let (translated, text) = try!(split_null(rest));
+                let text = if compressed { try!(inflate(text)) } else { text.to_vec() };
+                Text{
+                    keyword: keyword,
+                    text: try!(String::from_utf8(text).map_err(|_| invalid("iTXt text is not UTF-8"))),
+                    compressed: compressed,
+                    international: Some((latin1(language), try!(String::from_utf8(translated.to_vec()).map_err(|_| invalid("iTXt keyword is not UTF-8"))))),
+                }
+            }
+        };
+        Ok(Some(t))
+    }
+
+    pub fn to_chunk(&self) -> io::Result<Chunk> {
+        if self.keyword.is_empty() || self.keyword.len() > 79 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG text keywords must be 1-79 bytes"));
+        }
+        let mut data = try!(to_latin1(&self.keyword));
+        data.push(0);
+
+        let kind = match self.international {
+            Some((ref language, ref translated)) => {
+                data.extend_from_slice(&[self.compressed as u8, 0]);
+                data.extend(try!(to_latin1(language)));
+                data.push(0);
+                data.extend_from_slice(translated.as_bytes());
+                data.push(0);
+                if self.compressed {
+                    data.extend(try!(deflate(self.text.as_bytes())));
+                } else {
+                    data.extend_from_slice(self.text.as_bytes());
+                }
+                *b"iTXt"
+            }
+            None if self.compressed => {
+                data.push(0);
+                data.extend(try!(deflate(&try!(to_latin1(&self.text)))));
+                *b"zTXt"
+            }
+            None => {
+                data.extend(try!(to_latin1(&self.text)));
+                *b"tEXt"
+            }
+        };
+        Ok(Chunk{ kind: kind, data: data })
+    }
+}
+
+/// encode exif as an ImageMagick style raw profile, for software which predates eXIf
+pub fn encode_raw_profile(exif: &Exif) -> io::Result<Text> {
+    let mut buf = Cursor::new(EXIF_HEADER.to_vec());
+    buf.set_position(EXIF_HEADER.len() as u64);
+    try!(exif.encode(&mut buf));
+    let data = buf.into_inner();
+
+    let mut text = format!("\nexif\n{:8}\n", data.len());
+    for line in data.chunks(36) {
+        for b in line {
+            text.push_str(&format!("{:02x}", b));
+        }
+        text.push('\n');
+    }
+    Ok(Text{ keyword: RAW_PROFILE_KEYWORDS[0].to_string(), text: text, compressed: false, international: None })
+}
+
+// "\n<name>\n<length>\n<hex data>", optionally prefixed with the jpeg exif header
+fn decode_raw_profile(text: &str) -> io::Result<Vec<u8>> {
+    let mut words = text.split_whitespace();
+    words.next();
+    let len = try!(words.next().and_then(|l| l.parse::<usize>().ok()).ok_or(invalid("invalid raw profile length")));
+
+    let hex_len = try!(len.checked_mul(2).ok_or(invalid("invalid raw profile length")));
+    let hex = words.collect::<String>().into_bytes();
+    if hex.len() < hex_len {
+        return Err(invalid("truncated raw profile"));
+    }
+    let mut data = Vec::with_capacity(len);
+    for pair in hex[..hex_len].chunks(2) {
+        let s = try!(::std::str::from_utf8(pair).map_err(|_| invalid("invalid raw profile data")));
+        data.push(try!(u8::from_str_radix(s, 16).map_err(|_| invalid("invalid raw profile data"))));
+    }
+
+    if data.starts_with(EXIF_HEADER) {
+        data.drain(..EXIF_HEADER.len());
+    }
+    Ok(data)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn split_null(d: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let i = try!(d.iter().position(|&b| b == 0).ok_or(invalid("missing null separator in text chunk")));
+    Ok((&d[..i], &d[i + 1..]))
+}
+
+fn latin1(d: &[u8]) -> String {
+    d.iter().map(|&b| b as char).collect()
+}
+
+fn to_latin1(s: &str) -> io::Result<Vec<u8>> {
+    s.chars().map(|c| {
+        if (c as u32) < 0x100 { Ok(c as u8) }
+        else { Err(io::Error::new(io::ErrorKind::InvalidInput, "text is not representable in Latin-1, use iTXt")) }
+    }).collect()
+}
+
+#[cfg(feature = "deflate")]
+fn inflate(d: &[u8]) -> io::Result<Vec<u8>> {
+    let mut v = vec![];
+    try!(::flate2::read::ZlibDecoder::new(d).read_to_end(&mut v));
+    Ok(v)
+}
+
+#[cfg(feature = "deflate")]
+fn deflate(d: &[u8]) -> io::Result<Vec<u8>> {
+    let mut e = ::flate2::write::ZlibEncoder::new(vec![], ::flate2::Compression::default());
+    try!(e.write_all(d));
+    e.finish()
+}
+
+#[cfg(not(feature = "deflate"))]
+fn inflate(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "compressed text requires the \"deflate\" feature"))
+}
+
+#[cfg(not(feature = "deflate"))]
+fn deflate(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "compressed text requires the \"deflate\" feature"))
+}
+
+/// update a running crc-32 (ISO 3309) with the given data
+pub fn update_crc(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use exif::tag;
+    use exif::tests::{exif_with, roundtrip};
+
+    // a 1x1 greyscale image
+    pub fn sample() -> Png {
+        Png{ chunks: vec![
+            Chunk{ kind: *b"IHDR", data: vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0] },
+            Chunk{ kind: *b"IDAT", data: vec![0x78, 0x9C, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01] },
+            Chunk{ kind: *b"IEND", data: vec![] },
+        ] }
+    }
+
+    #[test]
+    fn crc() {
+        // IEND always has the same crc
+        assert_eq!(Chunk{ kind: *b"IEND", data: vec![] }.crc(), 0xAE426082);
+
+        let mut buf = vec![];
+        sample().encode(&mut buf).expect("encode png");
+        let n = buf.len();
+        buf[n - 1] ^= 1;
+        assert!(Png::new(&mut Cursor::new(buf)).is_err());
+    }
+
+    #[test]
+    fn exif_chunk() {
+        let mut p = sample();
+        assert!(p.exif().is_none());
+        p.set_exif(&exif_with(&[(tag::IMG_DESCRIPTION, "png")])).expect("set exif");
+        assert_eq!(p.chunks[1].kind, *b"eXIf");
+
+        let p = roundtrip(&p, Png::encode, Png::new);
+        let e = p.exif().expect("exif").expect("decode exif");
+        assert_eq!(e.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("png"));
+    }
+
+    #[test]
+    fn raw_profile() {
+        let mut p = sample();
+        p.add_text(&encode_raw_profile(&exif_with(&[(tag::IMG_DESCRIPTION, "png")])).expect("raw profile")).expect("add text");
+        let p = roundtrip(&p, Png::encode, Png::new);
+        let e = p.exif().expect("exif").expect("decode exif");
+        assert_eq!(e.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("png"));
+
+        let mut p = p;
+        p.set_exif(&exif_with(&[(tag::IMG_DESCRIPTION, "png")])).expect("set exif");
+        assert_eq!(p.text().expect("text").len(), 0);
+
+        let mut p = sample();
+        let huge = Text{ keyword: RAW_PROFILE_KEYWORDS[0].to_string(), text: "\nexif\n18446744073709551615\n00\n".to_string(), compressed: false, international: None };
+        p.add_text(&huge).expect("add text");
+        assert_eq!(p.exif().expect("exif").expect_err("length overflows").kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn raw_profile_beside_bad_text() {
+        let mut p = sample();
+        p.chunks.insert(1, Chunk{ kind: *b"tEXt", data: b"no separator".to_vec() });
+        p.add_text(&encode_raw_profile(&exif_with(&[(tag::IMG_DESCRIPTION, "png")])).expect("raw profile")).expect("add text");
+        assert!(p.text().is_err());
+        assert_eq!(text_keyword(&p.chunks[1]), None);
+
+        let e = p.exif().expect("exif").expect("decode exif");
+        assert_eq!(e.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("png"));
+        p.remove_exif();
+        assert_eq!(p.chunks.len(), 4);
+    }
+
+    #[test]
+    fn text_chunks() {
+        let mut p = sample();
+        let plain = Text{ keyword: "Title".to_string(), text: "caf\u{e9}".to_string(), compressed: false, international: None };
+        let intl = Text{
+            keyword: "Title".to_string(),
+            text: "\u{732b}".to_string(),
+            compressed: false,
+            international: Some(("ja".to_string(), "\u{984c}".to_string())),
+        };
+        p.add_text(&plain).expect("add tEXt");
+        p.add_text(&intl).expect("add iTXt");
+        assert!(p.add_text(&Text{ international: None, ..intl.clone() }).is_err());
+
+        let p = roundtrip(&p, Png::encode, Png::new);
+        let kinds: Vec<&[u8; 4]> = p.chunks.iter().map(|c| &c.kind).collect();
+        assert_eq!(kinds, vec![b"IHDR", b"tEXt", b"iTXt", b"IDAT", b"IEND"]);
+        assert_eq!(p.text().expect("text"), vec![plain, intl]);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn compressed_text() {
+        let mut p = sample();
+        let z = Text{ keyword: "Comment".to_string(), text: "aaaaaaaaaaaaaaaa".to_string(), compressed: true, international: None };
+        let i = Text{ international: Some(("".to_string(), "".to_string())), ..z.clone() };
+        p.add_text(&z).expect("add zTXt");
+        p.add_text(&i).expect("add iTXt");
+
+        let p = roundtrip(&p, Png::encode, Png::new);
+        assert_eq!(p.chunks[1].kind, *b"zTXt");
+        assert_eq!(p.text().expect("text"), vec![z, i]);
+    }
+}
@@ -0,0 +1,351 @@
+// https://developer.apple.com/documentation/quicktime-file-format
+//
+// QuickTime and MP4 files keep their metadata in moov: the creation time in
+// mvhd, "classic" user data strings (©mak, ©xyz...) in udta, and key/value
+// items in a meta box holding keys and ilst. iTunes style MP4 files use a
+// meta box inside udta whose ilst items are named by four character codes.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+use byteorder::{ReadBytesExt, ByteOrder, BigEndian};
+
+use bmff;
+use bmff::{BoxHeader, FourCC};
+use exif::{DateTime, GpsPosition};
+
+pub const MAKE: &str = "com.apple.quicktime.make";
+pub const MODEL: &str = "com.apple.quicktime.model";
+pub const CREATION_DATE: &str = "com.apple.quicktime.creationdate";
+pub const LOCATION_ISO6709: &str = "com.apple.quicktime.location.ISO6709";
+pub const CONTENT_IDENTIFIER: &str = "com.apple.quicktime.content.identifier";
+
+// major brands of files which carry a QuickTime style moov
+const BRANDS: [&FourCC; 10] = [b"qt  ", b"isom", b"iso2", b"mp41", b"mp42", b"avc1", b"M4V ", b"M4A ", b"3gp4", b"3gp5"];
+
+/// a decoded ilst item value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Utf8(String),
+    Int(i64),
+    Float(f64),
+    /// any other well known type, with its type indicator
+    Data(u32, Vec<u8>),
+}
+
+/// metadata read from the moov box of a QuickTime or MP4 file
+#[derive(Clone, Debug, Default)]
+pub struct QuickTime {
+    /// creation time from mvhd, if set
+    pub creation_time: Option<DateTime>,
+    /// meta items, named by their key or, in iTunes style lists, their four character code
+    pub items: Vec<(String, Value)>,
+    /// udta text entries such as ©mak and ©xyz
+    pub user_data: Vec<(FourCC, String)>,
+}
+
+/// return true if the reader appears to contain a QuickTime or MP4 file
+pub fn is_quicktime<R: Read + Seek>(rdr: &mut R) -> bool {
+    let end = match rdr.seek(SeekFrom::End(0)) {
+        Ok(e) => e,
+        Err(_) => return false,
+    };
+    if rdr.seek(SeekFrom::Start(0)).is_err() {
+        return false;
+    }
+    let first = match BoxHeader::read(rdr, end) {
+        Ok(b) => b,
+        Err(_) => return false,
+    };
+    match &first.kind {
+        b"ftyp" => match bmff::read_brands(rdr, &first) {
+            Ok(brands) => BRANDS.contains(&&brands[0]),
+            Err(_) => false,
+        },
+        // older QuickTime files have no ftyp
+        b"moov" | b"mdat" | b"wide" | b"free" | b"skip" | b"pnot" => true,
+        _ => false,
+    }
+}
+
+impl QuickTime {
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let boxes = try!(bmff::read_file(rdr));
+        let moov = try!(bmff::find(&boxes, b"moov")
+                        .ok_or(io::Error::new(io::ErrorKind::InvalidData, "missing moov box")));
+        let mut qt = QuickTime::default();
+
+        for b in try!(moov.children(rdr, 0)) {
+            match &b.kind {
+                b"mvhd" => qt.creation_time = try!(read_mvhd(rdr, &b)),
+                b"udta" => try!(qt.read_udta(rdr, &b)),
+                b"meta" => try!(qt.read_meta(rdr, &b)),
+                _ => {}
+            }
+        }
+        Ok(qt)
+    }
+
+    /// the value of the first meta item with the given key
+    pub fn item(&self, key: &str) -> Option<&Value> {
+        self.items.iter().find(|i| i.0 == key).map(|i| &i.1)
+    }
+
+    /// the first udta text entry of the given kind
+    pub fn user_data(&self, kind: &FourCC) -> Option<&str> {
+        self.user_data.iter().find(|u| &u.0 == kind).map(|u| &u.1[..])
+    }
+
+    /// camera manufacturer
+    pub fn make(&self) -> Option<&str> {
+        self.string(MAKE, b"\xa9mak")
+    }
+
+    /// camera model
+    pub fn model(&self) -> Option<&str> {
+        self.string(MODEL, b"\xa9mod")
+    }
+
+    /// capture time, falling back to the mvhd creation time
+    pub fn date_time_original(&self) -> Option<DateTime> {
+        self.string(CREATION_DATE, b"\xa9day")
+            .and_then(DateTime::from_iso8601)
+            .or(self.creation_time)
+    }
+
+    pub fn gps(&self) -> Option<GpsPosition> {
+        self.string(LOCATION_ISO6709, b"\xa9xyz").and_then(GpsPosition::from_iso6709)
+    }
+
+    /// pairs a Live Photo video with its still image
+    pub fn content_identifier(&self) -> Option<&str> {
+        match self.item(CONTENT_IDENTIFIER) {
+            Some(Value::Utf8(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    // a meta item with the given key, or failing that, a udta entry or
+    // iTunes style item of the given kind
+    fn string(&self, key: &str, kind: &FourCC) -> Option<&str> {
+        let name = fourcc_name(kind);
+        for k in &[key, &name[..]] {
+            if let Some(Value::Utf8(s)) = self.item(k) {
+                return Some(s);
+            }
+        }
+        self.user_data(kind)
+    }
+
+    fn read_udta<R: Read + Seek>(&mut self, rdr: &mut R, udta: &BoxHeader) -> io::Result<()> {
+        for b in try!(udta.children(rdr, 0)) {
+            if &b.kind == b"meta" {
+                try!(self.read_meta(rdr, &b));
+                continue;
+            }
+            // only the © entries are international text
+            if b.kind[0] != 0xa9 {
+                continue;
+            }
+            let d = try!(b.read_data(rdr));
+            if let Some(s) = read_udta_text(&d) {
+                self.user_data.push((b.kind, s));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_meta<R: Read + Seek>(&mut self, rdr: &mut R, meta: &BoxHeader) -> io::Result<()> {
+        // QuickTime meta boxes are plain boxes, MP4 ones are full boxes
+        let mut h = [0u8; 8];
+        try!(rdr.seek(SeekFrom::Start(meta.data_offset())));
+        try!(rdr.read_exact(&mut h));
+        let skip = if &h[4..] == b"hdlr" { 0 } else { 4 };
+
+        let children = try!(meta.children(rdr, skip));
+        let keys = match bmff::find(&children, b"keys") {
+            Some(b) => try!(read_keys(rdr, b)),
+            None => vec![],
+        };
+        let ilst = match bmff::find(&children, b"ilst") {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+
+        for item in try!(ilst.children(rdr, 0)) {
+            // items are named by a 1-based index into keys, or by their own kind
+            let index = BigEndian::read_u32(&item.kind) as usize;
+            let name = if !keys.is_empty() {
+                match keys.get(index.wrapping_sub(1)) {
+                    Some(k) => k.clone(),
+                    None => continue,
+                }
+            } else {
+                fourcc_name(&item.kind)
+            };
+            let data = try!(item.children(rdr, 0));
+            if let Some(b) = bmff::find(&data, b"data") {
+                let d = try!(b.read_data(rdr));
+                if let Some(v) = try!(decode_value(&d)) {
+                    self.items.push((name, v));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_mvhd<R: Read + Seek>(rdr: &mut R, mvhd: &BoxHeader) -> io::Result<Option<DateTime>> {
+    try!(rdr.seek(SeekFrom::Start(mvhd.data_offset())));
+    let (version, _) = try!(bmff::read_full_box(rdr));
+    let t = if version == 1 { try!(rdr.read_u64::<BigEndian>()) } else { try!(rdr.read_u32::<BigEndian>()) as u64 };
+    // zero means unset
+    Ok(if t == 0 { None } else { Some(DateTime::from_mac_epoch(t)) })
+}
+
+// u16 length, u16 language code, then the text
+fn read_udta_text(d: &[u8]) -> Option<String> {
+    if d.len() < 4 {
+        return None;
+    }
+    let len = BigEndian::read_u16(d) as usize;
+    let text = d[4..].get(..len)?;
+    Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string())
+}
+
+fn read_keys<R: Read + Seek>(rdr: &mut R, keys: &BoxHeader) -> io::Result<Vec<String>> {
+    let d = try!(keys.read_data(rdr));
+    let mut c = Cursor::new(&d[..]);
+    try!(bmff::read_full_box(&mut c));
+    let count = try!(c.read_u32::<BigEndian>());
+    let mut v = vec![];
+    for _ in 0..count {
+        let size = try!(c.read_u32::<BigEndian>()) as usize;
+        let mut namespace = [0u8; 4];
+        try!(c.read_exact(&mut namespace));
+        let mut name = vec![0u8; try!(size.checked_sub(8)
+                                      .ok_or(io::Error::new(io::ErrorKind::InvalidData, "invalid key size")))];
+        try!(c.read_exact(&mut name));
+        v.push(String::from_utf8_lossy(&name).into_owned());
+    }
+    Ok(v)
+}
+
+// the payload of a data box: type indicator, locale, then the value
+fn decode_value(d: &[u8]) -> io::Result<Option<Value>> {
+    if d.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short data box"));
+    }
+    let kind = BigEndian::read_u32(d) & 0xffffff;
+    let v = &d[8..];
+    Ok(Some(match (kind, v.len()) {
+        (1, _) => Value::Utf8(String::from_utf8_lossy(v).into_owned()),
+        (21, 1) => Value::Int(v[0] as i8 as i64),
+        (21, 2) => Value::Int(BigEndian::read_i16(v) as i64),
+        (21, 4) => Value::Int(BigEndian::read_i32(v) as i64),
+        (21, 8) => Value::Int(BigEndian::read_i64(v)),
+        (22, 1) => Value::Int(v[0] as i64),
+        (22, 2) => Value::Int(BigEndian::read_u16(v) as i64),
+        (22, 4) => Value::Int(BigEndian::read_u32(v) as i64),
+        (23, 4) => Value::Float(BigEndian::read_f32(v) as f64),
+        (24, 8) => Value::Float(BigEndian::read_f64(v)),
+        (21..=24, _) => return Ok(None),
+        _ => Value::Data(kind, v.to_vec()),
+    }))
+}
+
+// four character codes as latin-1, so that 0xa9 reads as ©
+fn fourcc_name(kind: &FourCC) -> String {
+    kind.iter().map(|&b| b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use bmff::tests::{make_box, make_full_box};
+
+    fn udta_text(kind: &[u8], s: &str) -> Vec<u8> {
+        let mut d = vec![0, s.len() as u8, 0x15, 0xc7];
+        d.extend_from_slice(s.as_bytes());
+        make_box(kind, &d)
+    }
+
+    fn data(kind: u32, v: &[u8]) -> Vec<u8> {
+        let mut d = vec![0, 0, 0, kind as u8, 0, 0, 0, 0];
+        d.extend_from_slice(v);
+        make_box(b"data", &d)
+    }
+
+    fn file(moov: &[u8]) -> Vec<u8> {
+        let mut f = make_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+        f.extend(make_box(b"moov", moov));
+        f.extend(make_box(b"mdat", b""));
+        f
+    }
+
+    fn mvhd(t: u32) -> Vec<u8> {
+        let mut d = vec![(t >> 24) as u8, (t >> 16) as u8, (t >> 8) as u8, t as u8];
+        d.extend_from_slice(&[0; 8]);
+        make_full_box(b"mvhd", 0, 0, &d)
+    }
+
+    #[test]
+    fn keys_and_items() {
+        let names = [LOCATION_ISO6709, MAKE, CREATION_DATE, CONTENT_IDENTIFIER, "com.example.rating"];
+        let mut keys = vec![0, 0, 0, names.len() as u8];
+        for n in &names {
+            keys.extend_from_slice(&[0, 0, 0, 8 + n.len() as u8]);
+            keys.extend_from_slice(b"mdta");
+            keys.extend_from_slice(n.as_bytes());
+        }
+        let values = [data(1, b"+37.3318-122.0312+010.000/"), data(1, b"Apple"),
+                      data(1, b"2019-05-04T10:11:12-0700"), data(1, b"A1B2-C3"), data(22, &[4])];
+        let mut ilst = vec![];
+        for (i, v) in values.iter().enumerate() {
+            ilst.extend(make_box(&[0, 0, 0, i as u8 + 1], v));
+        }
+        let mut meta = make_full_box(b"hdlr", 0, 0, b"\0\0\0\0mdta");
+        meta.extend(make_full_box(b"keys", 0, 0, &keys));
+        meta.extend(make_box(b"ilst", &ilst));
+
+        let mut moov = mvhd(3061152000);
+        moov.extend(make_box(b"meta", &meta));
+        moov.extend(make_box(b"udta", &udta_text(b"\xa9mod", "iPhone X")));
+
+        let mut c = Cursor::new(file(&moov));
+        assert!(is_quicktime(&mut c));
+        let qt = QuickTime::new(&mut c).expect("read quicktime");
+
+        assert_eq!(qt.make(), Some("Apple"));
+        assert_eq!(qt.model(), Some("iPhone X"));
+        assert_eq!(qt.content_identifier(), Some("A1B2-C3"));
+        assert_eq!(qt.item("com.example.rating"), Some(&Value::Int(4)));
+        assert_eq!(qt.date_time_original().expect("date").to_string(), "2019-05-04T10:11:12-07:00");
+        assert_eq!(qt.creation_time.expect("creation time").to_string(), "2001-01-01T00:00:00Z");
+        let p = qt.gps().expect("gps");
+        assert_eq!((p.latitude, p.longitude, p.altitude), (37.3318, -122.0312, Some(10.0)));
+    }
+
+    #[test]
+    fn user_data_fallback() {
+        // iTunes style meta, a full box without keys
+        let mut meta = make_full_box(b"hdlr", 0, 0, b"\0\0\0\0mdirappl");
+        meta.extend(make_box(b"ilst", &make_box(b"\xa9day", &data(1, b"2017-01-02"))));
+        let mut udta = udta_text(b"\xa9xyz", "+3719.908-12201.872/");
+        udta.extend(udta_text(b"\xa9mak", "Samsung"));
+        udta.extend(make_full_box(b"meta", 0, 0, &meta));
+
+        let mut moov = mvhd(0);
+        moov.extend(make_box(b"udta", &udta));
+        let qt = QuickTime::new(&mut Cursor::new(file(&moov))).expect("read quicktime");
+
+        assert_eq!(qt.creation_time, None);
+        assert_eq!(qt.make(), Some("Samsung"));
+        assert_eq!(qt.model(), None);
+        assert_eq!(qt.date_time_original().expect("date").to_string(), "2017-01-02T00:00:00");
+        assert!((qt.gps().expect("gps").latitude - 37.3318).abs() < 1e-5);
+
+        assert!(!is_quicktime(&mut Cursor::new(make_box(b"ftyp", b"heic\0\0\0\0mif1heic"))));
+    }
+}
@@ -0,0 +1,120 @@
+// http://lclevy.free.fr/cr2/
+//
+// CR2 is a TIFF file with a "CR" marker following the header, and four IFDs:
+// a full size jpeg preview with the exif data, a thumbnail, a small RGB
+// image, and the raw image.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::SeekFrom;
+
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+
+use exif::Exif;
+use exif::makernote::Registry;
+use super::{RawMetadata, Format, find_previews};
+
+/// contents of the 16 byte CR2 header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub big_endian: bool,
+    pub major: u8,
+    pub minor: u8,
+    /// offset of the IFD describing the raw image
+    pub raw_ifd_offset: u32,
+}
+
+impl Header {
+    pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let mut h = [0u8; 16];
+        try!(rdr.seek(SeekFrom::Start(0)));
+        try!(rdr.read_exact(&mut h));
+
+        let big_endian = match &h[..4] {
+            b"MM\0*" => true,
+            b"II*\0" => false,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid tiff header")),
+        };
+        if &h[8..10] != b"CR" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing CR2 marker"));
+        }
+
+        Ok(Header{
+            big_endian: big_endian,
+            major: h[10],
+            minor: h[11],
+            raw_ifd_offset: if big_endian { BigEndian::read_u32(&h[12..]) } else { LittleEndian::read_u32(&h[12..]) },
+        })
+    }
+}
+
+/// return true if the reader appears to contain a CR2 file
+pub fn is_cr2<R: Read + Seek>(rdr: &mut R) -> bool {
+    Header::read(rdr).is_ok()
+}
+
+/// read the exif data and Canon MakerNote from a CR2 file
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<RawMetadata> {
+    try!(Header::read(rdr));
+    try!(rdr.seek(SeekFrom::Start(0)));
+    let exif = try!(Exif::new(rdr));
+
+    let maker_note = match Registry::default().decode(&exif) {
+        Some(r) => Some(try!(r)),
+        None => None,
+    };
+
+    let previews = try!(find_previews(rdr, &exif, maker_note.as_ref()));
+
+    Ok(RawMetadata{ format: Format::Cr2, exif: exif, maker_note: maker_note, previews: previews })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        let mut v = vec![b'I', b'I', 0x2A, 0x00, 0x10, 0x00, 0x00, 0x00, b'C', b'R', 0x02, 0x00, 0xEF, 0xBE, 0x00, 0x00];
+        // IFD0 at 0x10: Make, ExifIFD
+        v.extend_from_slice(&[0x02, 0x00]);
+        v.extend_from_slice(&[0x0F, 0x01, 0x02, 0x00, 0x06, 0x00, 0x00, 0x00, 0x2E, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x69, 0x87, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(b"Canon\0");
+        // exif IFD at 0x34: MakerNote at 0x46
+        v.extend_from_slice(&[0x01, 0x00]);
+        v.extend_from_slice(&[0x7C, 0x92, 0x07, 0x00, 0x18, 0x00, 0x00, 0x00, 0x46, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        // Canon MakerNote: IFD with a single UShort array, stored at 0x58 relative to the tiff header
+        v.extend_from_slice(&[0x01, 0x00]);
+        v.extend_from_slice(&[0x01, 0x00, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, 0x58, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x01, 0x00, 0x02, 0x00, 0x03, 0x00]);
+        v
+    }
+
+    #[test]
+    fn read_cr2() {
+        let mut c = Cursor::new(sample());
+        assert!(is_cr2(&mut c));
+
+        let h = Header::read(&mut c).expect("header");
+        assert_eq!((h.major, h.minor, h.raw_ifd_offset), (2, 0, 0xBEEF));
+
+        let m = read(&mut c).expect("read cr2");
+        assert_eq!(m.exif.make(), Some("Canon"));
+        assert_eq!(m.exif.maker_note_offset, Some(0x46));
+
+        let note = m.maker_note.expect("maker note");
+        assert_eq!(note.vendor, "Canon");
+        assert_eq!(note.ifd.entry(0x0001).and_then(|e| e.data.uints()), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn not_cr2() {
+        let mut v = sample();
+        v[8] = b'X';
+        assert!(!is_cr2(&mut Cursor::new(v)));
+    }
+}
@@ -0,0 +1,137 @@
+// https://github.com/lclevy/canon_cr3
+//
+// CR3 is an ISO base media file. moov contains a Canon specific uuid box,
+// which in turn holds CMT1 (IFD0), CMT2 (exif IFD), CMT3 (Canon MakerNote)
+// and CMT4 (GPS IFD), each a complete TIFF structure.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::Cursor;
+
+use bmff;
+use bmff::BoxHeader;
+use exif::Exif;
+use exif::tag;
+use exif::makernote::Decoded;
+use super::{RawMetadata, Format};
+
+const CANON_UUID: [u8; 16] = [0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0,
+                              0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48];
+
+/// return true if the reader appears to contain a CR3 file
+pub fn is_cr3<R: Read + Seek>(rdr: &mut R) -> bool {
+    let boxes = match bmff::read_file(rdr) {
+        Ok(b) => b,
+        Err(_) => return false,
+    };
+    match bmff::find(&boxes, b"ftyp").map(|ftyp| bmff::read_brands(rdr, ftyp)) {
+        Some(Ok(brands)) => brands[0] == *b"crx ",
+        _ => false,
+    }
+}
+
+/// read the exif data and Canon MakerNote from a CR3 file
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<RawMetadata> {
+    if !is_cr3(rdr) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a CR3 file"));
+    }
+
+    let boxes = try!(bmff::read_file(rdr));
+    let moov = try!(bmff::find(&boxes, b"moov").ok_or(missing("moov")));
+    let uuid = try!(try!(moov.children(rdr, 0)).into_iter()
+                    .find(|b| b.usertype == Some(CANON_UUID))
+                    .ok_or(missing("Canon uuid")));
+    let cmts = try!(uuid.children(rdr, 0));
+
+    // CMT1 provides the byte order, the other IFDs are attached to it as children
+    let mut exif = try!(try!(read_cmt(rdr, &cmts, b"CMT1", 0)).ok_or(missing("CMT1")));
+    for &(kind, id) in &[(b"CMT2", tag::EXIF_IFD_POINTER), (b"CMT4", tag::GPS_INFO_IFD_POINTER)] {
+        if let Some(mut e) = try!(read_cmt(rdr, &cmts, kind, id)) {
+            exif.ifds[0].children.push(e.ifds.remove(0));
+        }
+    }
+    // offsets within the MakerNote are relative to CMT3, not CMT1
+    exif.maker_note_offset = None;
+
+    let maker_note = try!(read_cmt(rdr, &cmts, b"CMT3", tag::MAKER_NOTE)).map(|mut e| Decoded{
+        vendor: "Canon".to_string(),
+        ifd: e.ifds.remove(0),
+    });
+
+    // the PRVW and THMB previews are not located yet
+    Ok(RawMetadata{ format: Format::Cr3, exif: exif, maker_note: maker_note, previews: vec![] })
+}
+
+fn missing(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("CR3 file missing {}", what))
+}
+
+// decode the TIFF structure in the given CMT box, if present, as a single IFD with the given id
+fn read_cmt<R: Read + Seek>(rdr: &mut R, cmts: &[BoxHeader], kind: &bmff::FourCC, id: u16) -> io::Result<Option<Exif>> {
+    let b = match bmff::find(cmts, kind) {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let data = try!(b.read_data(rdr));
+    let mut e = try!(Exif::new(&mut Cursor::new(data)));
+    if e.ifds.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "empty CMT box"));
+    }
+    e.ifds.truncate(1);
+    e.ifds[0].id = id;
+    Ok(Some(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use bmff::tests::make_box;
+    use exif::{Ifd, Entry, EntryData};
+    use exif::tag;
+    use raw::tests::{ascii, encode};
+
+    fn tiff(entries: Vec<Entry>) -> Vec<u8> {
+        encode(Ifd{ id: 0, entries: entries, children: vec![] }, vec![], None)
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut cmts = make_box(b"CMT1", &tiff(vec![ascii(tag::MAKE, "Canon"), ascii(tag::MODEL, "Canon EOS R")]));
+        cmts.extend(make_box(b"CMT2", &tiff(vec![ascii(tag::DATE_TIME_ORIGINAL, "2018:09:05 10:00:00")])));
+        cmts.extend(make_box(b"CMT3", &tiff(vec![Entry{ tag: 0x0001, data: EntryData::UShort(vec![1, 2, 3]) }])));
+        cmts.extend(make_box(b"CMT4", &tiff(vec![ascii(tag::gps::LATITUDE_REF, "N")])));
+
+        let mut uuid = CANON_UUID.to_vec();
+        uuid.extend(cmts);
+
+        let mut file = make_box(b"ftyp", b"crx \0\0\0\x01crx isom");
+        file.extend(make_box(b"moov", &make_box(b"uuid", &uuid)));
+        file.extend(make_box(b"mdat", b"image data"));
+        file
+    }
+
+    #[test]
+    fn read_cr3() {
+        let mut c = Cursor::new(sample());
+        assert!(is_cr3(&mut c));
+
+        let m = read(&mut c).expect("read cr3");
+        assert_eq!(m.exif.model(), Some("Canon EOS R"));
+
+        let children = &m.exif.ifds[0].children;
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].id, tag::EXIF_IFD_POINTER);
+        assert_eq!(children[0].ascii(tag::DATE_TIME_ORIGINAL), Some("2018:09:05 10:00:00"));
+        assert_eq!(children[1].ascii(tag::gps::LATITUDE_REF), Some("N"));
+
+        let note = m.maker_note.expect("maker note");
+        assert_eq!(note.ifd.entry(0x0001).and_then(|e| e.data.uints()), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn not_cr3() {
+        let mut c = Cursor::new(make_box(b"ftyp", b"heic\0\0\0\0mif1"));
+        assert!(!is_cr3(&mut c));
+        assert!(read(&mut c).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+// camera raw formats. these are only read far enough to extract metadata,
+// image data is never decoded.
+//
+// most raw formats are TIFF files with vendor quirks: ORF and RW2 use their
+// own magic numbers, NEF, ARW and PEF are only distinguishable by Make, and
+// from plain TIFF by the presence of raw image data.
+// RW2 keeps its exif in the JpgFromRaw preview, ORF locates its preview
+// in the MakerNote.
+
+pub mod cr2;
+pub mod cr3;
+pub mod raf;
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+
+use exif::{Exif, Ifd, Entry, EntryData, TIFF_MAGIC};
+use exif::tag;
+use exif::jpeg;
+use exif::makernote::{Decoded, Registry, decode_ifd};
+
+/// "IIRO" and "MMOR"
+const ORF_MAGIC: u16 = 0x4f52;
+/// "IIRS", used by some older Olympus models
+const ORF_MAGIC_S: u16 = 0x5352;
+const RW2_MAGIC: u16 = 0x0055;
+
+const PHOTOMETRIC_CFA: u64 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u64 = 34892;
+/// Sony ARW, Nikon NEF and Pentax PEF compression schemes
+const RAW_COMPRESSIONS: &[u64] = &[32767, 34713, 65535];
+
+/// recognised raw file formats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Cr2,
+    Cr3,
+    Nef,
+    Arw,
+    Orf,
+    Rw2,
+    Pef,
+    Raf,
+}
+
+/// location of an embedded jpeg preview
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Preview {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl Preview {
+    pub fn read<R: Read + Seek>(&self, rdr: &mut R) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.length as usize];
+        try!(rdr.seek(SeekFrom::Start(self.offset)));
+        try!(rdr.read_exact(&mut buf));
+        Ok(buf)
+    }
+}
+
+/// metadata extracted from a raw file
+#[derive(Clone, Debug)]
+pub struct RawMetadata {
+    pub format: Format,
+    pub exif: Exif,
+    pub maker_note: Option<Decoded>,
+    /// embedded jpeg previews, in the order they were found
+    pub previews: Vec<Preview>,
+}
+
+/// read the metadata of any supported raw file
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<RawMetadata> {
+    if cr3::is_cr3(rdr) {
+        return cr3::read(rdr);
+    }
+    if cr2::is_cr2(rdr) {
+        return cr2::read(rdr);
+    }
+    if raf::is_raf(rdr) {
+        return raf::read(rdr);
+    }
+
+    try!(rdr.seek(SeekFrom::Start(0)));
+    let mut exif = try!(Exif::new_with_magic(rdr, &[TIFF_MAGIC, ORF_MAGIC, ORF_MAGIC_S, RW2_MAGIC]));
+    let format = match try!(magic(rdr)) {
+        ORF_MAGIC | ORF_MAGIC_S => Format::Orf,
+        RW2_MAGIC => Format::Rw2,
+        // a plain TIFF written by a camera maker's software is not raw
+        _ => match format_from_make(exif.make()) {
+            Some(f) if exif.ifds.iter().any(has_raw_data) => f,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognised raw format")),
+        },
+    };
+
+    // the MakerNote is decoded from the exif it was found in, as its offsets may be relative to it
+    let embedded = if format == Format::Rw2 { try!(jpg_from_raw_exif(&exif)) } else { None };
+    let maker_note = match Registry::default().decode(embedded.as_ref().unwrap_or(&exif)) {
+        Some(r) => Some(try!(r)),
+        None => None,
+    };
+    let previews = try!(find_previews(rdr, &exif, maker_note.as_ref()));
+
+    if let Some(mut e) = embedded {
+        if let (Some(ifd0), Some(jpeg_ifd0)) = (exif.ifds.first_mut(), e.ifds.first_mut()) {
+            for c in jpeg_ifd0.children.drain(..) {
+                if !ifd0.children.iter().any(|x| x.id == c.id) {
+                    ifd0.children.push(c);
+                }
+            }
+        }
+    }
+
+    Ok(RawMetadata{ format: format, exif: exif, maker_note: maker_note, previews: previews })
+}
+
+fn format_from_make(make: Option<&str>) -> Option<Format> {
+    let make = make?.to_uppercase();
+    if make.starts_with("NIKON") {
+        Some(Format::Nef)
+    } else if make.starts_with("SONY") {
+        Some(Format::Arw)
+    } else if make.starts_with("PENTAX") || make.starts_with("RICOH") || make.starts_with("ASAHI") {
+        Some(Format::Pef)
+    } else {
+        None
+    }
+}
+
+// RW2 IFD0 holds sensor information only, the exif IFD, GPS IFD and MakerNote
+// are found in the exif of the JpgFromRaw preview
+fn jpg_from_raw_exif(exif: &Exif) -> io::Result<Option<Exif>> {
+    let jpeg = match exif.ifds.first().and_then(|ifd| ifd.entry(tag::panasonic::JPG_FROM_RAW)) {
+        Some(&Entry{ data: EntryData::Undef(ref d), .. }) | Some(&Entry{ data: EntryData::Byte(ref d), .. }) => d,
+        _ => return Ok(None),
+    };
+    match try!(jpeg::extract_exif(&mut Cursor::new(jpeg))) {
+        Some(tiff) => Exif::new(&mut Cursor::new(tiff)).map(Some),
+        None => Ok(None),
+    }
+}
+
+// true if the IFD or any of its children describes CFA or linear raw data,
+// or data in one of the vendor specific raw compressions
+fn has_raw_data(ifd: &Ifd) -> bool {
+    let photometric = first_uint(ifd, tag::PHOTOMETRIC_INTERPRETATION);
+    let compression = first_uint(ifd, tag::COMPRESSION);
+    photometric == Some(PHOTOMETRIC_CFA) || photometric == Some(PHOTOMETRIC_LINEAR_RAW) ||
+        RAW_COMPRESSIONS.iter().any(|&c| compression == Some(c)) ||
+        ifd.children.iter().any(has_raw_data)
+}
+
+// the magic number following the byte order marker
+fn magic<R: Read + Seek>(rdr: &mut R) -> io::Result<u16> {
+    let mut h = [0u8; 4];
+    try!(rdr.seek(SeekFrom::Start(0)));
+    try!(rdr.read_exact(&mut h));
+    Ok(if &h[..2] == b"MM" { BigEndian::read_u16(&h[2..]) } else { LittleEndian::read_u16(&h[2..]) })
+}
+
+/// find the embedded jpeg previews referenced by a raw file's IFDs and MakerNote.
+/// candidates which don't start with a jpeg SOI marker are ignored.
+pub fn find_previews<R: Read + Seek>(rdr: &mut R, exif: &Exif, maker_note: Option<&Decoded>) -> io::Result<Vec<Preview>> {
+    let mut candidates = vec![];
+    for ifd in &exif.ifds {
+        collect_previews(ifd, &mut candidates);
+    }
+
+    if let Some(note) = maker_note {
+        if note.vendor == "Pentax" {
+            let start = first_uint(&note.ifd, tag::pentax::PREVIEW_IMAGE_START);
+            let length = first_uint(&note.ifd, tag::pentax::PREVIEW_IMAGE_LENGTH);
+            if let (Some(o), Some(l)) = (start, length) {
+                candidates.push(Preview{ offset: o, length: l });
+            }
+        }
+        if note.vendor == "Olympus" {
+            if let Some(p) = try!(olympus_preview(exif, &note.ifd)) {
+                candidates.push(p);
+            }
+        }
+    }
+
+    // RW2 stores an entire jpeg inline in IFD0, so its location isn't retained by Exif
+    if exif.ifds.first().and_then(|ifd| ifd.entry(tag::panasonic::JPG_FROM_RAW)).is_some() {
+        if let Some(p) = try!(entry_location(rdr, exif.big_endian, tag::panasonic::JPG_FROM_RAW)) {
+            candidates.push(p);
+        }
+    }
+
+    let mut previews: Vec<Preview> = vec![];
+    for p in candidates {
+        if p.length >= 2 && !previews.contains(&p) && try!(is_jpeg_at(rdr, p.offset)) {
+            previews.push(p);
+        }
+    }
+    Ok(previews)
+}
+
+// ORF previews are described by the CameraSettings IFD of the Olympus MakerNote,
+// with offsets relative to the note
+fn olympus_preview(exif: &Exif, note: &Ifd) -> io::Result<Option<Preview>> {
+    let (data, base) = match (exif.maker_note(), exif.maker_note_offset) {
+        (Some(d), Some(o)) => (d, o),
+        _ => return Ok(None),
+    };
+    let settings = match first_uint(note, tag::olympus::CAMERA_SETTINGS) {
+        Some(o) => o,
+        None => return Ok(None),
+    };
+    let ifd = try!(decode_ifd(data, 0, settings, data.get(8..10) == Some(b"MM")));
+    if first_uint(&ifd, tag::olympus::PREVIEW_IMAGE_VALID) == Some(0) {
+        return Ok(None);
+    }
+    let start = first_uint(&ifd, tag::olympus::PREVIEW_IMAGE_START);
+    let length = first_uint(&ifd, tag::olympus::PREVIEW_IMAGE_LENGTH);
+    Ok(match (start, length) {
+        (Some(o), Some(l)) => Some(Preview{ offset: base + o, length: l }),
+        _ => None,
+    })
+}
+
+fn collect_previews(ifd: &Ifd, out: &mut Vec<Preview>) {
+    let start = first_uint(ifd, tag::JPEG_THUMBNAIL_OFFSET);
+    let length = first_uint(ifd, tag::JPEG_THUMBNAIL_LENGTH);
+    if let (Some(o), Some(l)) = (start, length) {
+        out.push(Preview{ offset: o, length: l });
+    }
+
+    // a single strip of old style jpeg, or lossless jpeg in a reduced resolution image.
+    // lossless jpeg with NewSubFileType 0 is raw data.
+    let compression = first_uint(ifd, tag::COMPRESSION);
+    let reduced = first_uint(ifd, tag::NEW_SUBFILE_TYPE) == Some(1);
+    if compression == Some(6) || (compression == Some(7) && reduced) {
+        let offsets = ifd.entry(tag::STRIP_OFFSETS).and_then(|e| e.data.uints());
+        let counts = ifd.entry(tag::STRIP_BYTE_COUNTS).and_then(|e| e.data.uints());
+        if let (Some(o), Some(c)) = (offsets, counts) {
+            if o.len() == 1 && c.len() == 1 {
+                out.push(Preview{ offset: o[0], length: c[0] });
+            }
+        }
+    }
+
+    for c in &ifd.children {
+        collect_previews(c, out);
+    }
+}
+
+fn first_uint(ifd: &Ifd, t: u16) -> Option<u64> {
+    ifd.entry(t).and_then(|e| e.data.uints()).and_then(|v| v.first().cloned())
+}
+
+fn is_jpeg_at<R: Read + Seek>(rdr: &mut R, offset: u64) -> io::Result<bool> {
+    let mut soi = [0u8; 2];
+    try!(rdr.seek(SeekFrom::Start(offset)));
+    match rdr.read_exact(&mut soi) {
+        Ok(()) => Ok(soi == [0xFF, 0xD8]),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// find the location of the data of an out of line entry in IFD0
+fn entry_location<R: Read + Seek>(rdr: &mut R, big_endian: bool, t: u16) -> io::Result<Option<Preview>> {
+    let read_u16 = |b: &[u8]| if big_endian { BigEndian::read_u16(b) } else { LittleEndian::read_u16(b) };
+    let read_u32 = |b: &[u8]| if big_endian { BigEndian::read_u32(b) } else { LittleEndian::read_u32(b) };
+
+    let mut h = [0u8; 8];
+    try!(rdr.seek(SeekFrom::Start(0)));
+    try!(rdr.read_exact(&mut h));
+    try!(rdr.seek(SeekFrom::Start(read_u32(&h[4..]) as u64)));
+
+    let mut n = [0u8; 2];
+    try!(rdr.read_exact(&mut n));
+    for _ in 0..read_u16(&n) {
+        let mut e = [0u8; 12];
+        try!(rdr.read_exact(&mut e));
+        if read_u16(&e[..2]) != t {
+            continue;
+        }
+        // only single byte formats are meaningful as a byte range
+        let length = read_u32(&e[4..8]) as u64;
+        return Ok(match read_u16(&e[2..4]) {
+            1 | 2 | 6 | 7 if length > 4 => Some(Preview{ offset: read_u32(&e[8..]) as u64, length: length }),
+            _ => None,
+        });
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{WriteBytesExt, BigEndian};
+    use exif::{Exif, Ifd, Entry, EntryData};
+    use exif::tag;
+    use tiff::{Tiff, ImageData};
+
+    const JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xD9];
+
+    pub fn ascii(t: u16, s: &str) -> Entry {
+        Entry{ tag: t, data: EntryData::Ascii(s.to_string()) }
+    }
+
+    /// a jpeg holding nothing but the given exif
+    pub fn jpeg(e: &Exif) -> Vec<u8> {
+        let mut tiff = Cursor::new(vec![]);
+        e.encode(&mut tiff).expect("encode exif");
+        let tiff = tiff.into_inner();
+
+        let mut v = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        v.write_u16::<BigEndian>(2 + 6 + tiff.len() as u16).unwrap();
+        v.extend_from_slice(b"Exif\0\0");
+        v.extend(tiff);
+        v.extend_from_slice(&[0xFF, 0xD9]);
+        v
+    }
+
+    /// a little endian TIFF with the given IFD0 and image data, optionally with another magic number
+    pub fn encode(ifd0: Ifd, data: Vec<ImageData>, magic: Option<[u8; 2]>) -> Vec<u8> {
+        let t = Tiff{
+            exif: Exif::from_ifds(vec![ifd0]),
+            data: data,
+        };
+        let mut buf = Cursor::new(vec![]);
+        t.encode(&mut buf).expect("encode raw");
+        let mut v = buf.into_inner();
+        if let Some(m) = magic {
+            v[2] = m[0];
+            v[3] = m[1];
+        }
+        v
+    }
+
+    #[test]
+    fn nef_sub_ifd_preview() {
+        let sub = Ifd{ id: tag::SUB_IFDS, entries: vec![], children: vec![] };
+        let cfa = Ifd{ id: tag::SUB_IFDS, entries: vec![
+            Entry{ tag: tag::NEW_SUBFILE_TYPE, data: EntryData::ULong(vec![0]) },
+            Entry{ tag: tag::PHOTOMETRIC_INTERPRETATION, data: EntryData::UShort(vec![32803]) },
+        ], children: vec![] };
+        let ifd0 = Ifd{ id: 0, entries: vec![ascii(tag::MAKE, "NIKON CORPORATION")], children: vec![sub, cfa] };
+        let data = vec![ImageData{ path: vec![0, 0], tag: tag::JPEG_THUMBNAIL_OFFSET, chunks: vec![JPEG.to_vec()] }];
+        let mut c = Cursor::new(encode(ifd0, data, None));
+
+        let m = read(&mut c).expect("read nef");
+        assert_eq!(m.format, Format::Nef);
+        assert_eq!(m.previews.len(), 1);
+        assert_eq!(m.previews[0].read(&mut c).expect("preview"), JPEG.to_vec());
+    }
+
+    #[test]
+    fn orf_magic() {
+        let ifd0 = Ifd{ id: 0, entries: vec![ascii(tag::MAKE, "OLYMPUS IMAGING CORP.")], children: vec![] };
+        let mut c = Cursor::new(encode(ifd0, vec![], Some(*b"RO")));
+        assert!(Exif::new(&mut c).is_err());
+
+        let m = read(&mut c).expect("read orf");
+        assert_eq!(m.format, Format::Orf);
+        assert_eq!(m.exif.make(), Some("OLYMPUS IMAGING CORP."));
+    }
+
+    #[test]
+    fn rw2_jpg_from_raw() {
+        let mut jpeg = JPEG.to_vec();
+        jpeg.extend_from_slice(b"padding");
+        let ifd0 = Ifd{ id: 0, entries: vec![
+            ascii(tag::MAKE, "Panasonic"),
+            Entry{ tag: tag::panasonic::JPG_FROM_RAW, data: EntryData::Undef(jpeg.clone()) },
+        ], children: vec![] };
+        let mut c = Cursor::new(encode(ifd0, vec![], Some([0x55, 0x00])));
+
+        let m = read(&mut c).expect("read rw2");
+        assert_eq!(m.format, Format::Rw2);
+        assert_eq!(m.previews.len(), 1);
+        assert_eq!(m.previews[0].read(&mut c).expect("preview"), jpeg);
+    }
+
+    #[test]
+    fn rw2_embedded_exif() {
+        // Panasonic notes use offsets relative to the exif of the preview
+        let mut note = b"Panasonic\0\0\0".to_vec();
+        note.extend_from_slice(&[0x01, 0x00, 0x03, 0x00, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        let exif_ifd = Ifd{ id: tag::EXIF_IFD_POINTER, entries: vec![
+            ascii(tag::DATE_TIME_ORIGINAL, "2019:01:02 03:04:05"),
+            Entry{ tag: tag::MAKER_NOTE, data: EntryData::Undef(note) },
+        ], children: vec![] };
+        let preview = jpeg(&Exif::from_ifds(vec![Ifd{ id: 0, entries: vec![ascii(tag::MAKE, "Panasonic")], children: vec![exif_ifd] }]));
+
+        let ifd0 = Ifd{ id: 0, entries: vec![
+            ascii(tag::MAKE, "Panasonic"),
+            Entry{ tag: tag::panasonic::SENSOR_WIDTH, data: EntryData::UShort(vec![6000]) },
+            Entry{ tag: tag::panasonic::JPG_FROM_RAW, data: EntryData::Undef(preview.clone()) },
+        ], children: vec![] };
+        let mut c = Cursor::new(encode(ifd0, vec![], Some([0x55, 0x00])));
+
+        let m = read(&mut c).expect("read rw2");
+        assert_eq!(m.format, Format::Rw2);
+        assert_eq!(m.exif.ifds[0].entry(tag::panasonic::SENSOR_WIDTH).and_then(|e| e.data.uints()), Some(vec![6000]));
+        let date = m.exif.ifds[0].children.iter().find(|c| c.id == tag::EXIF_IFD_POINTER)
+            .and_then(|c| c.ascii(tag::DATE_TIME_ORIGINAL));
+        assert_eq!(date, Some("2019:01:02 03:04:05"));
+
+        let note = m.maker_note.expect("maker note");
+        assert_eq!(note.vendor, "Panasonic");
+        assert_eq!(note.ifd.entry(0x0003).and_then(|e| e.data.uints()), Some(vec![2]));
+        assert_eq!(m.previews[0].read(&mut c).expect("preview"), preview);
+    }
+
+    #[test]
+    fn orf_maker_note_preview() {
+        // CameraSettings is an IFD typed entry, its preview offset is relative to the note
+        let mut note = b"OLYMPUS\0II\x03\0".to_vec();
+        note.extend_from_slice(&[0x01, 0x00, 0x20, 0x20, 0x0D, 0x00, 0x01, 0x00, 0x00, 0x00, 0x1E, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x03, 0x00]);
+        note.extend_from_slice(&[0x00, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x01, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x02, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        note.extend_from_slice(JPEG);
+
+        let exif_ifd = Ifd{ id: tag::EXIF_IFD_POINTER, entries: vec![
+            Entry{ tag: tag::MAKER_NOTE, data: EntryData::Undef(note) },
+        ], children: vec![] };
+        let ifd0 = Ifd{ id: 0, entries: vec![ascii(tag::MAKE, "OLYMPUS IMAGING CORP.")], children: vec![exif_ifd] };
+        let mut c = Cursor::new(encode(ifd0, vec![], Some(*b"RO")));
+
+        let m = read(&mut c).expect("read orf");
+        assert_eq!(m.maker_note.expect("maker note").vendor, "Olympus");
+        assert_eq!(m.previews.len(), 1);
+        assert_eq!(m.previews[0].read(&mut c).expect("preview"), JPEG.to_vec());
+    }
+
+    #[test]
+    fn unknown_tiff() {
+        let ifd0 = Ifd{ id: 0, entries: vec![ascii(tag::MAKE, "Acme")], children: vec![] };
+        assert!(read(&mut Cursor::new(encode(ifd0, vec![], None))).is_err());
+
+        // e.g. a scan or an export from camera maker software
+        let ifd0 = Ifd{ id: 0, entries: vec![
+            ascii(tag::MAKE, "NIKON CORPORATION"),
+            Entry{ tag: tag::PHOTOMETRIC_INTERPRETATION, data: EntryData::UShort(vec![2]) },
+        ], children: vec![] };
+        assert!(read(&mut Cursor::new(encode(ifd0, vec![], None))).is_err());
+    }
+}
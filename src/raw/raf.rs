@@ -0,0 +1,244 @@
+// https://libopenraw.freedesktop.org/formats/raf/
+// http://fileformats.archiveteam.org/wiki/Fujifilm_RAF
+//
+// RAF is a big endian container holding a complete jpeg preview, which
+// carries the exif data, followed by a header of records describing the
+// CFA image and the image data itself.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+
+use byteorder::{ReadBytesExt, BigEndian, ByteOrder};
+
+use exif::{Exif, tag};
+use exif::jpeg;
+use exif::makernote::Registry;
+use super::{RawMetadata, Format, Preview};
+
+const MAGIC: &[u8] = b"FUJIFILMCCD-RAW";
+const HEADER_LEN: usize = 108;
+
+pub const RAW_IMAGE_FULL_SIZE: u16 = 0x0100;
+pub const RAW_IMAGE_CROP_TOP_LEFT: u16 = 0x0110;
+pub const RAW_IMAGE_CROPPED_SIZE: u16 = 0x0111;
+pub const RAW_IMAGE_ASPECT_RATIO: u16 = 0x0115;
+pub const RAW_IMAGE_SIZE: u16 = 0x0121;
+
+/// the fixed size header at the start of the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// format version, e.g. "0201"
+    pub version: [u8; 4],
+    pub camera: String,
+    pub jpeg: Preview,
+    /// location of the CFA header records
+    pub cfa_header: Preview,
+    /// location of the CFA image data
+    pub cfa: Preview,
+}
+
+/// a single CFA header record
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub tag: u16,
+    pub data: Vec<u8>,
+}
+
+/// dimensions in pixels, stored by RAF as (height, width)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size {
+    pub height: u16,
+    pub width: u16,
+}
+
+/// top level data structure representing a RAF file
+#[derive(Clone, Debug)]
+pub struct Raf {
+    pub header: Header,
+    pub records: Vec<Record>,
+    /// exif data and MakerNote of the embedded jpeg
+    pub metadata: RawMetadata,
+    pub full_size: Option<Size>,
+    /// top left corner of the crop, as (top, left)
+    pub crop_origin: Option<(u16, u16)>,
+    pub cropped_size: Option<Size>,
+    pub aspect_ratio: Option<Size>,
+    pub raw_size: Option<Size>,
+    /// exif orientation of the embedded jpeg, RAF has no orientation of its own
+    pub orientation: Option<u16>,
+}
+
+impl Header {
+    pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let mut h = vec![0u8; HEADER_LEN];
+        try!(rdr.seek(SeekFrom::Start(0)));
+        try!(rdr.read_exact(&mut h));
+        if &h[..MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a RAF file"));
+        }
+
+        let location = |at: usize| Preview{
+            offset: BigEndian::read_u32(&h[at..]) as u64,
+            length: BigEndian::read_u32(&h[at + 4..]) as u64,
+        };
+        let camera = h[28..60].iter().take_while(|&&c| c != 0).map(|&c| c as char).collect();
+
+        Ok(Header{
+            version: [h[16], h[17], h[18], h[19]],
+            camera: camera,
+            jpeg: location(84),
+            cfa_header: location(92),
+            cfa: location(100),
+        })
+    }
+}
+
+/// return true if the reader appears to contain a RAF file
+pub fn is_raf<R: Read + Seek>(rdr: &mut R) -> bool {
+    Header::read(rdr).is_ok()
+}
+
+impl Raf {
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        let header = try!(Header::read(rdr));
+        let records = try!(read_records(rdr, &header.cfa_header));
+
+        try!(rdr.seek(SeekFrom::Start(header.jpeg.offset)));
+        let segment = try!(try!(jpeg::extract_exif(rdr))
+                           .ok_or(io::Error::new(io::ErrorKind::InvalidData, "no exif in RAF jpeg preview")));
+        let exif = try!(Exif::new(&mut Cursor::new(segment)));
+        let maker_note = match Registry::default().decode(&exif) {
+            Some(r) => Some(try!(r)),
+            None => None,
+        };
+
+        let orientation = exif.ifds.first()
+            .and_then(|ifd| ifd.entry(tag::ORIENTATION))
+            .and_then(|e| e.data.uints())
+            .and_then(|v| v.first().map(|&o| o as u16));
+
+        let pair = |t: u16| records.iter().find(|r| r.tag == t && r.data.len() >= 4)
+            .map(|r| (BigEndian::read_u16(&r.data), BigEndian::read_u16(&r.data[2..])));
+        let size = |t: u16| pair(t).map(|(h, w)| Size{ height: h, width: w });
+
+        Ok(Raf{
+            full_size: size(RAW_IMAGE_FULL_SIZE),
+            crop_origin: pair(RAW_IMAGE_CROP_TOP_LEFT),
+            cropped_size: size(RAW_IMAGE_CROPPED_SIZE),
+            aspect_ratio: size(RAW_IMAGE_ASPECT_RATIO),
+            raw_size: size(RAW_IMAGE_SIZE),
+            orientation: orientation,
+            metadata: RawMetadata{
+                format: Format::Raf,
+                exif: exif,
+                maker_note: maker_note,
+                previews: vec![header.jpeg],
+            },
+            header: header,
+            records: records,
+        })
+    }
+}
+
+/// read the exif data and Fujifilm MakerNote from a RAF file
+pub fn read<R: Read + Seek>(rdr: &mut R) -> io::Result<RawMetadata> {
+    Raf::new(rdr).map(|r| r.metadata)
+}
+
+// count, followed by (tag, size, data) records
+fn read_records<R: Read + Seek>(rdr: &mut R, loc: &Preview) -> io::Result<Vec<Record>> {
+    if loc.length < 4 {
+        return Ok(vec![]);
+    }
+    try!(rdr.seek(SeekFrom::Start(loc.offset)));
+    let count = try!(rdr.read_u32::<BigEndian>());
+
+    let end = loc.offset + loc.length;
+    let mut pos = loc.offset + 4;
+    let mut records = vec![];
+    for _ in 0..count {
+        if pos + 4 > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated RAF header record"));
+        }
+        let t = try!(rdr.read_u16::<BigEndian>());
+        let size = try!(rdr.read_u16::<BigEndian>()) as u64;
+        pos += 4 + size;
+        if pos > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "RAF header record overruns header"));
+        }
+        let mut data = vec![0u8; size as usize];
+        try!(rdr.read_exact(&mut data));
+        records.push(Record{ tag: t, data: data });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{WriteBytesExt, BigEndian};
+    use exif::{Exif, Ifd, Entry, EntryData};
+    use exif::tag;
+    use raw::tests;
+
+    fn jpeg() -> Vec<u8> {
+        tests::jpeg(&Exif::from_ifds(vec![Ifd{ id: 0, entries: vec![
+            Entry{ tag: tag::MAKE, data: EntryData::Ascii("FUJIFILM".to_string()) },
+            Entry{ tag: tag::ORIENTATION, data: EntryData::UShort(vec![6]) },
+        ], children: vec![] }]))
+    }
+
+    fn sample() -> Vec<u8> {
+        let jpeg = jpeg();
+        let mut records = vec![];
+        records.write_u32::<BigEndian>(3).unwrap();
+        for &(t, a, b) in &[(RAW_IMAGE_FULL_SIZE, 4000u16, 6000u16), (RAW_IMAGE_CROP_TOP_LEFT, 10, 20), (0x2ff0, 1, 2)] {
+            records.write_u16::<BigEndian>(t).unwrap();
+            records.write_u16::<BigEndian>(4).unwrap();
+            records.write_u16::<BigEndian>(a).unwrap();
+            records.write_u16::<BigEndian>(b).unwrap();
+        }
+
+        let mut v = b"FUJIFILMCCD-RAW 0201FF129502".to_vec();
+        v.extend_from_slice(b"X-T2");
+        v.resize(84, 0);
+        let jpeg_offset = HEADER_LEN as u32;
+        let records_offset = jpeg_offset + jpeg.len() as u32;
+        for &x in &[jpeg_offset, jpeg.len() as u32, records_offset, records.len() as u32, 0, 0] {
+            v.write_u32::<BigEndian>(x).unwrap();
+        }
+        v.resize(HEADER_LEN, 0);
+        v.extend(jpeg);
+        v.extend(records);
+        v
+    }
+
+    #[test]
+    fn read_raf() {
+        let mut c = Cursor::new(sample());
+        assert!(is_raf(&mut c));
+
+        let raf = Raf::new(&mut c).expect("read raf");
+        assert_eq!(&raf.header.version, b"0201");
+        assert_eq!(raf.header.camera, "X-T2");
+        assert_eq!(raf.records.len(), 3);
+        assert_eq!(raf.full_size, Some(Size{ height: 4000, width: 6000 }));
+        assert_eq!(raf.crop_origin, Some((10, 20)));
+        assert_eq!(raf.raw_size, None);
+        assert_eq!(raf.orientation, Some(6));
+
+        assert_eq!(raf.metadata.format, Format::Raf);
+        assert_eq!(raf.metadata.exif.make(), Some("FUJIFILM"));
+        let preview = raf.metadata.previews[0].read(&mut c).expect("preview");
+        assert_eq!(&preview[..4], &[0xFF, 0xD8, 0xFF, 0xE1]);
+    }
+
+    #[test]
+    fn not_raf() {
+        let mut v = sample();
+        v[0] = b'X';
+        assert!(!is_raf(&mut Cursor::new(v)));
+    }
+}
@@ -0,0 +1,294 @@
+// http://partners.adobe.com/public/developer/en/tiff/TIFF6.pdf
+//
+// a TIFF file is an exif document whose IFDs additionally point at image data
+// (strips, tiles or an embedded jpeg). the offsets of that data are only valid
+// relative to the original file, so it is held separately here and relocated
+// when the file is written.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::{Cursor, SeekFrom};
+
+use exif::{Exif, Ifd, Entry, EntryData};
+use exif::tag;
+
+/// tags that point at image data, as (offsets, byte counts) pairs
+pub const DATA_POINTERS: &[(u16, u16)] = &[
+    (tag::STRIP_OFFSETS, tag::STRIP_BYTE_COUNTS),
+    (tag::TILE_OFFSETS, tag::TILE_BYTE_COUNTS),
+    (tag::JPEG_THUMBNAIL_OFFSET, tag::JPEG_THUMBNAIL_LENGTH),
+];
+
+/// return true if the given tag holds offsets to, or lengths of, image data
+pub fn is_data_pointer(t: u16) -> bool {
+    DATA_POINTERS.iter().any(|&(o, c)| t == o || t == c)
+}
+
+/// top level data structure representing a TIFF file
+#[derive(Clone, Debug)]
+pub struct Tiff {
+    /// metadata for all images in the file. entries for DATA_POINTERS
+    /// are rewritten when encoding and should not be modified directly.
+    pub exif: Exif,
+    pub data: Vec<ImageData>,
+}
+
+/// image data referenced by an IFD
+#[derive(Clone, Debug)]
+pub struct ImageData {
+    /// location of the IFD: an index into Exif::ifds, then into successive children
+    pub path: Vec<usize>,
+    /// offsets tag, one of the first members of DATA_POINTERS
+    pub tag: u16,
+    /// strips or tiles, in order
+    pub chunks: Vec<Vec<u8>>,
+}
+
+impl Tiff {
+    /// read a TIFF file, including all image data
+    pub fn new<R: Read + Seek>(rdr: &mut R) -> io::Result<Self> {
+        try!(rdr.seek(SeekFrom::Start(0)));
+        let exif = try!(Exif::new(rdr));
+        let len = try!(rdr.seek(SeekFrom::End(0)));
+
+        let mut data = vec![];
+        for (i, ifd) in exif.ifds.iter().enumerate() {
+            try!(collect_data(rdr, len, ifd, &mut vec![i], &mut data));
+        }
+
+        Ok(Tiff{ exif: exif, data: data })
+    }
+
+    /// write the TIFF file, relocating image data after the metadata
+    pub fn encode<W: Write + Seek>(&self, w: &mut W) -> io::Result<()> {
+        // the layout of the metadata depends only on the number and size of
+        // entries, so encode once with placeholder offsets to find its length
+        let mut exif = self.exif.clone();
+        for d in &self.data {
+            try!(d.set_pointers(&mut exif, &vec![0; d.chunks.len()]));
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        try!(exif.encode(&mut buf));
+
+        let mut pos = align(buf.get_ref().len() as u64);
+        for d in &self.data {
+            let mut offsets = Vec::with_capacity(d.chunks.len());
+            for c in &d.chunks {
+                offsets.push(pos);
+                pos = align(pos + c.len() as u64);
+            }
+            try!(d.set_pointers(&mut exif, &offsets));
+        }
+
+        let mut buf = Cursor::new(vec![]);
+        try!(exif.encode(&mut buf));
+        let meta = buf.into_inner();
+        try!(w.write_all(&meta));
+
+        let mut pos = meta.len() as u64;
+        for d in &self.data {
+            for c in &d.chunks {
+                let to = align(pos);
+                try!(pad_to(w, &mut pos, to));
+                try!(w.write_all(c));
+                pos += c.len() as u64;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ImageData {
+    fn counts_tag(&self) -> u16 {
+        DATA_POINTERS.iter().find(|&&(o, _)| o == self.tag).map(|&(_, c)| c).unwrap_or(0)
+    }
+
+    // replace the offset and byte count entries in the IFD this data belongs to
+    fn set_pointers(&self, exif: &mut Exif, offsets: &[u64]) -> io::Result<()> {
+        let counts_tag = self.counts_tag();
+        let big = exif.big_tiff;
+        let ifd = try!(ifd_at_mut(exif, &self.path)
+                        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "image data refers to missing IFD")));
+
+        let (offsets, counts) = if big {
+            (EntryData::ULong8(offsets.to_vec()),
+             EntryData::ULong8(self.chunks.iter().map(|c| c.len() as u64).collect()))
+        } else {
+            let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "image data beyond 4 GiB requires BigTIFF");
+            let offsets = try!(offsets.iter().map(|&o| if o > u32::MAX as u64 { Err(too_large()) } else { Ok(o as u32) })
+                               .collect::<io::Result<Vec<_>>>());
+            let counts = try!(self.chunks.iter().map(|c| if c.len() as u64 > u32::MAX as u64 { Err(too_large()) } else { Ok(c.len() as u32) })
+                              .collect::<io::Result<Vec<_>>>());
+            (EntryData::ULong(offsets), EntryData::ULong(counts))
+        };
+        set_entry(ifd, self.tag, offsets);
+        set_entry(ifd, counts_tag, counts);
+        Ok(())
+    }
+}
+
+/// find the IFD at the given path within exif
+pub fn ifd_at<'a>(exif: &'a Exif, path: &[usize]) -> Option<&'a Ifd> {
+    let (first, rest) = path.split_first()?;
+    rest.iter().fold(exif.ifds.get(*first), |ifd, &i| ifd.and_then(|ifd| ifd.children.get(i)))
+}
+
+/// find the IFD at the given path within exif, for modification
+pub fn ifd_at_mut<'a>(exif: &'a mut Exif, path: &[usize]) -> Option<&'a mut Ifd> {
+    let (first, rest) = path.split_first()?;
+    rest.iter().fold(exif.ifds.get_mut(*first), |ifd, &i| ifd.and_then(|ifd| ifd.children.get_mut(i)))
+}
+
+fn set_entry(ifd: &mut Ifd, t: u16, data: EntryData) {
+    if let Some(e) = ifd.entries.iter_mut().find(|e| e.tag == t) {
+        e.data = data;
+        return;
+    }
+    ifd.entries.push(Entry{ tag: t, data: data });
+}
+
+// len is the length of the file, which bounds the data any IFD can point at
+fn collect_data<R: Read + Seek>(rdr: &mut R, len: u64, ifd: &Ifd, path: &mut Vec<usize>, out: &mut Vec<ImageData>) -> io::Result<()> {
+    for &(offsets_tag, counts_tag) in DATA_POINTERS {
+        let offsets = ifd.entry(offsets_tag).and_then(|e| e.data.uints());
+        let counts = ifd.entry(counts_tag).and_then(|e| e.data.uints());
+        let (offsets, counts) = match (offsets, counts) {
+            (Some(o), Some(c)) => (o, c),
+            (None, None) => continue,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("incomplete image data pointers: 0x{:x}", offsets_tag))),
+        };
+        if offsets.len() != counts.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "image data offset and byte count mismatch"));
+        }
+
+        let mut chunks = Vec::with_capacity(offsets.len());
+        for (&o, &c) in offsets.iter().zip(&counts) {
+            match o.checked_add(c) {
+                Some(end) if end <= len => (),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("image data out of bounds: 0x{:x}", offsets_tag))),
+            }
+            let mut buf = vec![0u8; c as usize];
+            try!(rdr.seek(SeekFrom::Start(o)));
+            try!(rdr.read_exact(&mut buf));
+            chunks.push(buf);
+        }
+        out.push(ImageData{ path: path.clone(), tag: offsets_tag, chunks: chunks });
+    }
+
+    for (i, c) in ifd.children.iter().enumerate() {
+        path.push(i);
+        try!(collect_data(rdr, len, c, path, out));
+        path.pop();
+    }
+    Ok(())
+}
+
+fn align(pos: u64) -> u64 {
+    (pos + 1) & !1
+}
+
+fn pad_to<W: Write>(w: &mut W, pos: &mut u64, to: u64) -> io::Result<()> {
+    while *pos < to {
+        try!(w.write_all(&[0]));
+        *pos += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use exif::{Exif, Ifd, Entry, EntryData};
+    use exif::tag;
+
+    // two strips of 3 and 5 bytes, offsets stored out of line
+    fn sample() -> Vec<u8> {
+        let mut v = vec![b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00];
+        v.extend_from_slice(&[0x02, 0x00]);
+        v.extend_from_slice(&[0x11, 0x01, 0x04, 0x00, 0x02, 0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x00]);
+        v.extend_from_slice(&[0x17, 0x01, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x00, 0x05, 0x00]);
+        v.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        // 0x26: strip offsets
+        v.extend_from_slice(&[0x40, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00]);
+        v.resize(0x30, 0xAA);
+        v.extend_from_slice(b"world");
+        v.resize(0x40, 0xAA);
+        v.extend_from_slice(b"hey");
+        v
+    }
+
+    #[test]
+    fn read_strips() {
+        let t = Tiff::new(&mut Cursor::new(sample())).expect("read tiff");
+        assert_eq!(t.data.len(), 1);
+        assert_eq!(t.data[0].path, vec![0]);
+        assert_eq!(t.data[0].tag, tag::STRIP_OFFSETS);
+        assert_eq!(t.data[0].chunks, vec![b"hey".to_vec(), b"world".to_vec()]);
+    }
+
+    #[test]
+    fn rewrite_relocates_strips() {
+        let mut t = Tiff::new(&mut Cursor::new(sample())).expect("read tiff");
+        t.exif.ifds[0].entries.push(Entry{
+            tag: tag::IMG_DESCRIPTION,
+            data: EntryData::Ascii("a description long enough to move the strips".to_string()),
+        });
+
+        let mut buf = Cursor::new(vec![]);
+        t.encode(&mut buf).expect("encode tiff");
+        buf.set_position(0);
+
+        let t2 = Tiff::new(&mut buf).expect("reread tiff");
+        assert_eq!(t2.data[0].chunks, t.data[0].chunks);
+        assert_eq!(t2.exif.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("a description long enough to move the strips"));
+    }
+
+    #[test]
+    fn rewrite_big_tiff() {
+        let mut t = Tiff::new(&mut Cursor::new(sample())).expect("read tiff");
+        t.exif.big_tiff = true;
+
+        let mut buf = Cursor::new(vec![]);
+        t.encode(&mut buf).expect("encode tiff");
+        buf.set_position(0);
+
+        let t2 = Tiff::new(&mut buf).expect("reread tiff");
+        assert!(t2.exif.big_tiff);
+        assert_eq!(t2.data[0].chunks, t.data[0].chunks);
+    }
+
+    #[test]
+    fn data_pointer_tags() {
+        assert!(is_data_pointer(tag::TILE_BYTE_COUNTS));
+        assert!(!is_data_pointer(tag::ORIENTATION));
+
+        let exif = Exif::from_ifds(vec![Ifd{ id: 0, entries: vec![], children: vec![] }]);
+        assert!(ifd_at(&exif, &[0]).is_some());
+        assert!(ifd_at(&exif, &[0, 1]).is_none());
+    }
+
+    #[test]
+    fn data_out_of_bounds() {
+        let mut v = sample();
+        // byte count of the second strip
+        v[0x20] = 0xFF;
+        v[0x21] = 0xFF;
+        let e = Tiff::new(&mut Cursor::new(v)).expect_err("strip past end");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn offsets_beyond_4gib() {
+        let t = Tiff::new(&mut Cursor::new(sample())).expect("read tiff");
+        let mut exif = t.exif.clone();
+        let e = t.data[0].set_pointers(&mut exif, &[0x1_0000_0000, 0x40]).expect_err("offset too large");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        exif.big_tiff = true;
+        t.data[0].set_pointers(&mut exif, &[0x1_0000_0000, 0x40]).expect("BigTIFF offsets");
+        assert_eq!(exif.ifds[0].entry(tag::STRIP_OFFSETS).and_then(|e| e.data.uints()), Some(vec![0x1_0000_0000, 0x40]));
+    }
+}
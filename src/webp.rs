@@ -0,0 +1,313 @@
+// https://developers.google.com/speed/webp/docs/riff_container
+//
+// a WebP file is a RIFF container. simple files hold a single VP8 (lossy)
+// or VP8L (lossless) chunk, metadata requires the extended format, where
+// a leading VP8X chunk flags the presence of ICCP, EXIF and "XMP " chunks.
+
+use std::io::prelude::*;
+use std::io;
+use std::io::Cursor;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian, ByteOrder};
+
+use exif::Exif;
+
+pub type FourCC = [u8; 4];
+
+pub const FLAG_ANIMATION: u8 = 0x02;
+pub const FLAG_XMP: u8 = 0x04;
+pub const FLAG_EXIF: u8 = 0x08;
+pub const FLAG_ALPHA: u8 = 0x10;
+pub const FLAG_ICC: u8 = 0x20;
+
+const VP8X_LEN: usize = 10;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// a single RIFF chunk, without padding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub kind: FourCC,
+    pub data: Vec<u8>,
+}
+
+/// top level data structure representing a WebP file
+#[derive(Clone, Debug)]
+pub struct WebP {
+    pub chunks: Vec<Chunk>,
+}
+
+impl WebP {
+    pub fn new<R: Read>(rdr: &mut R) -> io::Result<Self> {
+        let mut h = [0u8; 12];
+        try!(rdr.read_exact(&mut h));
+        if &h[..4] != b"RIFF" || &h[8..] != b"WEBP" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WebP file"));
+        }
+
+        // the RIFF size covers "WEBP" and the chunks
+        let mut remaining = LittleEndian::read_u32(&h[4..]) as u64;
+        if remaining < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid RIFF size"));
+        }
+        remaining -= 4;
+
+        let mut chunks = vec![];
+        while remaining >= 8 {
+            let mut kind = [0u8; 4];
+            try!(rdr.read_exact(&mut kind));
+            let len = try!(rdr.read_u32::<LittleEndian>()) as u64;
+            let padded = len + (len & 1);
+            if 8 + padded > remaining {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk extends beyond RIFF size"));
+            }
+            let mut data = vec![0u8; padded as usize];
+            try!(rdr.read_exact(&mut data));
+            data.truncate(len as usize);
+            chunks.push(Chunk{ kind: kind, data: data });
+            remaining -= 8 + padded;
+        }
+
+        Ok(WebP{ chunks: chunks })
+    }
+
+    /// write the file, recomputing the RIFF size
+    pub fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let size = self.chunks.iter().fold(4, |n, c| n + 8 + c.data.len() + (c.data.len() & 1));
+        try!(w.write_all(b"RIFF"));
+        try!(w.write_u32::<LittleEndian>(size as u32));
+        try!(w.write_all(b"WEBP"));
+        for c in &self.chunks {
+            try!(w.write_all(&c.kind));
+            try!(w.write_u32::<LittleEndian>(c.data.len() as u32));
+            try!(w.write_all(&c.data));
+            if c.data.len() & 1 == 1 {
+                try!(w.write_all(&[0]));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find(&self, kind: &FourCC) -> Option<&Chunk> {
+        self.chunks.iter().find(|c| &c.kind == kind)
+    }
+
+    /// the VP8X flags, if this is an extended file
+    pub fn flags(&self) -> Option<u8> {
+        self.find(b"VP8X").and_then(|c| c.data.first().cloned())
+    }
+
+    /// canvas width and height, from VP8X or the image bitstream
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        if let Some(c) = self.find(b"VP8X") {
+            if c.data.len() < VP8X_LEN {
+                return None;
+            }
+            return Some((read_u24(&c.data[4..]) + 1, read_u24(&c.data[7..]) + 1));
+        }
+        self.bitstream_info().map(|(w, h, _)| (w, h))
+    }
+
+    pub fn exif(&self) -> Option<io::Result<Exif>> {
+        let d = &self.find(b"EXIF")?.data;
+        // some writers include the jpeg APP1 header
+        let d = if d.starts_with(EXIF_HEADER) { &d[EXIF_HEADER.len()..] } else { &d[..] };
+        Some(Exif::new(&mut Cursor::new(d)))
+    }
+
+    pub fn xmp(&self) -> Option<&[u8]> {
+        self.find(b"XMP ").map(|c| &c.data[..])
+    }
+
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.find(b"ICCP").map(|c| &c.data[..])
+    }
+
+    /// store exif in an EXIF chunk, converting to the extended format if necessary
+    pub fn set_exif(&mut self, exif: &Exif) -> io::Result<()> {
+        let mut buf = Cursor::new(vec![]);
+        try!(exif.encode(&mut buf));
+        self.set_chunk(*b"EXIF", buf.into_inner())
+    }
+
+    /// store an XMP packet in an "XMP " chunk, converting to the extended format if necessary
+    pub fn set_xmp(&mut self, xmp: &[u8]) -> io::Result<()> {
+        self.set_chunk(*b"XMP ", xmp.to_vec())
+    }
+
+    pub fn set_icc_profile(&mut self, profile: &[u8]) -> io::Result<()> {
+        self.set_chunk(*b"ICCP", profile.to_vec())
+    }
+
+    pub fn remove_icc_profile(&mut self) {
+        self.chunks.retain(|c| &c.kind != b"ICCP");
+        self.update_flags();
+    }
+
+    pub fn remove_exif(&mut self) {
+        self.chunks.retain(|c| &c.kind != b"EXIF");
+        self.update_flags();
+    }
+
+    pub fn remove_xmp(&mut self) {
+        self.chunks.retain(|c| &c.kind != b"XMP ");
+        self.update_flags();
+    }
+
+    /// replace or add a metadata chunk. ICCP directly follows VP8X, while EXIF
+    /// precedes "XMP " and both follow the image data.
+    pub fn set_chunk(&mut self, kind: FourCC, data: Vec<u8>) -> io::Result<()> {
+        try!(self.to_extended());
+        let c = Chunk{ kind: kind, data: data };
+        match self.chunks.iter().position(|c| c.kind == kind) {
+            Some(i) => self.chunks[i] = c,
+            None => {
+                let pos = if &kind == b"ICCP" {
+                    self.chunks.iter().position(|c| &c.kind == b"VP8X").map_or(0, |i| i + 1)
+                } else if &kind == b"EXIF" {
+                    self.chunks.iter().position(|c| &c.kind == b"XMP ").unwrap_or(self.chunks.len())
+                } else {
+                    self.chunks.len()
+                };
+                self.chunks.insert(pos, c);
+            }
+        }
+        self.update_flags();
+        Ok(())
+    }
+
+    /// convert a simple VP8 or VP8L file to the extended format, adding a VP8X chunk
+    pub fn to_extended(&mut self) -> io::Result<()> {
+        if self.find(b"VP8X").is_some() {
+            return Ok(());
+        }
+        let (width, height, alpha) = try!(self.bitstream_info()
+                                          .ok_or(io::Error::new(io::ErrorKind::InvalidData, "missing or invalid VP8/VP8L chunk")));
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "VP8 image has zero width or height"));
+        }
+
+        let mut data = vec![0u8; VP8X_LEN];
+        data[0] = if alpha { FLAG_ALPHA } else { 0 };
+        write_u24(&mut data[4..], width - 1);
+        write_u24(&mut data[7..], height - 1);
+        self.chunks.insert(0, Chunk{ kind: *b"VP8X", data: data });
+        Ok(())
+    }
+
+    // set the VP8X metadata flags according to the chunks present
+    fn update_flags(&mut self) {
+        let mut set = 0;
+        for &(kind, flag) in &[(b"ICCP", FLAG_ICC), (b"EXIF", FLAG_EXIF), (b"XMP ", FLAG_XMP)] {
+            if self.find(kind).is_some() {
+                set |= flag;
+            }
+        }
+        if let Some(c) = self.chunks.iter_mut().find(|c| &c.kind == b"VP8X") {
+            if let Some(f) = c.data.first_mut() {
+                *f = (*f & !(FLAG_ICC | FLAG_EXIF | FLAG_XMP)) | set;
+            }
+        }
+    }
+
+    // width, height and alpha from the VP8 or VP8L bitstream header
+    fn bitstream_info(&self) -> Option<(u32, u32, bool)> {
+        if let Some(c) = self.find(b"VP8 ") {
+            // 3 byte frame tag, start code, 14 bit dimensions with 2 bit scale
+            let d = &c.data;
+            if d.len() < 10 || d[3..6] != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let w = (LittleEndian::read_u16(&d[6..]) & 0x3FFF) as u32;
+            let h = (LittleEndian::read_u16(&d[8..]) & 0x3FFF) as u32;
+            return Some((w, h, false));
+        }
+        if let Some(c) = self.find(b"VP8L") {
+            // signature, then 14 bit width - 1, 14 bit height - 1, alpha bit, 3 bit version
+            let d = &c.data;
+            if d.len() < 5 || d[0] != 0x2F {
+                return None;
+            }
+            let bits = LittleEndian::read_u32(&d[1..]);
+            return Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, (bits >> 28) & 1 == 1));
+        }
+        None
+    }
+}
+
+fn read_u24(d: &[u8]) -> u32 {
+    d[0] as u32 | (d[1] as u32) << 8 | (d[2] as u32) << 16
+}
+
+fn write_u24(d: &mut [u8], v: u32) {
+    d[0] = v as u8;
+    d[1] = (v >> 8) as u8;
+    d[2] = (v >> 16) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::tag;
+    use exif::tests::{exif_with, roundtrip};
+
+    fn simple(kind: &FourCC, data: Vec<u8>) -> WebP {
+        WebP{ chunks: vec![Chunk{ kind: *kind, data: data }] }
+    }
+
+    // 3x2 lossless image with alpha, truncated after the header
+    fn lossless() -> WebP {
+        let bits: u32 = 2 | 1 << 14 | 1 << 28;
+        let mut d = vec![0x2F];
+        d.write_u32::<LittleEndian>(bits).unwrap();
+        simple(b"VP8L", d)
+    }
+
+    #[test]
+    fn riff_size_and_padding() {
+        let w = lossless();
+        let mut buf = vec![];
+        w.encode(&mut buf).expect("encode webp");
+        // header, chunk header, 5 bytes of data and a pad byte
+        assert_eq!(buf.len(), 12 + 8 + 6);
+        assert_eq!(LittleEndian::read_u32(&buf[4..]), buf.len() as u32 - 8);
+        assert_eq!(roundtrip(&w, WebP::encode, WebP::new).chunks, w.chunks);
+    }
+
+    #[test]
+    fn lossless_to_extended() {
+        let mut w = lossless();
+        assert_eq!(w.dimensions(), Some((3, 2)));
+        assert!(w.flags().is_none());
+
+        w.set_exif(&exif_with(&[(tag::IMG_DESCRIPTION, "webp")])).expect("set exif");
+        w.set_xmp(b"<x:xmpmeta/>").expect("set xmp");
+        let w = roundtrip(&w, WebP::encode, WebP::new);
+        assert_eq!(w.chunks.iter().map(|c| c.kind).collect::<Vec<_>>(), vec![*b"VP8X", *b"VP8L", *b"EXIF", *b"XMP "]);
+        assert_eq!(w.flags(), Some(FLAG_ALPHA | FLAG_EXIF | FLAG_XMP));
+        assert_eq!(w.dimensions(), Some((3, 2)));
+        assert_eq!(w.xmp(), Some(&b"<x:xmpmeta/>"[..]));
+
+        let e = w.exif().expect("exif").expect("decode exif");
+        assert_eq!(e.ifds[0].ascii(tag::IMG_DESCRIPTION), Some("webp"));
+
+        let mut w = w;
+        w.remove_exif();
+        assert_eq!(w.flags(), Some(FLAG_ALPHA | FLAG_XMP));
+        assert!(w.exif().is_none());
+    }
+
+    #[test]
+    fn lossy_dimensions() {
+        let mut w = simple(b"VP8 ", vec![0x50, 0x02, 0x00, 0x9D, 0x01, 0x2A, 0x40, 0x01, 0xF0, 0x00]);
+        assert_eq!(w.dimensions(), Some((320, 240)));
+        w.to_extended().expect("to extended");
+        assert_eq!(w.flags(), Some(0));
+        assert_eq!(w.dimensions(), Some((320, 240)));
+
+        let mut bad = simple(b"VP8 ", vec![0; 4]);
+        assert!(bad.set_exif(&exif_with(&[(tag::IMG_DESCRIPTION, "webp")])).is_err());
+
+        let mut empty = simple(b"VP8 ", vec![0x50, 0x02, 0x00, 0x9D, 0x01, 0x2A, 0x00, 0x00, 0xF0, 0x00]);
+        assert_eq!(empty.to_extended().expect_err("zero width").kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,397 @@
+// https://www.adobe.com/devnet/xmp.html (XMP Specification Part 1)
+//
+// an XMP packet is RDF/XML, usually wrapped in x:xmpmeta and an xpacket
+// processing instruction. properties are read from every rdf:Description
+// into a tree of simple values, structs and arrays, keyed by namespace URI.
+
+pub mod xml;
+pub mod sidecar;
+mod write;
+
+use std::io;
+
+use self::xml::{Element, XML_NS};
+pub use self::write::{PACKET_ID, DEFAULT_PADDING};
+
+pub mod ns {
+    pub const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+    pub const X: &str = "adobe:ns:meta/";
+    pub const DC: &str = "http://purl.org/dc/elements/1.1/";
+    pub const XMP: &str = "http://ns.adobe.com/xap/1.0/";
+    pub const XMP_RIGHTS: &str = "http://ns.adobe.com/xap/1.0/rights/";
+    pub const XMP_MM: &str = "http://ns.adobe.com/xap/1.0/mm/";
+    pub const XMP_NOTE: &str = "http://ns.adobe.com/xmp/note/";
+    pub const EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+    pub const TIFF: &str = "http://ns.adobe.com/tiff/1.0/";
+    pub const PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+    pub const IPTC_CORE: &str = "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/";
+    pub const CRS: &str = "http://ns.adobe.com/camera-raw-settings/1.0/";
+    pub const LR: &str = "http://ns.adobe.com/lightroom/1.0/";
+    pub const GCAMERA: &str = "http://ns.google.com/photos/1.0/camera/";
+    pub const CONTAINER: &str = "http://ns.google.com/photos/1.0/container/";
+    pub const CONTAINER_ITEM: &str = "http://ns.google.com/photos/1.0/container/item/";
+    /// private properties written by this crate
+    pub const IMAGEMETA: &str = "https://github.com/liamstask/imagemeta-rs/ns/1.0/";
+}
+
+/// well known namespaces and their customary prefixes, as (prefix, URI)
+pub const NAMESPACES: &[(&str, &str)] = &[
+    ("rdf", ns::RDF),
+    ("x", ns::X),
+    ("dc", ns::DC),
+    ("xmp", ns::XMP),
+    ("xmpRights", ns::XMP_RIGHTS),
+    ("xmpMM", ns::XMP_MM),
+    ("xmpNote", ns::XMP_NOTE),
+    ("exif", ns::EXIF),
+    ("tiff", ns::TIFF),
+    ("photoshop", ns::PHOTOSHOP),
+    ("Iptc4xmpCore", ns::IPTC_CORE),
+    ("crs", ns::CRS),
+    ("lr", ns::LR),
+    ("GCamera", ns::GCAMERA),
+    ("Container", ns::CONTAINER),
+    ("Item", ns::CONTAINER_ITEM),
+    ("imagemeta", ns::IMAGEMETA),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArrayKind {
+    /// unordered
+    Bag,
+    /// ordered
+    Seq,
+    /// alternatives, such as translations of a title
+    Alt,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    /// a URI, written as rdf:resource
+    Uri(String),
+    Struct(Vec<Property>),
+    /// items are properties named rdf:li
+    Array(ArrayKind, Vec<Property>),
+}
+
+/// a named value, with its xml:lang and any other qualifiers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Property {
+    pub ns: String,
+    pub name: String,
+    pub value: Value,
+    pub lang: Option<String>,
+    pub qualifiers: Vec<Property>,
+}
+
+/// top level data structure representing an XMP packet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Xmp {
+    /// namespace declarations from the packet, as (prefix, URI). these
+    /// take precedence over NAMESPACES when choosing prefixes.
+    pub namespaces: Vec<(String, String)>,
+    /// the rdf:about attribute, usually empty
+    pub about: String,
+    pub properties: Vec<Property>,
+}
+
+impl Property {
+    pub fn new(ns: &str, name: &str, value: Value) -> Self {
+        Property{ ns: ns.to_string(), name: name.to_string(), value: value, lang: None, qualifiers: vec![] }
+    }
+
+    /// an array item
+    pub fn item(value: Value) -> Self {
+        Property::new(ns::RDF, "li", value)
+    }
+}
+
+impl Xmp {
+    /// parse a packet, with or without the xpacket and x:xmpmeta wrappers
+    pub fn parse(d: &[u8]) -> io::Result<Self> {
+        let s = try!(::std::str::from_utf8(d).map_err(|_| invalid("XMP packet is not UTF-8")));
+        let root = try!(xml::parse(s.trim_start_matches('\u{feff}')));
+        let rdf = try!(find_rdf(&root).ok_or(invalid("missing rdf:RDF element")));
+
+        let mut x = Xmp::default();
+        collect_namespaces(&root, &mut x.namespaces);
+        for d in rdf.elements() {
+            if !d.name.is(ns::RDF, "Description") {
+                return Err(invalid("unexpected element in rdf:RDF"));
+            }
+            if let Some(about) = d.attribute(ns::RDF, "about") {
+                x.about = about.to_string();
+            }
+            for p in try!(description(d)) {
+                x.set_property(p);
+            }
+        }
+        Ok(x)
+    }
+
+    /// the prefix to use for a namespace, from the packet or the registry
+    pub fn prefix(&self, uri: &str) -> Option<&str> {
+        self.namespaces.iter().find(|n| n.1 == uri).map(|n| &n.0[..])
+            .or_else(|| NAMESPACES.iter().find(|n| n.1 == uri).map(|n| n.0))
+    }
+
+    /// the namespace bound to a prefix, from the packet or the registry
+    pub fn namespace(&self, prefix: &str) -> Option<&str> {
+        self.namespaces.iter().find(|n| n.0 == prefix).map(|n| &n.1[..])
+            .or_else(|| NAMESPACES.iter().find(|n| n.0 == prefix).map(|n| n.1))
+    }
+
+    pub fn property(&self, ns: &str, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.ns == ns && p.name == name)
+    }
+
+    /// find a value by path, e.g. "creator[1]" or "Flash/exif:Fired". struct
+    /// fields without a prefix are in the namespace of their parent, and
+    /// array indices start at 1.
+    pub fn get(&self, ns: &str, path: &str) -> Option<&Value> {
+        let mut ns = ns;
+        let mut props = &self.properties[..];
+        let mut value = None;
+        for seg in path.split('/') {
+            let (name, index) = match seg.find('[') {
+                Some(i) if seg.ends_with(']') => (&seg[..i], Some(seg[i + 1..seg.len() - 1].parse::<usize>().ok()?)),
+                _ => (seg, None),
+            };
+            let name = match name.find(':') {
+                Some(i) => {
+                    ns = self.namespace(&name[..i])?;
+                    &name[i + 1..]
+                }
+                None => name,
+            };
+            let mut v = &props.iter().find(|p| p.ns == ns && p.name == name)?.value;
+            if let Some(i) = index {
+                v = match *v {
+                    Value::Array(_, ref items) => &items.get(i.checked_sub(1)?)?.value,
+                    _ => return None,
+                };
+            }
+            // only structs have fields to descend into
+            props = match *v {
+                Value::Struct(ref fields) => fields,
+                _ => &[],
+            };
+            value = Some(v);
+        }
+        value
+    }
+
+    /// a simple text property
+    pub fn text(&self, ns: &str, name: &str) -> Option<&str> {
+        match self.property(ns, name)?.value {
+            Value::Text(ref s) | Value::Uri(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// the item of a language alternative for the given language, falling
+    /// back to "x-default" and then the first item
+    pub fn lang_alt(&self, ns: &str, name: &str, lang: &str) -> Option<&str> {
+        let items = match self.property(ns, name)?.value {
+            Value::Array(_, ref items) => items,
+            _ => return None,
+        };
+        let find = |l: &str| items.iter().find(|i| i.lang.as_ref().map(|il| il.eq_ignore_ascii_case(l)) == Some(true));
+        match find(lang).or_else(|| find("x-default")).or_else(|| items.first())?.value {
+            Value::Text(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// add a property, replacing any existing property of the same name
+    pub fn set_property(&mut self, p: Property) {
+        match self.properties.iter().position(|e| e.ns == p.ns && e.name == p.name) {
+            Some(i) => self.properties[i] = p,
+            None => self.properties.push(p),
+        }
+    }
+
+    pub fn set(&mut self, ns: &str, name: &str, value: Value) {
+        self.set_property(Property::new(ns, name, value));
+    }
+
+    pub fn remove(&mut self, ns: &str, name: &str) -> Option<Property> {
+        let i = self.properties.iter().position(|p| p.ns == ns && p.name == name)?;
+        Some(self.properties.remove(i))
+    }
+}
+
+fn find_rdf(e: &Element) -> Option<&Element> {
+    if e.name.is(ns::RDF, "RDF") {
+        return Some(e);
+    }
+    e.elements().into_iter().filter_map(find_rdf).next()
+}
+
+fn collect_namespaces(e: &Element, out: &mut Vec<(String, String)>) {
+    for n in &e.namespaces {
+        if !n.0.is_empty() && !out.iter().any(|o| o.0 == n.0) {
+            out.push(n.clone());
+        }
+    }
+    for c in e.elements() {
+        collect_namespaces(c, out);
+    }
+}
+
+// attributes which are properties rather than RDF syntax
+fn is_property_attribute(a: &xml::Attribute) -> bool {
+    !a.name.ns.is_empty() && a.name.ns != ns::RDF && a.name.ns != XML_NS
+}
+
+// the properties of an rdf:Description, in attribute and element form
+fn description(e: &Element) -> io::Result<Vec<Property>> {
+    let mut props = vec![];
+    for a in e.attributes.iter().filter(|a| is_property_attribute(a)) {
+        props.push(Property::new(&a.name.ns, &a.name.local, Value::Text(a.value.clone())));
+    }
+    // properties outside of any namespace can't be written back, so are dropped
+    for c in e.elements().into_iter().filter(|c| !c.name.ns.is_empty()) {
+        props.push(try!(property_element(c)));
+    }
+    Ok(props)
+}
+
+fn property_element(e: &Element) -> io::Result<Property> {
+    let mut p = Property::new(&e.name.ns, &e.name.local, Value::Text(String::new()));
+    p.lang = e.attribute(XML_NS, "lang").map(|l| l.to_string());
+
+    let children = e.elements();
+    p.value = if let Some(r) = e.attribute(ns::RDF, "resource") {
+        Value::Uri(r.to_string())
+    } else if e.attribute(ns::RDF, "parseType") == Some("Resource") {
+        Value::Struct(try!(description(e)))
+    } else if children.is_empty() {
+        // fields of a struct may also be given as attributes of the property
+        if e.attributes.iter().any(is_property_attribute) {
+            Value::Struct(try!(description(e)))
+        } else {
+            Value::Text(e.text())
+        }
+    } else {
+        let c = children[0];
+        if children.len() > 1 || c.name.ns != ns::RDF {
+            return Err(invalid("unexpected element in property value"));
+        }
+        let kind = match &c.name.local[..] {
+            "Bag" => ArrayKind::Bag,
+            "Seq" => ArrayKind::Seq,
+            "Alt" => ArrayKind::Alt,
+            "Description" => {
+                p.value = Value::Struct(try!(description(c)));
+                if let Some(l) = c.attribute(XML_NS, "lang") {
+                    p.lang = Some(l.to_string());
+                }
+                return Ok(general_qualifiers(p));
+            }
+            _ => return Err(invalid("unexpected RDF element in property value")),
+        };
+        let mut items = vec![];
+        for li in c.elements() {
+            if !li.name.is(ns::RDF, "li") {
+                return Err(invalid("unexpected element in array"));
+            }
+            items.push(try!(property_element(li)));
+        }
+        Value::Array(kind, items)
+    };
+    Ok(general_qualifiers(p))
+}
+
+// a struct with an rdf:value field is a qualified value: the other fields
+// are qualifiers, with xml:lang represented as such
+fn general_qualifiers(mut p: Property) -> Property {
+    let mut fields = match p.value {
+        Value::Struct(ref mut f) if f.iter().any(|f| f.ns == ns::RDF && f.name == "value") => ::std::mem::take(f),
+        _ => return p,
+    };
+    let i = fields.iter().position(|f| f.ns == ns::RDF && f.name == "value").unwrap_or(0);
+    p.value = fields.remove(i).value;
+    for q in fields {
+        if q.ns == XML_NS && q.name == "lang" {
+            if let Value::Text(l) = q.value {
+                p.lang = Some(l);
+            }
+        } else {
+            p.qualifiers.push(q);
+        }
+    }
+    p
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const PACKET: &str = r#"<?xpacket begin="﻿" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="test">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:Iptc4xmpCore="http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/"
+    xmlns:my="http://example.com/ns/"
+    xmp:Rating="4"
+    xmp:CreateDate="2018-09-05T10:11:12">
+   <dc:creator><rdf:Seq><rdf:li>Jane Doe</rdf:li><rdf:li>John Roe</rdf:li></rdf:Seq></dc:creator>
+   <dc:subject><rdf:Bag><rdf:li>sky</rdf:li><rdf:li>sea &amp; sand</rdf:li></rdf:Bag></dc:subject>
+   <dc:title><rdf:Alt>
+    <rdf:li xml:lang="x-default">Beach</rdf:li>
+    <rdf:li xml:lang="fr-FR">Plage</rdf:li>
+   </rdf:Alt></dc:title>
+   <exif:Flash rdf:parseType="Resource"><exif:Fired>True</exif:Fired><exif:Mode>2</exif:Mode></exif:Flash>
+   <Iptc4xmpCore:CreatorContactInfo Iptc4xmpCore:CiEmailWork="jane@example.com"/>
+   <my:Link rdf:resource="http://example.com/"/>
+   <my:Note><rdf:Description><rdf:value>hello</rdf:value><my:source>camera</my:source></rdf:Description></my:Note>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn parse_packet() {
+        let x = Xmp::parse(PACKET.as_bytes()).expect("parse xmp");
+        assert_eq!(x.text(ns::XMP, "Rating"), Some("4"));
+        assert_eq!(x.get(ns::DC, "creator[2]"), Some(&Value::Text("John Roe".to_string())));
+        assert!(x.get(ns::DC, "creator[3]").is_none());
+        assert_eq!(x.get(ns::DC, "subject[2]"), Some(&Value::Text("sea & sand".to_string())));
+        assert_eq!(x.lang_alt(ns::DC, "title", "fr-fr"), Some("Plage"));
+        assert_eq!(x.lang_alt(ns::DC, "title", "de"), Some("Beach"));
+        assert_eq!(x.get(ns::EXIF, "Flash/Mode"), Some(&Value::Text("2".to_string())));
+        assert_eq!(x.get(ns::EXIF, "Flash/exif:Fired"), Some(&Value::Text("True".to_string())));
+        assert_eq!(x.get(ns::IPTC_CORE, "CreatorContactInfo/CiEmailWork"), Some(&Value::Text("jane@example.com".to_string())));
+        // simple values and arrays have no fields
+        assert!(x.get(ns::XMP, "Rating/Rating").is_none());
+        assert!(x.get(ns::DC, "creator/creator").is_none());
+        assert_eq!(x.text("http://example.com/ns/", "Link"), Some("http://example.com/"));
+
+        let note = x.property("http://example.com/ns/", "Note").expect("qualified property");
+        assert_eq!(note.value, Value::Text("hello".to_string()));
+        assert_eq!(note.qualifiers[0].name, "source");
+
+        // prefixes from the packet are retained, and others come from the registry
+        assert_eq!(x.prefix("http://example.com/ns/"), Some("my"));
+        assert_eq!(x.prefix(ns::CRS), Some("crs"));
+        assert_eq!(x.namespace("my"), Some("http://example.com/ns/"));
+    }
+
+    #[test]
+    fn invalid_packets() {
+        assert!(Xmp::parse(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>").is_err());
+        assert!(Xmp::parse(b"\xff\xfe<").is_err());
+        let unexpected = r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:dc="http://purl.org/dc/elements/1.1/">
+            <rdf:Description><dc:title><dc:x/></dc:title></rdf:Description></rdf:RDF>"#;
+        assert!(Xmp::parse(unexpected.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// sidecar files hold a bare x:xmpmeta element, without an xpacket, next to
+// an image which can't or shouldn't be modified, usually a raw file. raw
+// processors keep their develop settings there, which must survive updates.
+
+use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{Xmp, ns};
+
+/// which of two sources wins when both have a property
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precedence {
+    Sidecar,
+    Embedded,
+}
+
+/// the sidecar path used by Lightroom and Capture One, replacing the
+/// extension. darktable instead appends ".xmp" to the full file name.
+pub fn sidecar_path<P: AsRef<Path>>(image: P) -> PathBuf {
+    image.as_ref().with_extension("xmp")
+}
+
+/// merge the XMP of a sidecar with that embedded in its image. Camera Raw
+/// settings come only from the sidecar if it has any, whatever the precedence,
+/// since they describe a single edit and mustn't be mixed.
+pub fn merge(embedded: &Xmp, sidecar: &Xmp, precedence: Precedence) -> Xmp {
+    let (low, high) = match precedence {
+        Precedence::Sidecar => (embedded, sidecar),
+        Precedence::Embedded => (sidecar, embedded),
+    };
+    let mut x = low.clone();
+    for p in &high.properties {
+        x.set_property(p.clone());
+    }
+    if sidecar.properties.iter().any(|p| p.ns == ns::CRS) {
+        x.properties.retain(|p| p.ns != ns::CRS);
+        x.properties.extend(sidecar.properties.iter().filter(|p| p.ns == ns::CRS).cloned());
+    }
+    for n in &high.namespaces {
+        if !x.namespaces.iter().any(|e| e.0 == n.0) {
+            x.namespaces.push(n.clone());
+        }
+    }
+    if !high.about.is_empty() {
+        x.about = high.about.clone();
+    }
+    x
+}
+
+impl Xmp {
+    /// read a sidecar file, with or without an xpacket
+    pub fn read_sidecar<R: Read>(rdr: &mut R) -> io::Result<Self> {
+        let mut d = vec![];
+        try!(rdr.read_to_end(&mut d));
+        Xmp::parse(&d)
+    }
+
+    /// write a sidecar file: an XML declaration and x:xmpmeta, without an xpacket
+    pub fn write_sidecar<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        try!(self.encode_xmpmeta(w));
+        w.write_all(b"\n")
+    }
+
+    /// update a sidecar with new values for the given properties, keeping its
+    /// other properties, including any Camera Raw settings, as they are
+    pub fn update_sidecar<R: Read, W: Write>(&self, rdr: &mut R, w: &mut W) -> io::Result<()> {
+        let sidecar = try!(Xmp::read_sidecar(rdr));
+        merge(self, &sidecar, Precedence::Embedded).write_sidecar(w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use xmp::Value;
+
+    const SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+   xmp:Rating="3"
+   crs:Exposure2012="+0.50"
+   crs:WhiteBalance="Custom"/>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn merge_and_update() {
+        let sidecar = Xmp::read_sidecar(&mut Cursor::new(SIDECAR)).expect("read sidecar");
+        assert_eq!(sidecar.text(ns::CRS, "Exposure2012"), Some("+0.50"));
+
+        let mut embedded = Xmp::default();
+        embedded.set(ns::XMP, "Rating", Value::Text("5".to_string()));
+        embedded.set(ns::DC, "format", Value::Text("image/x-canon-cr2".to_string()));
+        embedded.set(ns::CRS, "Exposure2012", Value::Text("-1.00".to_string()));
+
+        let x = merge(&embedded, &sidecar, Precedence::Sidecar);
+        assert_eq!(x.text(ns::XMP, "Rating"), Some("3"));
+        assert_eq!(x.text(ns::DC, "format"), Some("image/x-canon-cr2"));
+        let x = merge(&embedded, &sidecar, Precedence::Embedded);
+        assert_eq!(x.text(ns::XMP, "Rating"), Some("5"));
+        assert_eq!(x.text(ns::CRS, "Exposure2012"), Some("+0.50"));
+
+        let mut out = vec![];
+        embedded.update_sidecar(&mut Cursor::new(SIDECAR), &mut out).expect("update sidecar");
+        let s = String::from_utf8(out.clone()).expect("utf-8");
+        assert!(s.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<x:xmpmeta"));
+        assert!(!s.contains("xpacket"));
+        let updated = Xmp::read_sidecar(&mut Cursor::new(out)).expect("reread sidecar");
+        assert_eq!(updated.text(ns::XMP, "Rating"), Some("5"));
+        assert_eq!(updated.text(ns::CRS, "Exposure2012"), Some("+0.50"));
+        assert_eq!(updated.text(ns::CRS, "WhiteBalance"), Some("Custom"));
+
+        assert_eq!(sidecar_path("a/IMG_0001.CR2"), PathBuf::from("a/IMG_0001.xmp"));
+    }
+}
@@ -0,0 +1,241 @@
+// serialisation of a property tree as canonical RDF/XML: every property in
+// element form within a single rdf:Description, wrapped in x:xmpmeta and,
+// for embedding in files, an xpacket with whitespace padding.
+
+use std::io::prelude::*;
+use std::io;
+
+use super::{Xmp, Property, Value, ArrayKind, ns};
+use super::xml::{escape, XML_NS};
+
+/// the fixed id of the xpacket processing instruction
+pub const PACKET_ID: &str = "W5M0MpCehiHzreSzNTczkc9d";
+/// padding recommended by the XMP specification, allowing in-place edits
+pub const DEFAULT_PADDING: usize = 2048;
+
+impl Xmp {
+    /// write a complete packet, followed by `padding` bytes of whitespace
+    /// within the xpacket wrapper so that it can later be rewritten in place
+    pub fn encode<W: Write>(&self, w: &mut W, padding: usize) -> io::Result<()> {
+        try!(writeln!(w, "<?xpacket begin=\"\u{feff}\" id=\"{}\"?>", PACKET_ID));
+        try!(self.encode_xmpmeta(w));
+        try!(w.write_all(b"\n"));
+        try!(write_padding(w, padding));
+        w.write_all(b"<?xpacket end=\"w\"?>")
+    }
+
+    /// encode a packet of exactly `len` bytes, padded as needed, so that it
+    /// can overwrite an existing packet without moving any other data
+    pub fn encode_to_len(&self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        try!(self.encode(&mut buf, 0));
+        if buf.len() > len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "XMP packet is larger than the space available"));
+        }
+        let mut padded = vec![];
+        try!(self.encode(&mut padded, len - buf.len()));
+        Ok(padded)
+    }
+
+    /// write the x:xmpmeta element alone, as used for sidecar files
+    pub fn encode_xmpmeta<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let prefixes = self.prefixes();
+        if prefixes.iter().any(|p| p.1.is_empty()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "XMP properties must have a namespace"));
+        }
+
+        try!(writeln!(w, "<x:xmpmeta xmlns:x=\"{}\">", ns::X));
+        try!(writeln!(w, " <rdf:RDF xmlns:rdf=\"{}\">", ns::RDF));
+        try!(write!(w, "  <rdf:Description rdf:about=\"{}\"", escape(&self.about)));
+        for p in &prefixes {
+            if p.1 != ns::RDF && p.1 != ns::X && p.1 != XML_NS {
+                try!(write!(w, "\n    xmlns:{}=\"{}\"", p.0, escape(&p.1)));
+            }
+        }
+        if self.properties.is_empty() {
+            try!(w.write_all(b"/>\n"));
+        } else {
+            try!(w.write_all(b">\n"));
+            for p in &self.properties {
+                try!(write_property(w, p, &prefixes, 3));
+            }
+            try!(w.write_all(b"  </rdf:Description>\n"));
+        }
+        try!(w.write_all(b" </rdf:RDF>\n"));
+        w.write_all(b"</x:xmpmeta>")
+    }
+
+    // a prefix for every namespace in use: the packet's own, then the
+    // registry's, then generated ones which don't clash with either
+    fn prefixes(&self) -> Vec<(String, String)> {
+        let mut uris = vec![];
+        for p in &self.properties {
+            collect_uris(p, &mut uris);
+        }
+
+        let mut prefixes: Vec<(String, String)> = vec![("rdf".to_string(), ns::RDF.to_string()), ("xml".to_string(), XML_NS.to_string())];
+        let mut generated = 0;
+        for uri in uris {
+            if prefixes.iter().any(|p| p.1 == uri) {
+                continue;
+            }
+            let prefix = match self.prefix(&uri) {
+                Some(p) if !prefixes.iter().any(|e| e.0 == p) => p.to_string(),
+                _ => loop {
+                    generated += 1;
+                    let p = format!("ns{}", generated);
+                    if self.namespace(&p).is_none() && !prefixes.iter().any(|e| e.0 == p) {
+                        break p;
+                    }
+                },
+            };
+            prefixes.push((prefix, uri));
+        }
+        prefixes
+    }
+}
+
+fn collect_uris(p: &Property, uris: &mut Vec<String>) {
+    if !uris.contains(&p.ns) {
+        uris.push(p.ns.clone());
+    }
+    for q in &p.qualifiers {
+        collect_uris(q, uris);
+    }
+    match p.value {
+        Value::Struct(ref fields) | Value::Array(_, ref fields) => for f in fields {
+            collect_uris(f, uris);
+        },
+        Value::Text(_) | Value::Uri(_) => {}
+    }
+}
+
+fn write_property<W: Write>(w: &mut W, p: &Property, prefixes: &[(String, String)], depth: usize) -> io::Result<()> {
+    let indent = " ".repeat(depth);
+    let prefix = &prefixes.iter().find(|n| n.1 == p.ns).expect("namespace without a prefix").0;
+    let name = format!("{}:{}", prefix, p.name);
+    try!(write!(w, "{}<{}", indent, name));
+    if let Some(ref l) = p.lang {
+        try!(write!(w, " xml:lang=\"{}\"", escape(l)));
+    }
+
+    // other qualifiers turn the property into a struct with an rdf:value field
+    if !p.qualifiers.is_empty() {
+        try!(w.write_all(b" rdf:parseType=\"Resource\">\n"));
+        let mut v = Property::new(ns::RDF, "value", p.value.clone());
+        v.lang = None;
+        try!(write_property(w, &v, prefixes, depth + 1));
+        for q in &p.qualifiers {
+            try!(write_property(w, q, prefixes, depth + 1));
+        }
+        return writeln!(w, "{}</{}>", indent, name);
+    }
+
+    match p.value {
+        Value::Text(ref s) => writeln!(w, ">{}</{}>", escape(s), name),
+        Value::Uri(ref s) => writeln!(w, " rdf:resource=\"{}\"/>", escape(s)),
+        Value::Struct(ref fields) if fields.is_empty() => w.write_all(b" rdf:parseType=\"Resource\"/>\n"),
+        Value::Struct(ref fields) => {
+            try!(w.write_all(b" rdf:parseType=\"Resource\">\n"));
+            for f in fields {
+                try!(write_property(w, f, prefixes, depth + 1));
+            }
+            writeln!(w, "{}</{}>", indent, name)
+        }
+        Value::Array(kind, ref items) => {
+            let kind = match kind {
+                ArrayKind::Bag => "Bag",
+                ArrayKind::Seq => "Seq",
+                ArrayKind::Alt => "Alt",
+            };
+            try!(write!(w, ">\n{} <rdf:{}", indent, kind));
+            if items.is_empty() {
+                try!(w.write_all(b"/>\n"));
+            } else {
+                try!(w.write_all(b">\n"));
+                for i in items {
+                    try!(write_property(w, i, prefixes, depth + 2));
+                }
+                try!(writeln!(w, "{} </rdf:{}>", indent, kind));
+            }
+            writeln!(w, "{}</{}>", indent, name)
+        }
+    }
+}
+
+// lines of spaces, as recommended by the specification
+fn write_padding<W: Write>(w: &mut W, padding: usize) -> io::Result<()> {
+    let mut left = padding;
+    while left > 0 {
+        let n = if left > 100 { 100 } else { left };
+        try!(w.write_all(&vec![b' '; n - 1]));
+        try!(w.write_all(b"\n"));
+        left -= n;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use xmp::{Xmp, Property, Value, ns};
+    use xmp::tests::PACKET;
+
+    #[test]
+    fn roundtrip() {
+        let x = Xmp::parse(PACKET.as_bytes()).expect("parse xmp");
+        let mut buf = vec![];
+        x.encode(&mut buf, 250).expect("encode xmp");
+
+        let s = String::from_utf8(buf.clone()).expect("utf-8");
+        assert!(s.starts_with("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>"));
+        assert!(s.ends_with(&format!("</x:xmpmeta>\n{}<?xpacket end=\"w\"?>", " ".repeat(99) + "\n" + &" ".repeat(99) + "\n" + &" ".repeat(49) + "\n")));
+        assert!(s.contains("xmlns:my=\"http://example.com/ns/\""));
+        assert!(s.contains("<rdf:li>sea &amp; sand</rdf:li>"));
+
+        let y = Xmp::parse(&buf).expect("reparse xmp");
+        assert_eq!(y.properties, x.properties);
+        assert_eq!(y.prefix("http://example.com/ns/"), Some("my"));
+
+        let same = y.encode_to_len(buf.len()).expect("encode in place");
+        assert_eq!(same.len(), buf.len());
+        assert!(y.encode_to_len(100).is_err());
+    }
+
+    #[test]
+    fn generated_prefixes() {
+        let mut x = Xmp::default();
+        x.namespaces.push(("ns1".to_string(), "urn:taken".to_string()));
+        let mut p = Property::new("urn:new", "a", Value::Text("<\"tab\t\">".to_string()));
+        p.lang = Some("en".to_string());
+        x.set_property(p);
+        x.set(ns::DC, "format", Value::Text("image/jpeg".to_string()));
+
+        let mut buf = vec![];
+        x.encode_xmpmeta(&mut buf).expect("encode xmp");
+        let s = String::from_utf8(buf.clone()).expect("utf-8");
+        assert!(s.contains("xmlns:ns2=\"urn:new\""));
+        assert!(s.contains("xmlns:dc="));
+        assert_eq!(Xmp::parse(&buf).expect("reparse xmp").properties, x.properties);
+    }
+
+    #[test]
+    fn empty_namespace() {
+        let mut x = Xmp::default();
+        x.set(ns::DC, "format", Value::Text("image/jpeg".to_string()));
+        x.set_property(Property::new("", "loose", Value::Text("x".to_string())));
+        let e = x.encode_xmpmeta(&mut vec![]).expect_err("empty namespace");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        // nested within a struct
+        x.remove("", "loose");
+        x.set(ns::XMP, "Thing", Value::Struct(vec![Property::new("", "field", Value::Text("y".to_string()))]));
+        assert!(x.encode_xmpmeta(&mut vec![]).is_err());
+
+        let y = Xmp::parse(br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"><loose>x</loose><dc:format>image/jpeg</dc:format></rdf:Description>
+            </rdf:RDF></x:xmpmeta>"#).expect("parse xmp");
+        assert_eq!(y.properties.len(), 1);
+        y.encode_xmpmeta(&mut vec![]).expect("encode xmp");
+    }
+}
@@ -0,0 +1,364 @@
+// a minimal XML reader, sufficient for XMP packets: elements, attributes,
+// character data, CDATA, comments and processing instructions. DTDs are
+// skipped and only the predefined and numeric entities are recognised.
+// namespaces are resolved as elements are read.
+
+use std::io;
+
+pub const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NS: &str = "http://www.w3.org/2000/xmlns/";
+
+// limits recursion on hostile input. real packets nest a few levels deep.
+const MAX_DEPTH: usize = 256;
+
+/// a namespace qualified name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name {
+    /// namespace URI, empty if the name isn't in a namespace
+    pub ns: String,
+    pub prefix: Option<String>,
+    pub local: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub name: Name,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Element {
+    pub name: Name,
+    /// attributes, excluding namespace declarations
+    pub attributes: Vec<Attribute>,
+    /// namespace declarations made on this element, as (prefix, URI)
+    pub namespaces: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Name {
+    pub fn is(&self, ns: &str, local: &str) -> bool {
+        self.ns == ns && self.local == local
+    }
+}
+
+impl Element {
+    pub fn attribute(&self, ns: &str, local: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.name.is(ns, local)).map(|a| &a.value[..])
+    }
+
+    pub fn elements(&self) -> Vec<&Element> {
+        self.children.iter().filter_map(|n| match *n {
+            Node::Element(ref e) => Some(e),
+            Node::Text(_) => None,
+        }).collect()
+    }
+
+    /// the concatenated character data of this element
+    pub fn text(&self) -> String {
+        let mut s = String::new();
+        for n in &self.children {
+            if let Node::Text(ref t) = *n {
+                s.push_str(t);
+            }
+        }
+        s
+    }
+}
+
+/// parse a document, returning its root element
+pub fn parse(s: &str) -> io::Result<Element> {
+    let mut p = Parser{ s: s, pos: 0, scopes: vec![vec![
+        ("xml".to_string(), XML_NS.to_string()),
+        ("xmlns".to_string(), XMLNS_NS.to_string()),
+    ]] };
+    try!(p.skip_misc());
+    if !p.rest().starts_with('<') {
+        return Err(invalid("missing root element"));
+    }
+    let root = try!(p.element());
+    try!(p.skip_misc());
+    if !p.rest().is_empty() {
+        return Err(invalid("content after root element"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    /// namespace declarations of each open element
+    scopes: Vec<Vec<(String, String)>>,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let r = self.rest();
+        self.pos += r.len() - r.trim_start().len();
+    }
+
+    // skip to just after the next occurrence of `end`
+    fn skip_past(&mut self, end: &str) -> io::Result<&'a str> {
+        let r = self.rest();
+        let i = try!(r.find(end).ok_or(invalid("unterminated markup")));
+        self.pos += i + end.len();
+        Ok(&r[..i])
+    }
+
+    // whitespace, comments, processing instructions and doctype declarations
+    fn skip_misc(&mut self) -> io::Result<()> {
+        loop {
+            self.skip_whitespace();
+            let r = self.rest();
+            if r.starts_with("<?") {
+                try!(self.skip_past("?>"));
+            } else if r.starts_with("<!--") {
+                try!(self.skip_past("-->"));
+            } else if r.starts_with("<!DOCTYPE") {
+                try!(self.skip_doctype());
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_doctype(&mut self) -> io::Result<()> {
+        // the internal subset may contain '>'
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth == 0 => {
+                    self.pos += i + 1;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Err(invalid("unterminated doctype"))
+    }
+
+    fn name(&mut self) -> io::Result<&'a str> {
+        let r = self.rest();
+        let len = r.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/' || c == '<').unwrap_or(r.len());
+        if len == 0 {
+            return Err(invalid("expected a name"));
+        }
+        self.pos += len;
+        Ok(&r[..len])
+    }
+
+    fn expect(&mut self, s: &str) -> io::Result<()> {
+        if !self.rest().starts_with(s) {
+            return Err(invalid(&format!("expected '{}'", s)));
+        }
+        self.pos += s.len();
+        Ok(())
+    }
+
+    fn element(&mut self) -> io::Result<Element> {
+        try!(self.expect("<"));
+        let qname = try!(self.name());
+
+        let mut raw_attrs = vec![];
+        let mut namespaces = vec![];
+        let empty;
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                empty = true;
+                break;
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                empty = false;
+                break;
+            }
+            let name = try!(self.name());
+            self.skip_whitespace();
+            try!(self.expect("="));
+            self.skip_whitespace();
+            let quote = try!(self.rest().chars().next().ok_or(invalid("unexpected end of document")));
+            if quote != '"' && quote != '\'' {
+                return Err(invalid("expected a quoted attribute value"));
+            }
+            self.pos += 1;
+            let value = try!(unescape(try!(self.skip_past(if quote == '"' { "\"" } else { "'" }))));
+
+            if name == "xmlns" {
+                namespaces.push((String::new(), value));
+            } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+                namespaces.push((prefix.to_string(), value));
+            } else {
+                raw_attrs.push((name, value));
+            }
+        }
+
+        self.scopes.push(namespaces.clone());
+        if self.scopes.len() > MAX_DEPTH {
+            return Err(invalid("elements nested too deeply"));
+        }
+        let name = try!(self.resolve(qname, true));
+        let mut attributes = vec![];
+        for (n, v) in raw_attrs {
+            attributes.push(Attribute{ name: try!(self.resolve(n, false)), value: v });
+        }
+
+        let mut children = vec![];
+        if !empty {
+            try!(self.content(&mut children));
+            try!(self.expect("</"));
+            if try!(self.name()) != qname {
+                return Err(invalid("mismatched end tag"));
+            }
+            self.skip_whitespace();
+            try!(self.expect(">"));
+        }
+        self.scopes.pop();
+
+        Ok(Element{ name: name, attributes: attributes, namespaces: namespaces, children: children })
+    }
+
+    // children up to the end tag of the current element
+    fn content(&mut self, children: &mut Vec<Node>) -> io::Result<()> {
+        loop {
+            let r = self.rest();
+            if r.is_empty() {
+                return Err(invalid("unexpected end of document"));
+            } else if r.starts_with("</") {
+                return Ok(());
+            } else if r.starts_with("<![CDATA[") {
+                self.pos += 9;
+                let t = try!(self.skip_past("]]>"));
+                push_text(children, t.to_string());
+            } else if r.starts_with("<!--") {
+                try!(self.skip_past("-->"));
+            } else if r.starts_with("<?") {
+                try!(self.skip_past("?>"));
+            } else if r.starts_with('<') {
+                children.push(Node::Element(try!(self.element())));
+            } else {
+                let len = r.find('<').unwrap_or(r.len());
+                self.pos += len;
+                push_text(children, try!(unescape(&r[..len])));
+            }
+        }
+    }
+
+    // unprefixed attributes have no namespace, unprefixed elements take the default
+    fn resolve(&self, qname: &str, element: bool) -> io::Result<Name> {
+        let (prefix, local) = match qname.find(':') {
+            Some(i) => (Some(&qname[..i]), &qname[i + 1..]),
+            None => (None, qname),
+        };
+        let ns = match prefix {
+            None if !element => String::new(),
+            p => {
+                let p = p.unwrap_or("");
+                match self.scopes.iter().rev().flat_map(|s| s.iter()).find(|d| d.0 == p) {
+                    Some(d) => d.1.clone(),
+                    None if p.is_empty() => String::new(),
+                    None => return Err(invalid(&format!("undeclared namespace prefix '{}'", p))),
+                }
+            }
+        };
+        Ok(Name{ ns: ns, prefix: prefix.map(|p| p.to_string()), local: local.to_string() })
+    }
+}
+
+// adjacent text and CDATA sections form a single node
+fn push_text(children: &mut Vec<Node>, t: String) {
+    if let Some(&mut Node::Text(ref mut s)) = children.last_mut() {
+        s.push_str(&t);
+        return;
+    }
+    children.push(Node::Text(t));
+}
+
+fn unescape(s: &str) -> io::Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let end = try!(rest.find(';').ok_or(invalid("unterminated entity reference")));
+        let c = match &rest[..end] {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            e if e.starts_with("#x") => try!(char_ref(&e[2..], 16)),
+            e if e.starts_with('#') => try!(char_ref(&e[1..], 10)),
+            _ => return Err(invalid("unknown entity reference")),
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn char_ref(s: &str, radix: u32) -> io::Result<char> {
+    u32::from_str_radix(s, radix).ok().and_then(::std::char::from_u32).ok_or(invalid("invalid character reference"))
+}
+
+/// escape text for use in character data or a double quoted attribute value
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            // preserve characters which would otherwise be normalised away
+            '\t' | '\n' | '\r' => out.push_str(&format!("&#x{:X};", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_and_entities() {
+        let doc = r#"<?xml version="1.0"?><!DOCTYPE x [<!ENTITY a "b">]>
+            <a:root xmlns:a="urn:a" xmlns="urn:default" a:x='1 &lt; 2' y="&#x41;&#66;">
+              <child>text &amp; <![CDATA[<raw>]]></child><!-- comment --><a:empty/>
+            </a:root>"#;
+        let root = parse(doc).expect("parse");
+        assert!(root.name.is("urn:a", "root"));
+        assert_eq!(root.namespaces, vec![("a".to_string(), "urn:a".to_string()), (String::new(), "urn:default".to_string())]);
+        assert_eq!(root.attribute("urn:a", "x"), Some("1 < 2"));
+        assert_eq!(root.attribute("", "y"), Some("AB"));
+
+        let children = root.elements();
+        assert!(children[0].name.is("urn:default", "child"));
+        assert_eq!(children[0].text(), "text & <raw>");
+        assert!(children[1].name.is("urn:a", "empty"));
+
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<p:a/>").is_err());
+        assert_eq!(escape("a\"<\n"), "a&quot;&lt;&#xA;");
+    }
+}